@@ -1,22 +1,42 @@
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
 use jsonschema::JSONSchema;
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+/// What a channel needs on Kubernetes
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ChannelKubernetes {
+    /// Ports on which steps reading from this channel listen, each reading
+    /// step gets a Service exposing them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports:     Vec<u16>,
+    /// Extra objects (a message broker, ...) that are included as they are
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub manifests: Vec<Value>,
+}
+
 #[derive(Serialize, Debug)]
 pub struct Channel {
     pub id:              String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version:         Option<Version>,
     #[serde(rename = "requiredFields")]
     pub required_fields: Vec<String>,
     pub start:           Option<String>,
     pub docker:          Option<String>,
     pub stop:            Option<String>,
     pub options:         Vec<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kubernetes:      Option<ChannelKubernetes>,
     #[serde(skip_serializing)]
     pub schema:          JSONSchema,
     #[serde(skip_serializing)]
     pub location:        Option<PathBuf>,
+    /// File the channel was parsed from
+    #[serde(skip_serializing)]
+    pub file:            Option<PathBuf>,
 }
 
 impl<'de> Deserialize<'de> for Channel {
@@ -27,20 +47,24 @@ impl<'de> Deserialize<'de> for Channel {
         #[derive(Deserialize)]
         struct Ch {
             id:              String,
+            version:         Option<Version>,
             start:           Option<String>,
             pub docker:      Option<String>,
             stop:            Option<String>,
             #[serde(rename = "requiredFields")]
             required_fields: Vec<String>,
             options:         Option<Vec<Value>>,
+            kubernetes:      Option<ChannelKubernetes>,
         }
         let Ch {
             required_fields,
             id,
+            version,
             start,
             docker,
             stop,
             options,
+            kubernetes,
         } = <Ch as Deserialize>::deserialize(deserializer)?;
 
         let schema = json!({
@@ -61,17 +85,60 @@ impl<'de> Deserialize<'de> for Channel {
 
         Ok(Channel {
             id,
+            version,
             start,
             stop,
             location: None,
+            file: None,
             docker,
             options,
+            kubernetes,
             schema,
             required_fields,
         })
     }
 }
 
+/// Built-in channels that are a file in the run directory, with the extension
+/// of the paths generated for them
+pub const PIPE_CHANNELS: [(&str, &str); 2] =
+    [("fifo", "fifo"), ("unix-socket", "sock")];
+
+/// Directory of a run with the `fifo` and `unix-socket` channels that have a
+/// relative path
+pub const PIPES_DIR: &str = "channels";
+
+/// Longest path a unix socket can be bound to, `sun_path` minus its nul byte
+pub const MAX_SOCKET_PATH: usize = 107;
+
+/// The configuration of a `fifo` or `unix-socket` channel for the link of the
+/// field `link` (`<step>.<arg>.<field>`), a path in the run directory
+pub fn pipe_option(ty: &str, link: &str) -> Option<Value> {
+    let (_, extension) = PIPE_CHANNELS.iter().find(|(id, _)| *id == ty)?;
+    Some(json!({ "path": format!("{}.{}", link, extension) }))
+}
+
+/// Channels nautirust knows without a definition file, their options are
+/// generated for every link
+pub fn builtin_channels() -> Vec<Channel> {
+    PIPE_CHANNELS
+        .iter()
+        .map(|(id, _)| {
+            let channel = json!({ "id": id, "requiredFields": ["path"] });
+            serde_json::from_value(channel).expect("valid channel")
+        })
+        .collect()
+}
+
+/// Adds the built-in channels, definitions with the same id in `channels`
+/// take precedence
+pub fn add_builtin_channels(channels: &mut Vec<Channel>) {
+    for channel in builtin_channels() {
+        if !channels.iter().any(|c| c.id == channel.id) {
+            channels.push(channel);
+        }
+    }
+}
 
 #[cfg(feature = "io")]
 pub use io::*;
@@ -87,12 +154,12 @@ mod io {
 
     pub async fn parse_channels(path: &str) -> Vec<Channel> {
         let mut channels = Vec::new();
-        let mut iterator = glob(path)
+        let iterator = glob(path)
             .expect("Failed to read channels glob pattern")
             .flatten()
             .map(parse_channel);
 
-        while let Some(item) = iterator.next() {
+        for item in iterator {
             match item.await {
                 Ok(channel) => channels.push(channel),
                 Err(e) => eprintln!("Parsing channel failed '{}'", e),
@@ -110,11 +177,66 @@ mod io {
         let file = read_to_string(&path).await?;
         let mut channel: Channel = serde_json::from_str(&file)?;
         channel.location = path.parent().map(|x| x.into());
+        channel.file = Some(path.canonicalize().unwrap_or(path));
         Ok(channel)
     }
+
+    /// Creates the named pipe `path`, unless it exists
+    pub fn mkfifo(path: &Path) -> std::io::Result<()> {
+        use std::ffi::CString;
+        use std::io::ErrorKind;
+        use std::os::unix::ffi::OsStrExt;
+
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidInput, e))?;
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o644) } == 0 {
+            return Ok(());
+        }
+
+        match std::io::Error::last_os_error() {
+            e if e.kind() == ErrorKind::AlreadyExists => Ok(()),
+            e => Err(e),
+        }
+    }
+
+    /// Creates the named pipe of a `fifo` channel at `path`, the socket of a
+    /// `unix-socket` channel is created by its reader, only its directory is
+    /// created
+    pub fn create_pipe(
+        config: &ChannelConfig,
+        path: &Path,
+    ) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        match config.ty() {
+            "fifo" => mkfifo(path),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes the pipe or socket at `path`, when it exists, anything else
+    /// at `path` is left alone
+    pub fn remove_pipe(path: &Path) -> std::io::Result<()> {
+        use std::os::unix::fs::FileTypeExt;
+
+        let ty = match std::fs::symlink_metadata(path) {
+            Ok(metadata) => metadata.file_type(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if !ty.is_fifo() && !ty.is_socket() {
+            return Ok(());
+        }
+
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ChannelConfig {
     #[serde(rename = "type")]
     ty:            String,
@@ -130,4 +252,125 @@ impl ChannelConfig {
             config,
         }
     }
+
+    /// Id of the channel type
+    pub fn ty(&self) -> &str {
+        &self.ty
+    }
+
+    pub fn serialization(&self) -> &str {
+        &self.serialization
+    }
+
+    pub fn config(&self) -> &Value {
+        &self.config
+    }
+
+    /// Whether this is a built-in `fifo` or `unix-socket` channel
+    pub fn is_pipe(&self) -> bool {
+        PIPE_CHANNELS.iter().any(|(id, _)| *id == self.ty)
+    }
+
+    /// The path of a `fifo` or `unix-socket` channel, a relative one is in the
+    /// run directory at `run_file("channels/<path>")` and can't leave it.
+    /// `None` for other channels and channels without a path.
+    pub fn pipe_path(
+        &self,
+        run_file: &dyn Fn(&str) -> String,
+    ) -> Result<Option<PathBuf>, String> {
+        let path = match self.config.get("path").and_then(Value::as_str) {
+            Some(path) if self.is_pipe() => Path::new(path),
+            _ => return Ok(None),
+        };
+
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else if path.components().any(|c| c == Component::ParentDir) {
+            return Err(format!(
+                "The path {} of a {} channel leaves the run directory",
+                path.display(),
+                self.ty
+            ));
+        } else {
+            let name = Path::new(PIPES_DIR).join(path);
+            PathBuf::from(run_file(&name.display().to_string()))
+        };
+
+        let length = path.as_os_str().len();
+        if self.ty == "unix-socket" && length > MAX_SOCKET_PATH {
+            return Err(format!(
+                "The socket path {} is {} bytes, unix sockets allow at most {}",
+                path.display(),
+                length,
+                MAX_SOCKET_PATH
+            ));
+        }
+
+        Ok(Some(path))
+    }
+
+    /// This configuration with the path of a `fifo` or `unix-socket` channel
+    /// resolved by `pipe_path`
+    pub fn resolve_pipe(&self, run_file: &dyn Fn(&str) -> String) -> Self {
+        let mut config = self.clone();
+        if let Ok(Some(path)) = self.pipe_path(run_file) {
+            config.config["path"] = Value::String(path.display().to_string());
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipe(ty: &str, path: &str) -> ChannelConfig {
+        ChannelConfig::new(ty.into(), "text".into(), json!({ "path": path }))
+    }
+
+    fn run_file(name: &str) -> String {
+        format!("/run/{}", name)
+    }
+
+    #[test]
+    fn pipe_paths_are_in_the_run_directory() {
+        assert_eq!(
+            pipe("fifo", "a/b.fifo").pipe_path(&run_file),
+            Ok(Some(PathBuf::from("/run/channels/a/b.fifo")))
+        );
+        assert_eq!(
+            pipe("unix-socket", "/tmp/a.sock").pipe_path(&run_file),
+            Ok(Some(PathBuf::from("/tmp/a.sock")))
+        );
+        assert_eq!(pipe("file", "a.json").pipe_path(&run_file), Ok(None));
+        assert!(pipe("fifo", "../a.fifo").pipe_path(&run_file).is_err());
+        assert!(pipe("fifo", "a/../../b").pipe_path(&run_file).is_err());
+    }
+
+    #[test]
+    fn socket_paths_fit_in_sun_path() {
+        let name = "s".repeat(MAX_SOCKET_PATH - "/run/channels/".len());
+        assert!(pipe("unix-socket", &name).pipe_path(&run_file).is_ok());
+        let name = format!("{}s", name);
+        let error = pipe("unix-socket", &name).pipe_path(&run_file);
+        assert!(error.unwrap_err().contains("at most 107"));
+        // Only sockets are limited
+        assert!(pipe("fifo", &name).pipe_path(&run_file).is_ok());
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn remove_pipe_leaves_other_files() {
+        let dir = tempdir::TempDir::new("pipes").unwrap();
+        let file = dir.path().join("file");
+        let fifo = dir.path().join("fifo");
+        std::fs::write(&file, "data").unwrap();
+        mkfifo(&fifo).unwrap();
+
+        remove_pipe(&file).unwrap();
+        remove_pipe(&fifo).unwrap();
+        remove_pipe(&dir.path().join("missing")).unwrap();
+        assert!(file.is_file());
+        assert!(!fifo.exists());
+    }
 }
@@ -0,0 +1,50 @@
+use std::path::Path;
+
+use async_std::fs;
+use clap::Subcommand;
+
+use crate::channel::Channel;
+use crate::runner::Runner;
+
+/// Default location of the sub-step output cache
+pub const DEFAULT_CACHE_DIR: &str = ".nautirust/cache";
+
+/// Manage the cache of sub-step outputs
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Directory containing the cached outputs
+    #[clap(long, default_value = DEFAULT_CACHE_DIR)]
+    cache_dir: String,
+
+    #[clap(subcommand)]
+    action: Action,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// Remove all cached outputs
+    Clean,
+}
+
+impl Command {
+    pub(crate) async fn execute(
+        self,
+        _channels: Vec<Channel>,
+        _runners: Vec<Runner>,
+    ) {
+        match self.action {
+            Action::Clean => {
+                if !Path::new(&self.cache_dir).exists() {
+                    return;
+                }
+
+                match fs::remove_dir_all(&self.cache_dir).await {
+                    Ok(_) => println!("Removed {}", self.cache_dir),
+                    Err(e) => {
+                        eprintln!("Failed to remove {}: {}", self.cache_dir, e)
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_yaml::Value;
+
+/// The subset of the compose file format nautirust understands, unknown keys
+/// are kept as they are
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Compose {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub services: BTreeMap<String, Service>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub networks: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub volumes:  BTreeMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Service {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image:       Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build:       Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command:     Option<Value>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_environment",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub environment: BTreeMap<String, String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_depends_on",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub depends_on:  BTreeMap<String, Dependency>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes:     Vec<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports:       Vec<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub networks:    Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<Healthcheck>,
+    #[serde(flatten)]
+    pub other:       BTreeMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub condition: String,
+}
+
+impl Dependency {
+    fn started() -> Self {
+        Self {
+            condition: String::from("service_started"),
+        }
+    }
+
+    fn healthy() -> Self {
+        Self {
+            condition: String::from("service_healthy"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Healthcheck {
+    pub test:         Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval:     Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout:      Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries:      Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_period: Option<String>,
+}
+
+/// `environment` is either a map or a list of `KEY=VALUE` strings
+fn deserialize_environment<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Env {
+        List(Vec<String>),
+        Map(BTreeMap<String, Value>),
+    }
+
+    let out = match Env::deserialize(deserializer)? {
+        Env::List(list) => {
+            list.into_iter()
+                .map(|item| {
+                    match item.split_once('=') {
+                        Some((k, v)) => (k.to_string(), v.to_string()),
+                        None => (item, String::new()),
+                    }
+                })
+                .collect()
+        }
+        Env::Map(map) => {
+            map.into_iter()
+                .map(|(k, v)| {
+                    let v = match v {
+                        Value::String(s) => s,
+                        Value::Null => String::new(),
+                        v => {
+                            serde_yaml::to_string(&v)
+                                .unwrap_or_default()
+                                .trim()
+                                .to_string()
+                        }
+                    };
+                    (k, v)
+                })
+                .collect()
+        }
+    };
+
+    Ok(out)
+}
+
+/// `depends_on` is either a list of service names or a map with conditions
+fn deserialize_depends_on<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<String, Dependency>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Deps {
+        List(Vec<String>),
+        Map(BTreeMap<String, Dependency>),
+    }
+
+    let out = match Deps::deserialize(deserializer)? {
+        Deps::List(list) => {
+            list.into_iter()
+                .map(|s| (s, Dependency::started()))
+                .collect()
+        }
+        Deps::Map(map) => map,
+    };
+
+    Ok(out)
+}
+
+/// What a runner or channel `docker` script printed
+pub enum Fragment {
+    Structured(Compose),
+    Raw(String),
+}
+
+impl Fragment {
+    /// Parses the output of a `docker` script, either a compose document with
+    /// a `services` key or a map of services (JSON or YAML), anything else is
+    /// kept as raw text
+    pub fn parse(output: &str) -> Self {
+        if output.trim().is_empty() {
+            return Fragment::Structured(Compose::default());
+        }
+
+        let value: Value = match serde_yaml::from_str(output) {
+            Ok(value @ Value::Mapping(_)) => value,
+            _ => return Fragment::Raw(output.to_string()),
+        };
+
+        let is_document = ["services", "networks", "volumes"]
+            .iter()
+            .any(|key| value.get(key).is_some());
+
+        let compose = if is_document {
+            serde_yaml::from_value::<Compose>(value)
+        } else {
+            serde_yaml::from_value::<BTreeMap<String, Service>>(value).map(
+                |services| {
+                    Compose {
+                        services,
+                        ..Compose::default()
+                    }
+                },
+            )
+        };
+
+        match compose {
+            Ok(compose) => Fragment::Structured(compose),
+            Err(_) => Fragment::Raw(output.to_string()),
+        }
+    }
+}
+
+/// A compose file built from fragments, remembering which runner, channel or
+/// step contributed each service
+#[derive(Default)]
+pub struct ComposeBuilder {
+    compose: Compose,
+    owners:  BTreeMap<String, String>,
+    raw:     Vec<String>,
+    errors:  Vec<String>,
+}
+
+impl ComposeBuilder {
+    /// Merges a fragment, returning the names of the services it defines
+    pub fn add(&mut self, owner: &str, fragment: Fragment) -> Vec<String> {
+        let fragment = match fragment {
+            Fragment::Structured(compose) => compose,
+            Fragment::Raw(text) => {
+                self.raw.push(text);
+                return Vec::new();
+            }
+        };
+
+        let mut names = Vec::new();
+        for (name, service) in fragment.services {
+            if let Some(other) = self.owners.get(&name) {
+                self.errors.push(format!(
+                    "Service '{}' is defined by both {} and {}",
+                    name, other, owner
+                ));
+                continue;
+            }
+
+            self.owners.insert(name.clone(), owner.to_string());
+            self.compose.services.insert(name.clone(), service);
+            names.push(name);
+        }
+
+        merge_named(
+            &mut self.compose.networks,
+            fragment.networks,
+            "Network",
+            owner,
+            &mut self.errors,
+        );
+        merge_named(
+            &mut self.compose.volumes,
+            fragment.volumes,
+            "Volume",
+            owner,
+            &mut self.errors,
+        );
+
+        names
+    }
+
+    /// Makes every service in `from` depend on every service in `on`
+    pub fn depend(&mut self, from: &[String], on: &[String]) {
+        for dependent in from {
+            for dependency in on {
+                let cyclic = self
+                    .compose
+                    .services
+                    .get(dependency)
+                    .map(|s| s.depends_on.contains_key(dependent))
+                    .unwrap_or_default();
+
+                if dependent == dependency || cyclic {
+                    continue;
+                }
+
+                let healthy = self
+                    .compose
+                    .services
+                    .get(dependency)
+                    .map(|s| s.healthcheck.is_some())
+                    .unwrap_or_default();
+
+                if let Some(service) = self.compose.services.get_mut(dependent)
+                {
+                    service
+                        .depends_on
+                        .entry(dependency.clone())
+                        .or_insert_with(|| {
+                            if healthy {
+                                Dependency::healthy()
+                            } else {
+                                Dependency::started()
+                            }
+                        });
+                }
+            }
+        }
+    }
+
+    /// Serialises the compose file, failing on name collisions
+    pub fn build(self) -> Result<String, Vec<String>> {
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        if self.raw.is_empty() {
+            return serde_yaml::to_string(&self.compose)
+                .map_err(|e| vec![e.to_string()]);
+        }
+
+        // Raw fragments can only be appended to the services section
+        let Compose {
+            services,
+            networks,
+            volumes,
+        } = self.compose;
+
+        let mut out = String::from("services:\n");
+        if !services.is_empty() {
+            let services = serde_yaml::to_string(&services)
+                .map_err(|e| vec![e.to_string()])?;
+            out.push_str(&indent(&services));
+        }
+        self.raw.iter().for_each(|raw| out.push_str(&indent(raw)));
+
+        let rest = Compose {
+            networks,
+            volumes,
+            ..Compose::default()
+        };
+        if rest != Compose::default() {
+            out.push_str(
+                &serde_yaml::to_string(&rest)
+                    .map_err(|e| vec![e.to_string()])?,
+            );
+        }
+
+        Ok(out)
+    }
+}
+
+/// Indents the lines of `text` by two spaces, after removing the indentation
+/// they have in common, every line ends with a newline
+fn indent(text: &str) -> String {
+    let common = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches(' ').len())
+        .min()
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    for line in text.lines() {
+        if !line.trim().is_empty() {
+            out.push_str("  ");
+            out.push_str(&line[common..]);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn merge_named<D: Display>(
+    into: &mut BTreeMap<String, Value>,
+    from: BTreeMap<String, Value>,
+    kind: D,
+    owner: &str,
+    errors: &mut Vec<String>,
+) {
+    for (name, value) in from {
+        match into.get(&name) {
+            Some(existing) if existing != &value => {
+                errors.push(format!(
+                    "{} '{}' of {} conflicts with an earlier definition",
+                    kind, name, owner
+                ));
+            }
+            Some(_) => {}
+            None => {
+                into.insert(name, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn structured(output: &str) -> Compose {
+        match Fragment::parse(output) {
+            Fragment::Structured(compose) => compose,
+            Fragment::Raw(raw) => panic!("parsed as raw: {}", raw),
+        }
+    }
+
+    fn parsed(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn parses_documents_and_service_maps() {
+        let compose = structured(
+            "services:\n  kafka:\n    image: bitnami/kafka\nvolumes:\n  \
+             data: {}\n",
+        );
+        assert_eq!(
+            compose.services["kafka"].image.as_deref(),
+            Some("bitnami/kafka")
+        );
+        assert!(compose.volumes.contains_key("data"));
+
+        let compose = structured(
+            r#"{"step": {"build": ".", "environment": ["A=1", "B"],
+                "depends_on": ["kafka"]}}"#,
+        );
+        let step = &compose.services["step"];
+        assert_eq!(step.environment["A"], "1");
+        assert_eq!(step.environment["B"], "");
+        assert_eq!(step.depends_on["kafka"], Dependency::started());
+
+        assert_eq!(structured("  \n"), Compose::default());
+        assert!(matches!(Fragment::parse("- a list"), Fragment::Raw(_)));
+        assert!(matches!(
+            Fragment::parse("step:\n  ports: 8080\n"),
+            Fragment::Raw(_)
+        ));
+    }
+
+    #[test]
+    fn merges_fragments_and_dependencies() {
+        let mut builder = ComposeBuilder::default();
+        let kafka = builder.add(
+            "channel kafka",
+            Fragment::parse(
+                "kafka:\n  image: bitnami/kafka\n  healthcheck:\n    test: \
+                 true\n",
+            ),
+        );
+        let steps = builder.add(
+            "step a",
+            Fragment::parse(
+                "services:\n  a:\n    build: .\nnetworks:\n  net: {}\n",
+            ),
+        );
+        assert_eq!(kafka, ["kafka"]);
+        assert_eq!(steps, ["a"]);
+
+        builder.depend(&steps, &kafka);
+        // Cycles and self dependencies are skipped
+        builder.depend(&kafka, &steps);
+        builder.depend(&steps, &steps);
+
+        let out = parsed(&builder.build().unwrap());
+        assert_eq!(
+            out["services"]["a"]["depends_on"],
+            parsed("kafka: { condition: service_healthy }")
+        );
+        assert!(out["services"]["kafka"].get("depends_on").is_none());
+        assert!(out["networks"].get("net").is_some());
+    }
+
+    #[test]
+    fn service_name_collisions_fail() {
+        let mut builder = ComposeBuilder::default();
+        builder.add("channel a", Fragment::parse("db:\n  image: x\n"));
+        assert!(builder
+            .add("channel b", Fragment::parse("db:\n  image: y\n"))
+            .is_empty());
+        builder.add("step c", Fragment::parse("volumes:\n  data: {}\n"));
+        builder.add("step d", Fragment::parse("volumes:\n  data: {}\n"));
+        builder.add(
+            "step e",
+            Fragment::parse("volumes:\n  data:\n    driver: local\n"),
+        );
+
+        assert_eq!(
+            builder.build().unwrap_err(),
+            [
+                "Service 'db' is defined by both channel a and channel b",
+                "Volume 'data' of step e conflicts with an earlier definition",
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_fragments_are_indented_services() {
+        let mut builder = ComposeBuilder::default();
+        builder.add(
+            "channel kafka",
+            Fragment::parse("kafka:\n  image: bitnami/kafka\n"),
+        );
+        // Without a trailing newline, and indented like the old output
+        builder.add(
+            "step a",
+            Fragment::Raw(String::from("  a:\n    image: a:latest")),
+        );
+        builder.add("step b", Fragment::Raw(String::from("b:\n  build: .")));
+        builder.add("step c", Fragment::parse("volumes:\n  data: {}\n"));
+
+        let out = parsed(&builder.build().unwrap());
+        assert_eq!(
+            out,
+            parsed(
+                "services:\n  kafka: { image: bitnami/kafka }\n  a: { image: \
+                 'a:latest' }\n  b: { build: . }\nvolumes:\n  data: {}\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,579 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{stdout, Stdout};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen,
+    LeaveAlternateScreen,
+};
+use tui::backend::CrosstermBackend;
+use tui::layout::{Constraint, Direction, Layout};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{
+    Block, Borders, Cell, List, ListItem, Paragraph, Row, Table, TableState,
+};
+use tui::Terminal;
+
+use crate::control::Message;
+use crate::exec::{Event, ProcessKind};
+use crate::plan::Steps;
+use crate::step::StepArgument;
+
+/// Lines of log kept per step
+const LOG_LINES: usize = 5000;
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    Waiting,
+    Running,
+    Ready,
+    Exited(Option<i32>),
+    /// Stopped with a reason, like a timeout or on request
+    Stopped(String),
+    Restarting,
+    Failed,
+}
+
+struct StepRow {
+    id:        String,
+    kind:      ProcessKind,
+    state:     State,
+    pid:       Option<u32>,
+    started:   Option<Instant>,
+    restarts:  u32,
+    progress:  Option<f64>,
+    last_line: String,
+    log:       VecDeque<String>,
+}
+
+impl StepRow {
+    fn new(id: String, kind: ProcessKind) -> Self {
+        Self {
+            id,
+            kind,
+            state: State::Waiting,
+            pid: None,
+            started: None,
+            restarts: 0,
+            progress: None,
+            last_line: String::new(),
+            log: VecDeque::new(),
+        }
+    }
+}
+
+/// A channel of the plan with the fields writing to and reading from it
+struct ChannelRow {
+    ty:            String,
+    serialization: String,
+    config:        String,
+    writers:       Vec<String>,
+    readers:       Vec<String>,
+}
+
+/// Asked by the user in the dashboard
+pub(crate) enum Action {
+    Restart(String),
+    Stop(String),
+    Quit,
+}
+
+/// `run --tui`: a table of the steps, the channels and the log of the
+/// selected step
+pub(crate) struct Dashboard {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    steps:    Vec<StepRow>,
+    channels: Vec<ChannelRow>,
+    selected: usize,
+    /// Lines scrolled up from the end of the log
+    scroll:   usize,
+    /// Lines matching this are highlighted, `n` and `N` jump between them
+    search:   Option<String>,
+    /// The search being typed after `/`
+    typing:   Option<String>,
+    status:   String,
+    /// The last error of the pipeline itself, kept in the status when done
+    error:    Option<String>,
+    finished: bool,
+}
+
+/// The channels of the plan, keyed by their type and configuration
+fn channel_rows(plan: &Steps) -> Vec<ChannelRow> {
+    let mut channels: BTreeMap<(String, String), ChannelRow> = BTreeMap::new();
+
+    for run in &plan.steps {
+        let id = &run.processor_config.id;
+        for (arg, value) in &run.args {
+            let (fields, writer) = match value {
+                StepArgument::StreamWriter { fields } => (fields, true),
+                StepArgument::StreamReader { fields } => (fields, false),
+                _ => continue,
+            };
+
+            for (field, config) in fields {
+                let key =
+                    (config.ty().to_string(), config.config().to_string());
+                let row = channels.entry(key).or_insert_with(|| {
+                    ChannelRow {
+                        ty:            config.ty().to_string(),
+                        serialization: config.serialization().to_string(),
+                        config:        config.config().to_string(),
+                        writers:       Vec::new(),
+                        readers:       Vec::new(),
+                    }
+                });
+                let target = format!("{}.{}.{}", id, arg, field);
+                if writer {
+                    row.writers.push(target);
+                } else {
+                    row.readers.push(target);
+                }
+            }
+        }
+    }
+
+    channels.into_values().collect()
+}
+
+fn format_uptime(since: Duration) -> String {
+    let secs = since.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, (secs / 60) % 60),
+    }
+}
+
+impl Dashboard {
+    /// Takes over the terminal, `steps` are the steps and sub-steps in the
+    /// order they are started
+    pub fn start(
+        plan: &Steps,
+        steps: Vec<(String, ProcessKind)>,
+    ) -> std::io::Result<Self> {
+        enable_raw_mode()?;
+        let mut out = stdout();
+        execute!(out, EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(out))?;
+        terminal.hide_cursor()?;
+
+        let steps = steps
+            .into_iter()
+            .map(|(id, kind)| StepRow::new(id, kind))
+            .collect();
+
+        Ok(Self {
+            terminal,
+            steps,
+            channels: channel_rows(plan),
+            selected: 0,
+            scroll: 0,
+            search: None,
+            typing: None,
+            status: String::new(),
+            error: None,
+            finished: false,
+        })
+    }
+
+    /// Shows the channels of a new version of the plan
+    pub fn reload(&mut self, plan: &Steps) {
+        self.channels = channel_rows(plan);
+    }
+
+    /// Shows `status` below the log until the next key press
+    pub fn set_status(&mut self, status: String) {
+        self.status = status;
+    }
+
+    fn row(&mut self, step: &str) -> Option<&mut StepRow> {
+        self.steps.iter_mut().find(|row| row.id == step)
+    }
+
+    pub fn update(&mut self, event: &Event) {
+        match event {
+            Event::Started {
+                step, kind, pid, ..
+            } => {
+                // A step added to the plan while it runs
+                if self.row(step).is_none() {
+                    self.steps.push(StepRow::new(step.clone(), *kind));
+                }
+                if let Some(row) = self.row(step) {
+                    row.state = State::Running;
+                    row.pid = Some(*pid);
+                    row.started = Some(Instant::now());
+                }
+            }
+            Event::Log { step, line, .. } => {
+                if let Some(row) = self.row(step) {
+                    row.last_line = line.clone();
+                    row.log.push_back(line.clone());
+                    if row.log.len() > LOG_LINES {
+                        row.log.pop_front();
+                    }
+                }
+            }
+            Event::Cached { step } => {
+                if let Some(row) = self.row(step) {
+                    row.state = State::Exited(Some(0));
+                    row.last_line = String::from("using cached output");
+                }
+            }
+            Event::Exited(exit) => {
+                if let Some(row) = self.row(&exit.step) {
+                    row.state = match &exit.reason {
+                        Some(reason) => State::Stopped(reason.clone()),
+                        None => State::Exited(exit.exit_code),
+                    };
+                    row.started = None;
+                }
+            }
+            Event::Restarted { step, restarts } => {
+                if let Some(row) = self.row(step) {
+                    row.state = State::Restarting;
+                    row.restarts = *restarts;
+                }
+            }
+            Event::Control { step, message } => {
+                if let Some(row) = self.row(step) {
+                    match message {
+                        Message::Ready => row.state = State::Ready,
+                        Message::Progress { percent, .. } => {
+                            row.progress = Some(*percent)
+                        }
+                        Message::Done { .. } => row.progress = Some(100.0),
+                        Message::Warning { message } => {
+                            row.log.push_back(format!("warning: {}", message))
+                        }
+                        Message::Metric { .. } => {}
+                    }
+                }
+            }
+            Event::Error { step, message } => {
+                if let Some(row) = self.row(step) {
+                    row.state = State::Failed;
+                    row.last_line = message.clone();
+                    row.log.push_back(message.clone());
+                }
+            }
+            Event::PipelineError { message } => {
+                self.status = format!("Error: {}", message);
+                self.error = Some(message.clone());
+            }
+            Event::Finished => {}
+        }
+    }
+
+    /// Every process exited, the dashboard stays until it is closed
+    pub fn finish(&mut self) {
+        self.finished = true;
+        self.status = match &self.error {
+            Some(error) => {
+                format!("Finished with error: {}, press q to close", error)
+            }
+            None => String::from("Finished, press q to close"),
+        };
+    }
+
+    fn selected_id(&self) -> Option<String> {
+        self.steps.get(self.selected).map(|row| row.id.clone())
+    }
+
+    /// Scrolls the log to the next line matching the search, older ones
+    /// first when `older` is set
+    fn jump(&mut self, older: bool) {
+        let query = match &self.search {
+            Some(query) => query.to_lowercase(),
+            None => return,
+        };
+        let log = match self.steps.get(self.selected) {
+            Some(row) => &row.log,
+            None => return,
+        };
+
+        // The line at the bottom of the pane is `len - 1 - scroll`
+        let len = log.len();
+        let current = len.saturating_sub(self.scroll.saturating_add(1));
+        let matches = |i: &usize| log[*i].to_lowercase().contains(&query);
+        let found = if older {
+            (0..current).rev().find(matches)
+        } else {
+            (current + 1..len).find(matches)
+        };
+
+        match found {
+            Some(i) => self.scroll = len - 1 - i,
+            None => self.status = format!("No more matches for '{}'", query),
+        }
+    }
+
+    fn key(&mut self, key: KeyEvent) -> Option<Action> {
+        if let Some(typing) = &mut self.typing {
+            match key.code {
+                KeyCode::Enter => {
+                    let query = self.typing.take().unwrap_or_default();
+                    self.search = (!query.is_empty()).then_some(query);
+                    self.jump(true);
+                }
+                KeyCode::Esc => self.typing = None,
+                KeyCode::Backspace => {
+                    typing.pop();
+                }
+                KeyCode::Char(c) => typing.push(c),
+                _ => {}
+            }
+            return None;
+        }
+
+        self.status.clear();
+        match key.code {
+            KeyCode::Char('c') if key.modifiers == KeyModifiers::CONTROL => {
+                return Some(Action::Quit);
+            }
+            KeyCode::Char('q') => return Some(Action::Quit),
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.selected = self.selected.saturating_sub(1);
+                self.scroll = 0;
+            }
+            KeyCode::Down | KeyCode::Char('j')
+                if self.selected + 1 < self.steps.len() =>
+            {
+                self.selected += 1;
+                self.scroll = 0;
+            }
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_add(10),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Home => self.scroll = usize::MAX,
+            KeyCode::End => self.scroll = 0,
+            KeyCode::Char('/') => self.typing = Some(String::new()),
+            KeyCode::Esc => self.search = None,
+            KeyCode::Char('n') => self.jump(true),
+            KeyCode::Char('N') => self.jump(false),
+            KeyCode::Char('r') if !self.finished => {
+                let id = self.selected_id()?;
+                self.status = format!("Restarting {}", id);
+                return Some(Action::Restart(id));
+            }
+            KeyCode::Char('s') if !self.finished => {
+                let id = self.selected_id()?;
+                self.status = format!("Stopping {}", id);
+                return Some(Action::Stop(id));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// The keys pressed since the last call, without waiting for any
+    pub fn input(&mut self) -> Vec<Action> {
+        let mut actions = Vec::new();
+        while let Ok(true) = event::poll(Duration::ZERO) {
+            match event::read() {
+                Ok(event::Event::Key(key))
+                    if key.kind == KeyEventKind::Press =>
+                {
+                    actions.extend(self.key(key));
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        actions
+    }
+
+    pub fn draw(&mut self) -> std::io::Result<()> {
+        let Self {
+            terminal,
+            steps,
+            channels,
+            selected,
+            scroll,
+            search,
+            typing,
+            status,
+            ..
+        } = self;
+
+        terminal.draw(|f| {
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(steps.len() as u16 + 3),
+                    Constraint::Min(5),
+                    Constraint::Length(1),
+                ])
+                .split(f.size());
+            let top = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([
+                    Constraint::Percentage(70),
+                    Constraint::Percentage(30),
+                ])
+                .split(rows[0]);
+
+            let table = Table::new(steps.iter().map(|row| {
+                let (state, color) = match &row.state {
+                    State::Waiting => {
+                        (String::from("waiting"), Color::DarkGray)
+                    }
+                    State::Running => (String::from("running"), Color::Green),
+                    State::Ready => (String::from("ready"), Color::Green),
+                    State::Exited(Some(0)) => {
+                        (String::from("exited 0"), Color::Blue)
+                    }
+                    State::Exited(code) => {
+                        let code = code
+                            .map(|c| c.to_string())
+                            .unwrap_or_else(|| String::from("?"));
+                        (format!("exited {}", code), Color::Red)
+                    }
+                    State::Stopped(reason) => (reason.clone(), Color::Yellow),
+                    State::Restarting => {
+                        (String::from("restarting"), Color::Yellow)
+                    }
+                    State::Failed => (String::from("failed"), Color::Red),
+                };
+                let state = match row.progress {
+                    Some(progress) if row.state != State::Waiting => {
+                        format!("{} {:.0}%", state, progress)
+                    }
+                    _ => state,
+                };
+
+                Row::new(vec![
+                    Cell::from(row.id.clone()),
+                    Cell::from(match row.kind {
+                        ProcessKind::Step => "step",
+                        ProcessKind::SubStep => "sub-step",
+                    }),
+                    Cell::from(state).style(Style::default().fg(color)),
+                    Cell::from(
+                        row.pid.map(|p| p.to_string()).unwrap_or_default(),
+                    ),
+                    Cell::from(
+                        row.started
+                            .map(|s| format_uptime(s.elapsed()))
+                            .unwrap_or_default(),
+                    ),
+                    Cell::from(row.restarts.to_string()),
+                    Cell::from(row.last_line.clone()),
+                ])
+            }))
+            .header(
+                Row::new(vec![
+                    "Step",
+                    "Kind",
+                    "State",
+                    "Pid",
+                    "Uptime",
+                    "Restarts",
+                    "Last line",
+                ])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(Block::default().borders(Borders::ALL).title("Steps"))
+            .widths(&[
+                Constraint::Length(20),
+                Constraint::Length(8),
+                Constraint::Length(16),
+                Constraint::Length(8),
+                Constraint::Length(8),
+                Constraint::Length(8),
+                Constraint::Min(10),
+            ])
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+            let mut state = TableState::default();
+            state.select(Some(*selected));
+            f.render_stateful_widget(table, top[0], &mut state);
+
+            let items = channels
+                .iter()
+                .map(|c| {
+                    ListItem::new(vec![
+                        Spans::from(Span::styled(
+                            format!(
+                                "{} ({}) {}",
+                                c.ty, c.serialization, c.config
+                            ),
+                            Style::default().add_modifier(Modifier::BOLD),
+                        )),
+                        Spans::from(format!(
+                            "  {} -> {}",
+                            c.writers.join(", "),
+                            c.readers.join(", ")
+                        )),
+                    ])
+                })
+                .collect::<Vec<_>>();
+            let list = List::new(items).block(
+                Block::default().borders(Borders::ALL).title("Channels"),
+            );
+            f.render_widget(list, top[1]);
+
+            let log_area = rows[1];
+            let height = log_area.height.saturating_sub(2) as usize;
+            let (title, lines) = match steps.get(*selected) {
+                Some(row) => {
+                    let len = row.log.len();
+                    *scroll = (*scroll).min(len.saturating_sub(height));
+                    let end = len - *scroll;
+                    let start = end.saturating_sub(height);
+
+                    let query = search.as_ref().map(|q| q.to_lowercase());
+                    let lines = row
+                        .log
+                        .range(start..end)
+                        .map(|line| {
+                            let hit = query
+                                .as_ref()
+                                .map(|q| line.to_lowercase().contains(q))
+                                .unwrap_or(false);
+                            let style = if hit {
+                                Style::default()
+                                    .fg(Color::Black)
+                                    .bg(Color::Yellow)
+                            } else {
+                                Style::default()
+                            };
+                            Spans::from(Span::styled(line.clone(), style))
+                        })
+                        .collect::<Vec<_>>();
+                    (format!("Log of {}", row.id), lines)
+                }
+                None => (String::from("Log"), Vec::new()),
+            };
+            let log = Paragraph::new(lines)
+                .block(Block::default().borders(Borders::ALL).title(title));
+            f.render_widget(log, log_area);
+
+            let footer =
+                match typing {
+                    Some(query) => format!("/{}", query),
+                    None if !status.is_empty() => status.clone(),
+                    None => String::from(
+                        "↑/↓ select  PgUp/PgDn/Home/End scroll  / search  n/N \
+                     older/newer match  r restart  s stop  q quit",
+                    ),
+                };
+            f.render_widget(
+                Paragraph::new(footer).style(Style::default().fg(Color::Cyan)),
+                rows[2],
+            );
+        })?;
+
+        Ok(())
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
@@ -1,13 +1,16 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::path::Path;
+use std::process::Child;
+use std::thread::JoinHandle;
 
 use async_std::fs::{self, read_to_string, write};
 use tempdir::TempDir;
 
-use super::run::Steps;
+use super::compose::{ComposeBuilder, Fragment};
 use super::OutputConfig;
 use crate::channel::Channel;
+use crate::plan::Steps;
 use crate::runner::Runner;
 
 /// Create a docker-compose file from a nautirust pipeline
@@ -20,6 +23,13 @@ pub struct Command {
     /// temporary directory to put step configuration files
     #[clap(short, long)]
     tmp_dir: Option<String>,
+    /// Print the scripts that would be executed, without executing them
+    #[clap(long)]
+    dry_run: bool,
+    /// Concatenate the output of the docker scripts as text instead of
+    /// parsing and merging it
+    #[clap(long)]
+    raw:     bool,
 }
 
 impl Command {
@@ -45,7 +55,7 @@ impl Command {
                 out
             });
 
-        let content = read_to_string(self.file).await.unwrap();
+        let content = read_to_string(&self.file).await.unwrap();
         let values: Steps = serde_json::from_str(&content).unwrap();
 
         // Check if each runner can docker
@@ -66,29 +76,28 @@ impl Command {
             return;
         }
 
-        let mut procs = Vec::new();
-
-        let used_channels = super::get_used_channels(&content, &channels);
-        used_channels.for_each(
-            |Channel {
-                 docker,
-                 location,
-                 id,
-                 ..
-             }| {
-                super::add_add_subproc(
-                    docker,
-                    location.as_ref(),
-                    &mut procs,
-                    id,
-                    OutputConfig {
-                        stdout: true,
-                        stderr: false,
-                    },
-                )
-            },
-        );
+        let output_config = || {
+            OutputConfig {
+                stdout: true,
+                stderr: false,
+            }
+        };
 
+        let mut channel_procs = Vec::new();
+        for channel in super::get_used_channels(&content, &channels) {
+            let mut procs = Vec::new();
+            super::add_add_subproc(
+                &channel.docker,
+                channel.location.as_ref(),
+                &mut procs,
+                &channel.id,
+                output_config(),
+                self.dry_run,
+            );
+            channel_procs.push((&channel.id, procs));
+        }
+
+        let mut step_procs = Vec::new();
         for value in &values.steps {
             let file = path.join(format!("{}.json", value.processor_config.id));
             let config = serde_json::to_vec_pretty(&value).unwrap();
@@ -117,28 +126,99 @@ impl Command {
                 .replace("{config}", &config_path)
                 .replace("{cwd}", &current_dir);
 
+            let mut procs = Vec::new();
             super::add_add_subproc(
                 &script.into(),
                 runner.location.as_ref(),
                 &mut procs,
                 &value.processor_config.id,
-                OutputConfig {
-                    stdout: true,
-                    stderr: false,
-                },
+                output_config(),
+                self.dry_run,
             );
+            step_procs.push((value, procs));
+
+            if self.dry_run {
+                println!("  config:  {}", file.display());
+            }
+        }
+
+        if self.dry_run {
+            return;
+        }
+
+        let collect =
+            |procs: Vec<(Child, JoinHandle<String>, JoinHandle<String>)>| {
+                procs
+                    .into_iter()
+                    .map(|(mut proc, h1, h2)| {
+                        proc.wait().unwrap();
+                        let output = h1.join().unwrap();
+                        h2.join().unwrap();
+                        output
+                    })
+                    .collect::<String>()
+            };
+
+        let parse = |owner: &str, output: String| {
+            if self.raw {
+                return Fragment::Raw(output);
+            }
+
+            let fragment = Fragment::parse(&output);
+            if let Fragment::Raw(_) = fragment {
+                eprintln!(
+                    "Output of the docker script of {} is not a valid compose \
+                     fragment, adding it as raw text",
+                    owner
+                );
+            }
+            fragment
+        };
+
+        let mut builder = ComposeBuilder::default();
+
+        let mut channel_services = HashMap::new();
+        for (id, procs) in channel_procs {
+            let owner = format!("channel {}", id);
+            let fragment = parse(&owner, collect(procs));
+            let services = builder.add(&owner, fragment);
+            channel_services.insert(id.as_str(), services);
+        }
+
+        let mut step_services = Vec::new();
+        for (value, procs) in step_procs {
+            let owner = format!("step {}", value.processor_config.id);
+            let fragment = parse(&owner, collect(procs));
+            step_services.push((value, builder.add(&owner, fragment)));
+        }
+
+        // Steps depend on the services of the channels they use, and writers
+        // on the readers they are linked to, so consumers are up before data
+        // is produced
+        for (step, services) in &step_services {
+            for config in step.readers().chain(step.writers()) {
+                if let Some(on) = channel_services.get(config.ty()) {
+                    builder.depend(services, on);
+                }
+            }
+
+            for (other, other_services) in &step_services {
+                if step.writers().any(|w| other.readers().any(|r| r == w)) {
+                    builder.depend(services, other_services);
+                }
+            }
         }
 
-        let docker_header = "services:\n";
-        let docker_content: String = [docker_header.to_string()]
-            .into_iter()
-            .chain(procs.into_iter().map(|(mut proc, h1, h2)| {
-                proc.wait().unwrap();
-                let output = h1.join().unwrap();
-                h2.join().unwrap();
-                output
-            }))
-            .collect();
+        let docker_content = match builder.build() {
+            Ok(content) => content,
+            Err(errors) => {
+                eprintln!("Failed to create the docker-compose file.");
+                for e in errors {
+                    eprintln!("Error: {}", e);
+                }
+                return;
+            }
+        };
 
         if self.output {
             write("docker-compose.yml", docker_content).await.unwrap();
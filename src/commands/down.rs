@@ -0,0 +1,106 @@
+use std::path::Path;
+use std::time::Duration;
+
+use async_std::fs::read_to_string;
+
+use super::runs::{self, RunInfo};
+use crate::channel::Channel;
+use crate::hooks::Hooks;
+use crate::runner::Runner;
+
+/// Stop a running pipeline and run the stop scripts of its channels/runners
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Run id, defaults to the most recent running pipeline
+    run:      Option<String>,
+    /// Seconds to wait for the run to stop before killing it
+    #[clap(long, default_value_t = 10)]
+    grace:    u64,
+    /// Directory containing the run directories
+    #[clap(long, default_value = runs::DEFAULT_RUNS_DIR)]
+    runs_dir: String,
+}
+
+impl Command {
+    pub(crate) async fn execute(
+        self,
+        channels: Vec<Channel>,
+        runners: Vec<Runner>,
+        hooks: &Hooks,
+    ) {
+        let base = Path::new(&self.runs_dir);
+        let (path, info) =
+            match runs::find_active_run(base, self.run.as_deref()).await {
+                Some(x) => x,
+                None => {
+                    eprintln!("No running pipeline found");
+                    return;
+                }
+            };
+
+        match info.supervisor {
+            Some(pid) if info.detached && super::is_alive(pid) => {
+                // The supervisor terminates its steps itself when asked to
+                println!("Stopping run {}", info.id);
+                unsafe {
+                    libc::kill(pid as libc::pid_t, libc::SIGTERM);
+                }
+
+                let mut waited = 0;
+                while super::is_alive(pid) && waited < self.grace * 10 {
+                    async_std::task::sleep(Duration::from_millis(100)).await;
+                    waited += 1;
+                }
+
+                if super::is_alive(pid) {
+                    eprintln!("Run did not stop in time, killing it");
+                    super::signal_group(pid, libc::SIGKILL);
+                    for proc in &info.processes {
+                        if let (Some(pid), None) = (proc.pid, proc.finished) {
+                            super::signal_group(pid, libc::SIGKILL);
+                        }
+                    }
+                }
+            }
+            Some(pid) if super::is_alive(pid) => {
+                eprintln!(
+                    "Run {} is running in the foreground (pid {}), stop it \
+                     there",
+                    info.id, pid
+                );
+                return;
+            }
+            _ => {}
+        }
+
+        // Record the outcome on behalf of a supervisor that was killed
+        let mut info = RunInfo::load(&path).await.unwrap_or(info);
+        let now = runs::now_millis();
+        info.stopped = true;
+        info.finished.get_or_insert(now);
+        for proc in &mut info.processes {
+            if proc.finished.is_none() {
+                proc.finished = Some(now);
+                proc.reason = Some(String::from("killed"));
+            }
+        }
+        if let Err(e) = info.save(&path).await {
+            eprintln!("Failed to write run metadata: {}", e);
+        }
+
+        match read_to_string(path.join("plan.json")).await {
+            Ok(content) => {
+                super::stop::stop_pipeline(
+                    &info.plan,
+                    &content,
+                    Some(&path),
+                    &channels,
+                    &runners,
+                    hooks,
+                    false,
+                )
+            }
+            Err(e) => eprintln!("Failed to read the plan of the run: {}", e),
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use async_std::fs::{self, read_to_string, write};
+use clap::Subcommand;
+
+use super::run::get_params;
+use crate::channel::Channel;
+use crate::exec::{render_config, Params};
+use crate::limits::Limits;
+use crate::plan::Steps;
+use crate::runner::Runner;
+use crate::step::{Output, RunThing, StepArgument};
+
+mod sh;
+mod systemd;
+
+/// Export a pipeline so it can run without nautirust
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    #[clap(subcommand)]
+    format: Format,
+}
+
+#[derive(Subcommand, Debug)]
+enum Format {
+    Systemd(systemd::Command),
+    Sh(sh::Command),
+}
+
+impl Command {
+    pub(crate) async fn execute(
+        self,
+        channels: Vec<Channel>,
+        runners: Vec<Runner>,
+    ) {
+        match self.format {
+            Format::Systemd(systemd) => {
+                systemd.execute(channels, runners).await
+            }
+            Format::Sh(sh) => sh.execute(channels, runners).await,
+        }
+    }
+}
+
+/// Arguments shared by all export formats
+#[derive(clap::Args, Debug)]
+struct ExportArgs {
+    /// Config file
+    file:       String,
+    /// Directory to write the exported files to
+    #[clap(short, long)]
+    output:     Option<String>,
+    /// Name of the pipeline, defaults to the name of the config file
+    #[clap(long)]
+    name:       Option<String>,
+    /// Directory the step configuration files are read from when the pipeline
+    /// runs, defaults to where they are written
+    #[clap(long)]
+    config_dir: Option<String>,
+}
+
+/// A process that has to finish before a step starts, its output is written
+/// to `output`
+pub(crate) struct PreStep {
+    pub id:       String,
+    pub config:   String,
+    pub command:  String,
+    pub location: Option<PathBuf>,
+    pub output:   String,
+    pub stream:   Output,
+    /// Ids of the sub-steps this one needs the output of
+    pub needs:    Vec<String>,
+}
+
+pub(crate) struct ExportStep<'a> {
+    pub id:        String,
+    pub run:       RunThing,
+    pub runner:    &'a Runner,
+    pub config:    String,
+    pub command:   String,
+    pub limits:    Limits,
+    /// Sub-steps, in the order they have to run
+    pub pre:       Vec<PreStep>,
+    /// Reads or writes a stream, instead of running to completion
+    pub streaming: bool,
+    /// Indices of the steps reading what this step writes
+    pub readers:   Vec<usize>,
+}
+
+/// A plan resolved into the scripts and configuration files `run` would use
+pub(crate) struct Export<'a> {
+    pub name:       String,
+    /// Where the exported files are written
+    pub out_dir:    PathBuf,
+    /// Where the configuration files are read from at run time
+    pub config_dir: String,
+    pub channels:   Vec<&'a Channel>,
+    pub runners:    Vec<&'a Runner>,
+    pub steps:      Vec<ExportStep<'a>>,
+}
+
+/// Turns an id into something usable in file and unit names
+fn file_name(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn runner_command(runner: &Runner, config_file: &str, cwd: &str) -> String {
+    runner
+        .script
+        .replace("{config}", &format!("'{}'", config_file))
+        .replace("{cwd}", &format!("'{}'", cwd))
+}
+
+impl ExportArgs {
+    /// Reads the plan and resolves its steps, the configuration files are
+    /// expected in `<output>/<name>/` unless `--config-dir` is given
+    async fn resolve<'a>(
+        &self,
+        channels: &'a [Channel],
+        runners: &'a [Runner],
+        default_output: &str,
+    ) -> Result<Export<'a>, Vec<String>> {
+        let content = read_to_string(&self.file).await.map_err(|e| {
+            vec![format!("Failed to read {}: {}", self.file, e)]
+        })?;
+        let values: Steps =
+            serde_json::from_str(&content).map_err(|e| vec![e.to_string()])?;
+        let params = get_params(&values.params)?;
+
+        let name = self.name.clone().unwrap_or_else(|| {
+            Path::new(&self.file)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| String::from("pipeline"))
+        });
+        let name = file_name(&name);
+
+        let out_dir =
+            PathBuf::from(self.output.as_deref().unwrap_or(default_output));
+        let cwd = env::current_dir()
+            .and_then(|d| d.canonicalize())
+            .map_err(|e| vec![e.to_string()])?;
+        let config_dir = match &self.config_dir {
+            Some(dir) => dir.clone(),
+            None => cwd.join(&out_dir).join(&name).display().to_string(),
+        };
+        let cwd = cwd.display().to_string();
+
+        let mut errors = Vec::new();
+        let mut steps = Vec::new();
+        for run in &values.steps {
+            let id = &run.processor_config.id;
+            let runner = match runners
+                .iter()
+                .find(|r| r.id == run.processor_config.runner_id)
+            {
+                Some(runner) => runner,
+                None => {
+                    errors.push(format!("No runner found for step {}", id));
+                    continue;
+                }
+            };
+
+            let mut pre = Vec::new();
+            if let Err(e) =
+                pre_steps(run, runners, &params, &config_dir, &cwd, &mut pre)
+            {
+                errors.push(e);
+                continue;
+            }
+
+            let config = match render_config(run, &params, &|file| {
+                format!("{}/{}", config_dir, file)
+            }) {
+                Some(config) => config,
+                None => {
+                    errors
+                        .push(format!("Failed to render the config of {}", id));
+                    continue;
+                }
+            };
+            let config_file = format!("{}/{}.json", config_dir, file_name(id));
+
+            steps.push(ExportStep {
+                id: id.clone(),
+                run: run.clone(),
+                runner,
+                command: runner_command(runner, &config_file, &cwd),
+                config,
+                limits: runner.limits.merge(&run.processor_config.limits),
+                pre,
+                streaming: run.readers().next().is_some()
+                    || run.writers().next().is_some(),
+                readers: Vec::new(),
+            });
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        for i in 0..steps.len() {
+            let readers = (0..steps.len())
+                .filter(|&j| {
+                    j != i
+                        && steps[i]
+                            .run
+                            .writers()
+                            .any(|w| steps[j].run.readers().any(|r| r == w))
+                })
+                .collect();
+            steps[i].readers = readers;
+        }
+
+        let used_channels: Vec<String> =
+            super::get_used_channels(&content, channels)
+                .map(|c| c.id.clone())
+                .collect();
+        let used_channels = channels
+            .iter()
+            .filter(|c| used_channels.contains(&c.id))
+            .filter(|c| c.start.is_some() || c.stop.is_some())
+            .collect();
+        let used_runners = runners
+            .iter()
+            .filter(|r| r.start.is_some() || r.stop.is_some())
+            .filter(|r| steps.iter().any(|s| s.runner.id == r.id))
+            .collect();
+
+        Ok(Export {
+            name,
+            out_dir,
+            config_dir,
+            channels: used_channels,
+            runners: used_runners,
+            steps,
+        })
+    }
+}
+
+impl<'a> Export<'a> {
+    /// Configuration files as `(file name, content)`, sub-steps included
+    pub fn config_files(&self) -> impl Iterator<Item = (String, &str)> {
+        self.steps.iter().flat_map(|step| {
+            std::iter::once((&step.id, &step.config))
+                .chain(step.pre.iter().map(|pre| (&pre.id, &pre.config)))
+                .map(|(id, config)| {
+                    (format!("{}.json", file_name(id)), config.as_str())
+                })
+        })
+    }
+
+    /// Writes the configuration files to `<output>/<name>/`, next to the
+    /// exported files
+    pub async fn write_config_files(&self) -> std::io::Result<()> {
+        let dir = self.out_dir.join(&self.name);
+        fs::create_dir_all(&dir).await?;
+        for (file, config) in self.config_files() {
+            write(dir.join(file), config).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the sub-steps of `run`, nested sub-steps first
+fn pre_steps(
+    run: &RunThing,
+    runners: &[Runner],
+    params: &Params,
+    config_dir: &str,
+    cwd: &str,
+    out: &mut Vec<PreStep>,
+) -> Result<(), String> {
+    for arg in run.args.values() {
+        let sub = match arg {
+            StepArgument::Step { sub } => sub,
+            _ => continue,
+        };
+
+        pre_steps(&sub.run, runners, params, config_dir, cwd, out)?;
+
+        let id = &sub.run.processor_config.id;
+        let runner = runners
+            .iter()
+            .find(|r| r.id == sub.run.processor_config.runner_id)
+            .ok_or_else(|| format!("No runner found for sub-step {}", id))?;
+
+        let config = render_config(&sub.run, params, &|file| {
+            format!("{}/{}", config_dir, file)
+        })
+        .ok_or_else(|| format!("Failed to render the config of {}", id))?;
+
+        let config_file = format!("{}/{}.json", config_dir, file_name(id));
+        let terminator = match sub.output {
+            Output::Stdout => "stdout",
+            Output::Stderr => "stderr",
+        };
+
+        let needs = sub
+            .run
+            .args
+            .values()
+            .filter_map(|arg| {
+                match arg {
+                    StepArgument::Step { sub } => {
+                        Some(sub.run.processor_config.id.clone())
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+
+        out.push(PreStep {
+            id: id.clone(),
+            command: runner_command(runner, &config_file, cwd),
+            location: runner.location.clone(),
+            config,
+            output: format!("{}/{}.{}", config_dir, id, terminator),
+            stream: sub.output.clone(),
+            needs,
+        });
+    }
+
+    Ok(())
+}
+
+/// Prints the errors of `resolve`
+fn print_errors(errors: Vec<String>) {
+    eprintln!("Failed to export the pipeline.");
+    for e in errors {
+        eprintln!("Error: {}", e);
+    }
+}
@@ -0,0 +1,419 @@
+use std::fmt::Write as _;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use async_std::fs::{self, write};
+
+use super::{print_errors, Export, ExportArgs, ExportStep};
+use crate::channel::Channel;
+use crate::runner::Runner;
+use crate::step::Output;
+
+const HEREDOC: &str = "NAUTIRUST_EOF";
+
+/// Write a POSIX shell script that prepares, runs and stops the pipeline
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    #[clap(flatten)]
+    args: ExportArgs,
+}
+
+/// Quotes `s` as a single shell word
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// A subshell running `script` with `sh` in `location`, after the `setup`
+/// commands, `prefix` is put in front of `sh`
+fn subshell(
+    script: &str,
+    location: Option<&Path>,
+    setup: &[String],
+    prefix: &str,
+) -> String {
+    let location = location
+        .and_then(crate::commands::expand_tilde)
+        .map(|l| l.canonicalize().unwrap_or(l));
+
+    let mut commands = Vec::new();
+    if let Some(dir) = location {
+        commands.push(format!("cd {}", quote(&dir.display().to_string())));
+    }
+    commands.extend(setup.iter().cloned());
+    commands.push(format!("exec {}sh -c {}", prefix, quote(script)));
+
+    format!("( {} )", commands.join(" && "))
+}
+
+/// Runs a start, stop or build script, reporting but ignoring failures like
+/// `prepare` and `stop` do
+fn script(
+    out: &mut String,
+    what: &str,
+    script: &Option<String>,
+    location: Option<&Path>,
+) {
+    if let Some(script) = script {
+        let _ = writeln!(
+            out,
+            "{} || echo {} >&2",
+            subshell(script, location, &[], ""),
+            quote(&format!("{} failed", what))
+        );
+    }
+}
+
+/// Runs the sub-steps of `step` that did not run yet, then starts it in the
+/// background. `sub_steps` are the ids of the sub-steps that already ran.
+fn step<'a>(
+    out: &mut String,
+    step: &'a ExportStep,
+    sub_steps: &mut Vec<&'a str>,
+) {
+    let _ =
+        writeln!(out, "echo {}", quote(&format!("Starting step {}", step.id)));
+
+    for pre in &step.pre {
+        // A sub-step shared by several steps runs once, rerunning it would
+        // overwrite the output an earlier step may be reading
+        if sub_steps.contains(&pre.id.as_str()) {
+            continue;
+        }
+        sub_steps.push(&pre.id);
+
+        let redirect = match pre.stream {
+            Output::Stdout => ">",
+            Output::Stderr => "2>",
+        };
+        let _ = writeln!(
+            out,
+            "{} {} {} || fail {}",
+            subshell(&pre.command, pre.location.as_deref(), &[], ""),
+            redirect,
+            quote(&pre.output),
+            quote(&format!("Sub-step {} failed", pre.id))
+        );
+    }
+
+    // Limits are applied in the subshell, so they only affect the step
+    let limits = &step.limits;
+    let mut ulimits = Vec::new();
+    if let Some(bytes) = limits.max_memory {
+        ulimits.push(format!("ulimit -v {}", bytes / 1024));
+    }
+    if let Some(secs) = limits.cpu_time {
+        ulimits.push(format!("ulimit -t {}", secs));
+    }
+    if let Some(n) = limits.open_files {
+        ulimits.push(format!("ulimit -n {}", n));
+    }
+
+    let mut prefix = String::new();
+    if let Some(n) = limits.nice {
+        let _ = write!(prefix, "nice -n {} ", n);
+    }
+    if let Some(secs) = limits.timeout {
+        let _ =
+            write!(prefix, "timeout -k {} {} ", limits.grace_period(), secs);
+    }
+
+    let _ = writeln!(
+        out,
+        "{} &",
+        subshell(
+            &step.command,
+            step.runner.location.as_deref(),
+            &ulimits,
+            &prefix
+        )
+    );
+    let _ = writeln!(out, "PIDS=\"$PIDS $!\"");
+}
+
+fn build_script(export: &Export, file: &str) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "#!/bin/sh");
+    let _ = writeln!(
+        out,
+        "# Pipeline {}, exported by nautirust from {}",
+        export.name, file
+    );
+    let _ = writeln!(
+        out,
+        "# Starts the channels and runners, runs the steps and stops \
+         everything again\n# when the steps are done or on SIGINT/SIGTERM."
+    );
+    let _ = writeln!(out, "set -u");
+    let _ = writeln!(
+        out,
+        "# Background steps get their own process group when job control is \
+         available\n# (run from a terminal), so they are stopped as a whole"
+    );
+    let _ = writeln!(out, "set -m");
+    let _ = writeln!(out);
+
+    let _ = writeln!(out, "# Step configuration");
+    let _ = writeln!(out, "mkdir -p {} || exit 1", quote(&export.config_dir));
+    for (name, config) in export.config_files() {
+        let path = format!("{}/{}", export.config_dir, name);
+        let _ = writeln!(out, "cat > {} <<'{}'", quote(&path), HEREDOC);
+        let _ = writeln!(out, "{}", config);
+        let _ = writeln!(out, "{}", HEREDOC);
+    }
+    let _ = writeln!(out);
+
+    let _ = writeln!(out, "PIDS=");
+    let _ = writeln!(out, "STOPPED=");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "stop_pipeline() {{");
+    let _ = writeln!(out, "    [ -n \"$STOPPED\" ] && return");
+    let _ = writeln!(out, "    STOPPED=1");
+    let _ = writeln!(out, "    for pid in $PIDS; do");
+    let _ = writeln!(
+        out,
+        "        kill -TERM -- \"-$pid\" 2>/dev/null || kill -TERM \"$pid\" \
+         2>/dev/null"
+    );
+    let _ = writeln!(out, "    done");
+    let _ = writeln!(out, "    wait");
+    let mut stops = Vec::new();
+    for channel in &export.channels {
+        let mut stop = String::new();
+        script(
+            &mut stop,
+            &format!("Stop script of channel {}", channel.id),
+            &channel.stop,
+            channel.location.as_deref(),
+        );
+        stops.push(stop);
+    }
+    for runner in &export.runners {
+        let mut stop = String::new();
+        script(
+            &mut stop,
+            &format!("Stop script of runner {}", runner.id),
+            &runner.stop,
+            runner.location.as_deref(),
+        );
+        stops.push(stop);
+    }
+    // Stopped in the reverse order, a script can span several lines
+    for stop in stops.iter().rev().filter(|s| !s.is_empty()) {
+        let _ = write!(out, "    {}", stop);
+    }
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "fail() {{");
+    let _ = writeln!(out, "    echo \"$1\" >&2");
+    let _ = writeln!(out, "    stop_pipeline");
+    let _ = writeln!(out, "    exit 1");
+    let _ = writeln!(out, "}}");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "trap 'stop_pipeline; exit 130' INT");
+    let _ = writeln!(out, "trap 'stop_pipeline; exit 143' TERM");
+    let _ = writeln!(out);
+
+    let _ = writeln!(out, "# Prepare");
+    for channel in &export.channels {
+        script(
+            &mut out,
+            &format!("Start script of channel {}", channel.id),
+            &channel.start,
+            channel.location.as_deref(),
+        );
+    }
+    for runner in &export.runners {
+        script(
+            &mut out,
+            &format!("Start script of runner {}", runner.id),
+            &runner.start,
+            runner.location.as_deref(),
+        );
+    }
+    for step in &export.steps {
+        let config = &step.run.processor_config;
+        script(
+            &mut out,
+            &format!("Build script of step {}", step.id),
+            &config.build,
+            config.location.as_deref().map(Path::new),
+        );
+    }
+    let _ = writeln!(out);
+
+    let _ = writeln!(out, "# Run");
+    let mut sub_steps = Vec::new();
+    for s in &export.steps {
+        step(&mut out, s, &mut sub_steps);
+    }
+    let _ = writeln!(out);
+
+    let _ = writeln!(out, "STATUS=0");
+    let _ = writeln!(out, "for pid in $PIDS; do");
+    let _ = writeln!(out, "    wait \"$pid\" || STATUS=1");
+    let _ = writeln!(out, "done");
+    let _ = writeln!(out);
+    let _ = writeln!(out, "# Stop");
+    let _ = writeln!(out, "stop_pipeline");
+    let _ = writeln!(out, "exit $STATUS");
+
+    out
+}
+
+impl Command {
+    pub(crate) async fn execute(
+        self,
+        channels: Vec<Channel>,
+        runners: Vec<Runner>,
+    ) {
+        let export = match self.args.resolve(&channels, &runners, ".").await {
+            Ok(export) => export,
+            Err(errors) => return print_errors(errors),
+        };
+
+        let content = build_script(&export, &self.args.file);
+        let path = export.out_dir.join(format!("{}.sh", export.name));
+
+        if let Err(e) = fs::create_dir_all(&export.out_dir).await {
+            return print_errors(vec![e.to_string()]);
+        }
+        write(&path, content).await.unwrap();
+        fs::set_permissions(&path, PermissionsExt::from_mode(0o755))
+            .await
+            .unwrap();
+
+        println!(
+            "Wrote {}, it writes the configuration files to {}",
+            path.display(),
+            export.config_dir
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    use super::*;
+    use crate::commands::export::PreStep;
+    use crate::limits::Limits;
+
+    fn channel(id: &str, stop: &str) -> Channel {
+        serde_json::from_value(json!({
+            "id": id,
+            "requiredFields": [],
+            "start": format!("echo start {}", id),
+            "stop": stop,
+        }))
+        .unwrap()
+    }
+
+    fn export<'a>(channels: &'a [Channel]) -> Export<'a> {
+        Export {
+            name:       String::from("pipeline"),
+            out_dir:    ".".into(),
+            config_dir: String::from("/tmp/pipeline"),
+            channels:   channels.iter().collect(),
+            runners:    Vec::new(),
+            steps:      Vec::new(),
+        }
+    }
+
+    fn export_step<'a>(
+        id: &str,
+        runner: &'a Runner,
+        pre: &[&str],
+    ) -> ExportStep<'a> {
+        let run = serde_json::from_value(json!({
+            "processorConfig": {
+                "id": id,
+                "runnerId": "JsRunner",
+                "config": {},
+                "args": [],
+            },
+            "args": {},
+        }))
+        .unwrap();
+        let pre = pre
+            .iter()
+            .map(|id| {
+                PreStep {
+                    id:       id.to_string(),
+                    config:   format!("{}.json", id),
+                    command:  format!("run {}", id),
+                    location: None,
+                    output:   format!("{}.out", id),
+                    stream:   Output::Stdout,
+                    needs:    Vec::new(),
+                }
+            })
+            .collect();
+
+        ExportStep {
+            id: id.to_string(),
+            run,
+            runner,
+            config: format!("{}.json", id),
+            command: format!("run {}", id),
+            limits: Limits::default(),
+            pre,
+            streaming: false,
+            readers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn shared_sub_steps_run_once() {
+        let runner: Runner = serde_json::from_value(json!({
+            "id": "JsRunner",
+            "runnerScript": "node {config}",
+            "canUseChannel": ["file"],
+            "requiredFields": [],
+            "canUseSerialization": ["json"],
+        }))
+        .unwrap();
+        let mut export = export(&[]);
+        export.steps = vec![
+            export_step("first", &runner, &["shared", "own"]),
+            export_step("second", &runner, &["shared"]),
+        ];
+        let script = build_script(&export, "plan.json");
+
+        assert_eq!(script.matches("'run shared'").count(), 1, "{}", script);
+        assert_eq!(script.matches("'run own'").count(), 1, "{}", script);
+        assert_eq!(script.matches("'run second'").count(), 1, "{}", script);
+    }
+
+    #[test]
+    fn multi_line_stop_scripts_stay_whole() {
+        let channels = [
+            channel("first", "if true; then\n  echo 'stop first'\nfi"),
+            channel("second", "echo stop second"),
+        ];
+        let script = build_script(&export(&channels), "plan.json");
+
+        let second = script.find("echo stop second").unwrap();
+        let first = script.find("if true; then\n  echo").unwrap();
+        assert!(second < first, "stop scripts are not reversed:\n{}", script);
+
+        let mut sh = Command::new("sh")
+            .arg("-n")
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        sh.stdin
+            .take()
+            .unwrap()
+            .write_all(script.as_bytes())
+            .unwrap();
+        let output = sh.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}\n{}",
+            String::from_utf8_lossy(&output.stderr),
+            script
+        );
+    }
+}
@@ -0,0 +1,348 @@
+use std::fmt::Write as _;
+use std::path::Path;
+
+use async_std::fs::write;
+
+use super::{print_errors, Export, ExportArgs, ExportStep, PreStep};
+use crate::channel::Channel;
+use crate::runner::Runner;
+use crate::step::Output;
+
+/// Write systemd units that start the pipeline, one per step and per
+/// channel/runner start and stop script
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    #[clap(flatten)]
+    args:        ExportArgs,
+    /// Restart policy of the steps that read or write a stream
+    #[clap(long, default_value = "on-failure", possible_values = [
+        "no", "always", "on-success", "on-failure", "on-abnormal", "on-abort",
+    ])]
+    restart:     String,
+    /// Seconds to wait before restarting a step
+    #[clap(long, default_value_t = 5)]
+    restart_sec: u64,
+}
+
+/// Quotes a script as a `/bin/sh -c` command line systemd does not expand
+fn exec(script: &str) -> String {
+    let mut out = String::from("/bin/sh -c \"");
+    for c in script.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '%' => out.push_str("%%"),
+            '$' => out.push_str("$$"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn working_directory(location: Option<&Path>) -> Option<String> {
+    location
+        .and_then(crate::commands::expand_tilde)
+        .map(|l| l.canonicalize().unwrap_or(l).display().to_string())
+}
+
+struct Units<'a> {
+    export: Export<'a>,
+    files:  Vec<(String, String)>,
+}
+
+impl<'a> Units<'a> {
+    fn target(&self) -> String {
+        format!("{}.target", self.export.name)
+    }
+
+    fn channel_unit(&self, id: &str) -> String {
+        format!(
+            "{}-channel-{}.service",
+            self.export.name,
+            super::file_name(id)
+        )
+    }
+
+    fn runner_unit(&self, id: &str) -> String {
+        format!(
+            "{}-runner-{}.service",
+            self.export.name,
+            super::file_name(id)
+        )
+    }
+
+    fn step_unit(&self, id: &str) -> String {
+        format!("{}-step-{}.service", self.export.name, super::file_name(id))
+    }
+
+    fn sub_step_unit(&self, id: &str) -> String {
+        format!(
+            "{}-substep-{}.service",
+            self.export.name,
+            super::file_name(id)
+        )
+    }
+
+    /// A oneshot unit writing the output of a sub-step, run once however many
+    /// steps need it
+    fn sub_step(&self, pre: &PreStep) -> String {
+        let redirect = match pre.stream {
+            Output::Stdout => ">",
+            Output::Stderr => "2>",
+        };
+        let script =
+            format!("( {} ) {} '{}'", pre.command, redirect, pre.output);
+
+        let requires: Vec<String> =
+            pre.needs.iter().map(|id| self.sub_step_unit(id)).collect();
+
+        let mut unit = String::new();
+        let _ = writeln!(unit, "[Unit]");
+        let _ = writeln!(
+            unit,
+            "Description=Sub-step {} of pipeline {}",
+            pre.id, self.export.name
+        );
+        let _ = writeln!(unit, "PartOf={}", self.target());
+        if !requires.is_empty() {
+            let _ = writeln!(unit, "Requires={}", requires.join(" "));
+            let _ = writeln!(unit, "After={}", requires.join(" "));
+        }
+        let _ = writeln!(unit);
+        let _ = writeln!(unit, "[Service]");
+        let _ = writeln!(unit, "Type=oneshot");
+        let _ = writeln!(unit, "RemainAfterExit=yes");
+        if let Some(dir) = working_directory(pre.location.as_deref()) {
+            let _ = writeln!(unit, "WorkingDirectory={}", dir);
+        }
+        let _ = writeln!(unit, "ExecStart={}", exec(&script));
+        unit
+    }
+
+    /// A oneshot unit that runs `start` when started and `stop` when stopped
+    fn script_unit(
+        &self,
+        description: &str,
+        start: &Option<String>,
+        stop: &Option<String>,
+        location: Option<&Path>,
+    ) -> String {
+        let mut unit = String::new();
+        let _ = writeln!(unit, "[Unit]");
+        let _ = writeln!(unit, "Description={}", description);
+        let _ = writeln!(unit, "PartOf={}", self.target());
+        let _ = writeln!(unit);
+        let _ = writeln!(unit, "[Service]");
+        let _ = writeln!(unit, "Type=oneshot");
+        let _ = writeln!(unit, "RemainAfterExit=yes");
+        if let Some(dir) = working_directory(location) {
+            let _ = writeln!(unit, "WorkingDirectory={}", dir);
+        }
+        let _ = writeln!(
+            unit,
+            "ExecStart={}",
+            start
+                .as_deref()
+                .map(exec)
+                .unwrap_or_else(|| "/bin/true".into())
+        );
+        if let Some(stop) = stop {
+            let _ = writeln!(unit, "ExecStop={}", exec(stop));
+        }
+        unit
+    }
+
+    fn step(
+        &self,
+        step: &ExportStep,
+        restart: &str,
+        restart_sec: u64,
+    ) -> String {
+        let mut requires: Vec<String> = Vec::new();
+        let mut require = |unit: String| {
+            if !requires.contains(&unit) {
+                requires.push(unit);
+            }
+        };
+
+        for channel in &self.export.channels {
+            if step
+                .run
+                .readers()
+                .chain(step.run.writers())
+                .any(|c| c.ty() == channel.id)
+            {
+                require(self.channel_unit(&channel.id));
+            }
+        }
+        if self.export.runners.iter().any(|r| r.id == step.runner.id) {
+            require(self.runner_unit(&step.runner.id));
+        }
+        for pre in &step.pre {
+            require(self.sub_step_unit(&pre.id));
+        }
+        // Writers start after the steps reading from them
+        for &reader in &step.readers {
+            require(self.step_unit(&self.export.steps[reader].id));
+        }
+
+        let mut unit = String::new();
+        let _ = writeln!(unit, "[Unit]");
+        let _ = writeln!(
+            unit,
+            "Description=Step {} of pipeline {}",
+            step.id, self.export.name
+        );
+        let _ = writeln!(unit, "PartOf={}", self.target());
+        if !requires.is_empty() {
+            let _ = writeln!(unit, "Requires={}", requires.join(" "));
+            let _ = writeln!(unit, "After={}", requires.join(" "));
+        }
+        let _ = writeln!(unit);
+        let _ = writeln!(unit, "[Service]");
+        if step.streaming {
+            let _ = writeln!(unit, "Type=exec");
+            let _ = writeln!(unit, "Restart={}", restart);
+            let _ = writeln!(unit, "RestartSec={}", restart_sec);
+        } else {
+            let _ = writeln!(unit, "Type=oneshot");
+            let _ = writeln!(unit, "RemainAfterExit=yes");
+        }
+        if let Some(dir) = working_directory(step.runner.location.as_deref()) {
+            let _ = writeln!(unit, "WorkingDirectory={}", dir);
+        }
+
+        let _ = writeln!(unit, "ExecStart={}", exec(&step.command));
+
+        let limits = &step.limits;
+        if let Some(secs) = limits.timeout {
+            let key = if step.streaming {
+                "RuntimeMaxSec"
+            } else {
+                "TimeoutStartSec"
+            };
+            let _ = writeln!(unit, "{}={}", key, secs);
+        }
+        let _ = writeln!(unit, "TimeoutStopSec={}", limits.grace_period());
+        if let Some(bytes) = limits.max_memory {
+            let _ = writeln!(unit, "LimitAS={}", bytes);
+        }
+        if let Some(secs) = limits.cpu_time {
+            let _ = writeln!(unit, "LimitCPU={}", secs);
+        }
+        if let Some(n) = limits.open_files {
+            let _ = writeln!(unit, "LimitNOFILE={}", n);
+        }
+        if let Some(n) = limits.nice {
+            let _ = writeln!(unit, "Nice={}", n);
+        }
+
+        unit
+    }
+
+    fn build(&mut self, restart: &str, restart_sec: u64) {
+        let mut wants = Vec::new();
+
+        for channel in &self.export.channels {
+            let name = self.channel_unit(&channel.id);
+            let unit = self.script_unit(
+                &format!(
+                    "Channel {} of pipeline {}",
+                    channel.id, self.export.name
+                ),
+                &channel.start,
+                &channel.stop,
+                channel.location.as_deref(),
+            );
+            wants.push(name.clone());
+            self.files.push((name, unit));
+        }
+
+        for runner in &self.export.runners {
+            let name = self.runner_unit(&runner.id);
+            let unit = self.script_unit(
+                &format!(
+                    "Runner {} of pipeline {}",
+                    runner.id, self.export.name
+                ),
+                &runner.start,
+                &runner.stop,
+                runner.location.as_deref(),
+            );
+            wants.push(name.clone());
+            self.files.push((name, unit));
+        }
+
+        let mut sub_steps: Vec<&str> = Vec::new();
+        for pre in self.export.steps.iter().flat_map(|s| &s.pre) {
+            if sub_steps.contains(&pre.id.as_str()) {
+                continue;
+            }
+            sub_steps.push(&pre.id);
+            let name = self.sub_step_unit(&pre.id);
+            let unit = self.sub_step(pre);
+            wants.push(name.clone());
+            self.files.push((name, unit));
+        }
+
+        for step in &self.export.steps {
+            let name = self.step_unit(&step.id);
+            let unit = self.step(step, restart, restart_sec);
+            wants.push(name.clone());
+            self.files.push((name, unit));
+        }
+
+        let mut target = String::new();
+        let _ = writeln!(target, "[Unit]");
+        let _ = writeln!(target, "Description=Pipeline {}", self.export.name);
+        let _ = writeln!(target, "Wants={}", wants.join(" "));
+        let _ = writeln!(target);
+        let _ = writeln!(target, "[Install]");
+        let _ = writeln!(target, "WantedBy=multi-user.target");
+        self.files.push((self.target(), target));
+    }
+}
+
+impl Command {
+    pub(crate) async fn execute(
+        self,
+        channels: Vec<Channel>,
+        runners: Vec<Runner>,
+    ) {
+        let export =
+            match self.args.resolve(&channels, &runners, "systemd").await {
+                Ok(export) => export,
+                Err(errors) => return print_errors(errors),
+            };
+
+        if let Err(e) = export.write_config_files().await {
+            return print_errors(vec![e.to_string()]);
+        }
+
+        let mut units = Units {
+            export,
+            files: Vec::new(),
+        };
+        units.build(&self.restart, self.restart_sec);
+
+        let dir = &units.export.out_dir;
+        for (name, content) in &units.files {
+            write(dir.join(name), content).await.unwrap();
+        }
+
+        println!(
+            "Wrote {} units to {}, configuration files are read from {}",
+            units.files.len(),
+            dir.display(),
+            units.export.config_dir
+        );
+        println!(
+            "Install with: cp {0}/*.service {0}/*.target /etc/systemd/system/ \
+             && systemctl daemon-reload && systemctl enable --now {1}",
+            dir.display(),
+            units.target()
+        );
+    }
+}
@@ -6,6 +6,7 @@ use serde_json::Value;
 
 use crate::channel::Channel;
 use crate::commands::generate::state::State;
+use crate::commands::lock::{lock_path, Lock};
 use crate::runner::Runner;
 use crate::step;
 
@@ -48,7 +49,7 @@ pub struct Runtime<'a> {
 }
 
 impl Command {
-    pub(crate) async fn execute<'a>(
+    pub(crate) async fn execute(
         self,
         channels: Vec<Channel>,
         runners: Vec<Runner>,
@@ -85,13 +86,20 @@ impl Command {
             state.apply_step(self.automatic, step, &mut runtime);
         }
 
-        let pretty = serde_json::to_string_pretty(
-            &state.complete(self.automatic, &mut runtime),
-        )
-        .unwrap();
+        let plan = state.complete(self.automatic, &mut runtime);
+        let pretty = serde_json::to_string_pretty(&plan).unwrap();
 
         if let Some(location) = self.output {
-            fs::write(location, pretty.as_bytes()).await.unwrap();
+            fs::write(&location, pretty.as_bytes()).await.unwrap();
+
+            let lock = Lock::new(&plan, &runners, &channels).await;
+            if let Err(e) = lock.save(&location).await {
+                eprintln!(
+                    "Failed to write {}: {}",
+                    lock_path(&location).display(),
+                    e
+                );
+            }
         } else {
             println!("\n");
             println!("{}", pretty);
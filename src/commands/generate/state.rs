@@ -7,7 +7,8 @@ use serde_json::{Map, Value};
 use super::command::Runtime;
 use super::user;
 use crate::channel::ChannelConfig;
-use crate::commands::run::Steps;
+use crate::hooks::Hooks;
+use crate::plan::Steps;
 use crate::step::{
     Output, Step, StepArg, StepArgument, StepArguments, SubStep,
 };
@@ -81,10 +82,11 @@ pub struct State<'a> {
     all_step_args: HashMap<String, StepArguments>,
     done:          Vec<String>,
     used:          HashSet<String>,
-    params: Vec<String>,
+    params:        Vec<String>,
 }
 
 pub struct Ctx<'a> {
+    step:           &'a str,
     channels:       &'a Vec<String>,
     serializations: &'a Vec<String>,
 }
@@ -97,6 +99,7 @@ impl<'a> State<'a> {
         runtime: &mut Runtime<'a>,
     ) {
         let ctx = Ctx {
+            step:           &step.id,
             channels:       runtime.channels.get(&step.runner_id).unwrap(),
             serializations: runtime
                 .serializations
@@ -155,6 +158,7 @@ impl<'a> State<'a> {
             // todo! make better
             let (config, tmp_target) = user::ask_channel_config(
                 id,
+                &format!("{}.{}.{}", ctx.step, arg.id, id),
                 ctx.channels,
                 ctx.serializations,
                 &mut self.open_channels,
@@ -294,12 +298,18 @@ impl<'a> State<'a> {
             .filter(|x| linked_step_ser.iter().any(|y| x == &y))
             .collect();
         let serialization = user::ask_user_for_serialization(&possible_sers);
+        let cache = user::ask_user_for(
+            "Cache output across runs",
+            &["no", "yes"],
+            false,
+        ) == 1;
         StepArgument::Step {
-            sub: SubStep {
+            sub: Box::new(SubStep {
                 run: linked_step.clone().into_runthing(),
                 output,
                 serialization,
-            },
+                cache,
+            }),
         }
     }
 
@@ -315,6 +325,7 @@ impl<'a> State<'a> {
                 );
 
                 let (config, ty) = user::ask_user_for_channel(
+                    &target.to_string(),
                     target.possible_channels,
                     &mut runtime.channel_options,
                     automatic,
@@ -345,6 +356,7 @@ impl<'a> State<'a> {
         Steps {
             steps:  args,
             params: self.params,
+            hooks:  Hooks::default(),
         }
     }
 }
@@ -7,7 +7,7 @@ use dialoguer::FuzzySelect;
 use serde_json::Value;
 
 use super::state::TmpTarget;
-use crate::channel::ChannelConfig;
+use crate::channel::{pipe_option, ChannelConfig};
 
 pub fn create_valid_tmp_target_fn<'a>(
     channel_types: &'a [String],
@@ -29,8 +29,11 @@ pub fn get_if_only_one<T, I: Iterator<Item = T>>(mut iter: I) -> Option<T> {
         .and_then(|v| if iter.next().is_some() { None } else { Some(v) })
 }
 
+/// Asks how the reader field `id` is linked, `reader` is the field as
+/// `<step>.<arg>.<field>`
 pub fn ask_channel_config<'a>(
     id: &str,
+    reader: &str,
     channel_types: &[String],
     ser_types: &[String],
     open_channels: &mut Vec<TmpTarget<'a>>,
@@ -95,7 +98,13 @@ pub fn ask_channel_config<'a>(
         }
     };
 
-    let (config, ty) = ask_user_for_channel(&types, channel_options, automatic);
+    let link = target.as_ref().map(|t| t.to_string());
+    let (config, ty) = ask_user_for_channel(
+        link.as_deref().unwrap_or(reader),
+        &types,
+        channel_options,
+        automatic,
+    );
     let ser = ask_user_for_serialization(&sers);
 
     Some((ChannelConfig::new(ty.to_string(), ser, config), target))
@@ -107,7 +116,9 @@ pub fn ask_user_for_serialization<S: Display>(options: &[S]) -> String {
     options[ser_index].to_string()
 }
 
+/// Asks the channel type and configuration of `link` (`<step>.<arg>.<field>`)
 pub fn ask_user_for_channel<'a>(
+    link: &str,
     types: &'a [String],
     channel_options: &mut HashMap<String, Vec<Value>>,
     automatic: bool,
@@ -117,6 +128,13 @@ pub fn ask_user_for_channel<'a>(
 
     let options = channel_options.get_mut(ty).unwrap();
 
+    // Built-in channels without options get one of their own per link
+    if let (true, Some(out)) = (options.is_empty(), pipe_option(ty, link)) {
+        let type_style = Style::new().italic();
+        println!("Chosen channel config: {}", type_style.apply_to(&out));
+        return (out, ty);
+    }
+
     if automatic {
         let out = options.remove(0);
         let type_style = Style::new().italic();
@@ -0,0 +1,110 @@
+use std::path::Path;
+use std::thread::{spawn, JoinHandle};
+
+use super::runs;
+use crate::hooks::{run_hooks, HookEvent, Hooks, Trigger};
+
+/// Fires the hooks of the events of one `run`, `prepare` or `stop`
+pub(crate) struct Notifier {
+    hooks:   Hooks,
+    base:    HookEvent,
+    /// Step hooks run in the background so they do not hold up the pipeline
+    running: Vec<JoinHandle<()>>,
+}
+
+fn report(failed: Vec<(String, String)>) {
+    for (command, why) in failed {
+        eprintln!("Failed to run hook '{}': {}", command, why);
+    }
+}
+
+impl Notifier {
+    /// `hooks` are the ones of the orchestrator config, they are combined
+    /// with `plan_hooks`
+    pub fn new(
+        hooks: &Hooks,
+        plan_hooks: &Hooks,
+        command: &str,
+        plan: &str,
+    ) -> Self {
+        Self {
+            hooks:   hooks.merge(plan_hooks),
+            base:    HookEvent {
+                command: command.to_string(),
+                plan: plan.to_string(),
+                ..HookEvent::default()
+            },
+            running: Vec::new(),
+        }
+    }
+
+    pub fn set_run(&mut self, id: &str, run_dir: &Path) {
+        self.base.run_id = Some(id.to_string());
+        self.base.run_dir = Some(run_dir.display().to_string());
+    }
+
+    fn event(&self) -> HookEvent {
+        HookEvent {
+            timestamp: runs::now_millis(),
+            ..self.base.clone()
+        }
+    }
+
+    fn fire_background(&mut self, trigger: Trigger, event: HookEvent) {
+        if self.hooks.get(trigger).is_empty() {
+            return;
+        }
+
+        let hooks = self.hooks.clone();
+        self.running
+            .push(spawn(move || report(run_hooks(&hooks, trigger, &event))));
+    }
+
+    pub fn step_start(&mut self, step: &str, kind: &str, pid: u32) {
+        let event = HookEvent {
+            step: Some(step.to_string()),
+            kind: Some(kind.to_string()),
+            pid: Some(pid),
+            ..self.event()
+        };
+        self.fire_background(Trigger::StepStart, event);
+    }
+
+    pub fn step_exit(
+        &mut self,
+        step: &str,
+        kind: &str,
+        exit_code: Option<i32>,
+        reason: Option<String>,
+    ) {
+        let event = HookEvent {
+            step: Some(step.to_string()),
+            kind: Some(kind.to_string()),
+            exit_code,
+            reason,
+            ..self.event()
+        };
+        self.fire_background(Trigger::StepExit, event);
+    }
+
+    /// Waits for the step hooks, then runs `on_pipeline_fail` when something
+    /// failed and `on_pipeline_done` otherwise. `error` is what went wrong in
+    /// the pipeline itself, outside of its processes.
+    pub fn pipeline_end(&mut self, failed: Vec<String>, error: Option<String>) {
+        for handle in self.running.drain(..) {
+            let _ = handle.join();
+        }
+
+        let trigger = if failed.is_empty() && error.is_none() {
+            Trigger::PipelineDone
+        } else {
+            Trigger::PipelineFail
+        };
+        let event = HookEvent {
+            failed,
+            reason: error,
+            ..self.event()
+        };
+        report(run_hooks(&self.hooks, trigger, &event));
+    }
+}
@@ -0,0 +1,616 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+
+use async_std::fs::{self, read_to_string, write};
+use serde_json::Value;
+
+use super::run::get_params;
+use crate::channel::Channel;
+use crate::exec::render_config;
+use crate::plan::Steps;
+use crate::runner::{Runner, RunnerKubernetes};
+use crate::step::{RunThing, StepArgument};
+
+/// Directory in the container in which the step configuration is mounted
+const CONFIG_DIR: &str = "/etc/nautirust";
+const CONFIG_FILE: &str = "config.json";
+
+/// Create Kubernetes manifests from a nautirust pipeline
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Config file
+    file:      String,
+    /// Write one file per object to this directory instead of printing a
+    /// single multi-document YAML
+    #[clap(short, long)]
+    output:    Option<String>,
+    /// Also write a kustomization.yaml listing the generated files
+    #[clap(long, requires = "output")]
+    kustomize: bool,
+    /// Namespace of the generated objects
+    #[clap(short, long)]
+    namespace: Option<String>,
+}
+
+/// Turns an id into a valid DNS-1123 label, failing when nothing of it is
+/// left
+fn object_name(id: &str) -> Result<String, String> {
+    let name: String = id
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+
+    let name = name.trim_matches('-');
+    let name = if name.len() > 63 { &name[..63] } else { name };
+    let name = name.trim_end_matches('-');
+    if name.is_empty() {
+        return Err(format!(
+            "'{}' has no characters usable in a Kubernetes object name",
+            id
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// A step needs a Deployment when it reads or writes a stream, otherwise it
+/// runs to completion as a Job
+fn is_streaming(run: &RunThing) -> bool {
+    run.readers().next().is_some() || run.writers().next().is_some()
+}
+
+fn has_sub_steps(run: &RunThing) -> bool {
+    run.args
+        .values()
+        .any(|arg| matches!(arg, StepArgument::Step { .. }))
+}
+
+/// The generated objects, remembering which step or channel contributed
+/// each kind and name
+struct Manifests<'a> {
+    pipeline:  String,
+    namespace: Option<&'a str>,
+    objects:   Vec<(String, Value)>,
+    owners:    BTreeMap<(String, String), String>,
+    errors:    Vec<String>,
+}
+
+impl<'a> Manifests<'a> {
+    fn new(pipeline: String, namespace: Option<&'a str>) -> Self {
+        Manifests {
+            pipeline,
+            namespace,
+            objects: Vec::new(),
+            owners: BTreeMap::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn metadata(&self, name: &str, component: &str) -> Value {
+        let mut metadata = json!({
+            "name": name,
+            "labels": {
+                "app.kubernetes.io/name": component,
+                "app.kubernetes.io/part-of": self.pipeline,
+                "app.kubernetes.io/managed-by": "nautirust",
+            },
+        });
+
+        if let Some(ns) = self.namespace {
+            metadata["namespace"] = json!(ns);
+        }
+
+        metadata
+    }
+
+    /// Adds an object written to `file`, failing when `owner` defines an
+    /// object of the same kind and name as an earlier owner
+    fn add(&mut self, owner: &str, file: String, object: Value) {
+        let kind = object["kind"].as_str().unwrap_or_default().to_string();
+        let name = object["metadata"]["name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        let mut keys =
+            vec![((String::new(), file.clone()), format!("File '{}'", file))];
+        if !kind.is_empty() && !name.is_empty() {
+            keys.push((
+                (kind.clone(), name.clone()),
+                format!("{} '{}'", kind, name),
+            ));
+        }
+
+        for (key, what) in keys {
+            if let Some(other) = self.owners.get(&key) {
+                self.errors.push(format!(
+                    "{} is defined by both {} and {}",
+                    what, other, owner
+                ));
+                return;
+            }
+            self.owners.insert(key, owner.to_string());
+        }
+
+        self.objects.push((file, object));
+    }
+
+    /// Adds an object of `step`, named `name`
+    fn push(&mut self, step: &str, kind: &str, name: &str, object: Value) {
+        let file = format!("{}-{}.yaml", name, kind.to_lowercase());
+        self.add(&format!("step '{}'", step), file, object);
+    }
+
+    /// The objects, or every collision found while adding them
+    fn build(self) -> Result<Vec<(String, Value)>, Vec<String>> {
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        Ok(self.objects)
+    }
+
+    fn config_map(&mut self, step: &str, name: &str, config: String) {
+        let object = json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": self.metadata(&format!("{}-config", name), name),
+            "data": { CONFIG_FILE: config },
+        });
+        self.push(step, "ConfigMap", &format!("{}-config", name), object);
+    }
+
+    fn workload(
+        &mut self,
+        step: &str,
+        name: &str,
+        kube: &RunnerKubernetes,
+        location: Option<&str>,
+        streaming: bool,
+    ) {
+        let config_dir = format!("{}/{}", CONFIG_DIR, name);
+        let config_path = format!("{}/{}", config_dir, CONFIG_FILE);
+        let cwd = kube.working_dir.as_deref().or(location).unwrap_or("/");
+
+        let command: Vec<String> = kube
+            .command
+            .iter()
+            .map(|part| {
+                // `{cwd}/file` with `/` as the directory is `/file`
+                part.replace("{config}", &config_path)
+                    .replace(
+                        "{cwd}/",
+                        &format!("{}/", cwd.trim_end_matches('/')),
+                    )
+                    .replace("{cwd}", cwd)
+            })
+            .collect();
+
+        let mut container = json!({
+            "name": name,
+            "image": kube.image,
+            "command": command,
+            "volumeMounts": [{
+                "name": "config",
+                "mountPath": config_dir,
+                "readOnly": true,
+            }],
+        });
+
+        if let Some(dir) = &kube.working_dir {
+            container["workingDir"] = json!(dir);
+        }
+        if !kube.env.is_empty() {
+            container["env"] = kube
+                .env
+                .iter()
+                .map(|(k, v)| json!({ "name": k, "value": v }))
+                .collect();
+        }
+        if let Some(resources) = &kube.resources {
+            container["resources"] = resources.clone();
+        }
+
+        let pod = json!({
+            "metadata": { "labels": { "app.kubernetes.io/name": name } },
+            "spec": {
+                "restartPolicy": if streaming { "Always" } else { "Never" },
+                "containers": [container],
+                "volumes": [{
+                    "name": "config",
+                    "configMap": { "name": format!("{}-config", name) },
+                }],
+            },
+        });
+
+        let (kind, spec) = if streaming {
+            (
+                "Deployment",
+                json!({
+                    "replicas": 1,
+                    "selector": {
+                        "matchLabels": { "app.kubernetes.io/name": name },
+                    },
+                    "template": pod,
+                }),
+            )
+        } else {
+            ("Job", json!({ "backoffLimit": 0, "template": pod }))
+        };
+
+        let object = json!({
+            "apiVersion": if streaming { "apps/v1" } else { "batch/v1" },
+            "kind": kind,
+            "metadata": self.metadata(name, name),
+            "spec": spec,
+        });
+        self.push(step, kind, name, object);
+    }
+
+    fn service(&mut self, step: &str, name: &str, ports: &[u16]) {
+        let ports: Vec<Value> = ports
+            .iter()
+            .map(|port| {
+                json!({
+                    "name": format!("port-{}", port),
+                    "port": port,
+                    "targetPort": port,
+                })
+            })
+            .collect();
+
+        let object = json!({
+            "apiVersion": "v1",
+            "kind": "Service",
+            "metadata": self.metadata(name, name),
+            "spec": {
+                "selector": { "app.kubernetes.io/name": name },
+                "ports": ports,
+            },
+        });
+        self.push(step, "Service", name, object);
+    }
+}
+
+impl Command {
+    pub(crate) async fn execute(
+        self,
+        channels: Vec<Channel>,
+        runners: Vec<Runner>,
+    ) {
+        let content = read_to_string(&self.file).await.unwrap();
+        let values: Steps = serde_json::from_str(&content).unwrap();
+
+        let params = match get_params(&values.params) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Failed to get params.");
+                for e in e {
+                    eprintln!("Error: {}", e);
+                }
+                return;
+            }
+        };
+
+        // Check if each runner can be deployed
+        let step_runners = match values
+            .steps
+            .iter()
+            .map(|s| s.processor_config.find_runner(&runners))
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Failed to find the runners.");
+                eprintln!("Error: {}", e);
+                return;
+            }
+        };
+        let runners_without_kubernetes = step_runners
+            .iter()
+            .filter(|runner| runner.kubernetes.is_none())
+            .map(|r| &r.id)
+            .collect::<HashSet<_>>();
+
+        if !runners_without_kubernetes.is_empty() {
+            eprintln!(
+                "Not all runners support kubernetes ({:?})",
+                runners_without_kubernetes
+            );
+            return;
+        }
+
+        let pipeline = Path::new(&self.file)
+            .file_stem()
+            .and_then(|s| object_name(&s.to_string_lossy()).ok())
+            .unwrap_or_else(|| String::from("pipeline"));
+
+        let mut manifests = Manifests::new(pipeline, self.namespace.as_deref());
+
+        // Objects the used channels need, included once per channel
+        for channel in super::get_used_channels(&content, &channels) {
+            let manifests_of_channel = channel
+                .kubernetes
+                .iter()
+                .flat_map(|k| k.manifests.iter().cloned());
+
+            let channel_name = match object_name(&channel.id) {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("Failed to name the objects of a channel.");
+                    eprintln!("Error: {}", e);
+                    return;
+                }
+            };
+            for (i, object) in manifests_of_channel.enumerate() {
+                let file = format!("channel-{}-{}.yaml", channel_name, i);
+                manifests.add(
+                    &format!("channel '{}'", channel.id),
+                    file,
+                    object,
+                );
+            }
+        }
+
+        let mut names = BTreeMap::new();
+        for (step, runner) in values.steps.iter().zip(step_runners) {
+            let id = &step.processor_config.id;
+            let name = match object_name(id) {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("Failed to name the objects of step '{}'.", id);
+                    eprintln!("Error: {}", e);
+                    return;
+                }
+            };
+            if let Some(other) = names.insert(name.clone(), id) {
+                eprintln!(
+                    "Steps '{}' and '{}' both get the object name '{}'",
+                    other, id, name
+                );
+                return;
+            }
+
+            // The config is mounted read-only, so nothing can write the
+            // output of a sub-step next to it
+            if has_sub_steps(step) {
+                eprintln!(
+                    "Step '{}' has sub-steps, which can't run on kubernetes",
+                    id
+                );
+                return;
+            }
+
+            let config = match render_config(step, &params, &|file| {
+                format!("{}/{}/{}", CONFIG_DIR, name, file)
+            }) {
+                Some(config) => config,
+                None => {
+                    eprintln!("Failed to render the config of step '{}'", id);
+                    return;
+                }
+            };
+
+            let kube = runner.kubernetes.as_ref().unwrap();
+
+            manifests.config_map(id, &name, config);
+            manifests.workload(
+                id,
+                &name,
+                kube,
+                step.processor_config.location.as_deref(),
+                is_streaming(step),
+            );
+
+            // Readers of channels that listen get a Service
+            let mut ports = BTreeSet::new();
+            for reader in step.readers() {
+                let channel_ports = channels
+                    .iter()
+                    .filter(|c| c.id == reader.ty())
+                    .flat_map(|c| c.kubernetes.iter())
+                    .flat_map(|k| k.ports.iter());
+                for port in channel_ports {
+                    ports.insert(*port);
+                }
+            }
+            if !ports.is_empty() {
+                let ports: Vec<u16> = ports.into_iter().collect();
+                manifests.service(id, &name, &ports);
+            }
+        }
+
+        let objects = match manifests.build() {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Failed to build the manifests.");
+                for e in e {
+                    eprintln!("Error: {}", e);
+                }
+                return;
+            }
+        };
+        let to_yaml = |object: &Value| serde_yaml::to_string(object).unwrap();
+
+        let dir = match self.output {
+            Some(dir) => dir,
+            None => {
+                for (_, object) in &objects {
+                    println!("---");
+                    print!("{}", to_yaml(object));
+                }
+                return;
+            }
+        };
+
+        let dir = Path::new(&dir);
+        fs::create_dir_all(dir).await.unwrap();
+
+        for (file, object) in &objects {
+            write(dir.join(file), to_yaml(object)).await.unwrap();
+        }
+
+        if self.kustomize {
+            let mut kustomization = json!({
+                "apiVersion": "kustomize.config.k8s.io/v1beta1",
+                "kind": "Kustomization",
+                "resources": objects.iter().map(|(f, _)| f).collect::<Vec<_>>(),
+            });
+            if let Some(ns) = &self.namespace {
+                kustomization["namespace"] = json!(ns);
+            }
+            write(dir.join("kustomization.yaml"), to_yaml(&kustomization))
+                .await
+                .unwrap();
+        }
+
+        println!("Wrote {} objects to {}", objects.len(), dir.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn kube() -> RunnerKubernetes {
+        RunnerKubernetes {
+            image:       String::from("runner:latest"),
+            command:     vec![
+                String::from("node"),
+                String::from("{cwd}/index.js"),
+                String::from("{config}"),
+            ],
+            working_dir: None,
+            env:         BTreeMap::from([(
+                String::from("LOG"),
+                String::from("debug"),
+            )]),
+            resources:   None,
+        }
+    }
+
+    #[test]
+    fn object_name_is_a_dns_label() {
+        assert_eq!(object_name("My_Step").unwrap(), "my-step");
+        assert_eq!(object_name("--a.b--").unwrap(), "a-b");
+        assert_eq!(object_name(&"x".repeat(70)).unwrap().len(), 63);
+        assert_eq!(
+            object_name(&format!("{}_y", "x".repeat(62))).unwrap(),
+            "x".repeat(62)
+        );
+    }
+
+    #[test]
+    fn object_name_fails_when_empty() {
+        assert!(object_name("").is_err());
+        assert!(object_name("__").is_err());
+        assert!(object_name("ß").is_err());
+    }
+
+    #[test]
+    fn streaming_step_is_a_deployment() {
+        let mut manifests = Manifests::new("pipe".into(), Some("ns"));
+        manifests.config_map("Step", "step", String::from("{}"));
+        manifests.workload("Step", "step", &kube(), Some("/app"), true);
+        manifests.service("Step", "step", &[8080]);
+        let objects = manifests.build().unwrap();
+
+        let files: Vec<_> = objects.iter().map(|(f, _)| f.as_str()).collect();
+        assert_eq!(
+            files,
+            [
+                "step-config-configmap.yaml",
+                "step-deployment.yaml",
+                "step-service.yaml"
+            ]
+        );
+
+        let config = &objects[0].1;
+        assert_eq!(config["data"]["config.json"], "{}");
+        assert_eq!(config["metadata"]["namespace"], "ns");
+
+        let deployment = &objects[1].1;
+        assert_eq!(deployment["apiVersion"], "apps/v1");
+        assert_eq!(
+            deployment["metadata"]["labels"]["app.kubernetes.io/part-of"],
+            "pipe"
+        );
+        let pod = &deployment["spec"]["template"]["spec"];
+        assert_eq!(pod["restartPolicy"], "Always");
+        assert_eq!(pod["volumes"][0]["configMap"]["name"], "step-config");
+        let container = &pod["containers"][0];
+        assert_eq!(
+            container["command"],
+            json!(["node", "/app/index.js", "/etc/nautirust/step/config.json"])
+        );
+        assert_eq!(
+            container["env"],
+            json!([{"name": "LOG", "value": "debug"}])
+        );
+        assert_eq!(container["volumeMounts"][0]["readOnly"], true);
+
+        let service = &objects[2].1;
+        assert_eq!(service["spec"]["ports"][0]["port"], 8080);
+    }
+
+    #[test]
+    fn finite_step_is_a_job() {
+        let mut manifests = Manifests::new("pipe".into(), None);
+        manifests.workload("step", "step", &kube(), None, false);
+        let objects = manifests.build().unwrap();
+
+        let job = &objects[0].1;
+        assert_eq!(job["kind"], "Job");
+        assert_eq!(job["apiVersion"], "batch/v1");
+        assert!(job["metadata"].get("namespace").is_none());
+        let pod = &job["spec"]["template"]["spec"];
+        assert_eq!(job["spec"]["backoffLimit"], 0);
+        assert_eq!(pod["restartPolicy"], "Never");
+        assert_eq!(pod["containers"][0]["command"][1], "/index.js");
+    }
+
+    #[test]
+    fn colliding_objects_fail() {
+        let object = json!({
+            "kind": "Service",
+            "metadata": { "name": "broker" },
+        });
+
+        let mut manifests = Manifests::new("pipe".into(), None);
+        manifests.add("channel 'a'", "channel-a-0.yaml".into(), object.clone());
+        manifests.add("channel 'b'", "channel-b-0.yaml".into(), object);
+        manifests.service("Broker", "broker", &[80]);
+        manifests.add(
+            "channel 'c'",
+            "channel-c-0.yaml".into(),
+            json!({ "kind": "ConfigMap", "metadata": { "name": "s-config" } }),
+        );
+        manifests.config_map("S", "s", String::from("{}"));
+        manifests.add(
+            "channel 'a_b'",
+            "channel-a-b-0.yaml".into(),
+            json!({ "kind": "Secret", "metadata": { "name": "x" } }),
+        );
+        manifests.add(
+            "channel 'a-b'",
+            "channel-a-b-0.yaml".into(),
+            json!({ "kind": "Secret", "metadata": { "name": "y" } }),
+        );
+
+        let errors = manifests.build().unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                "Service 'broker' is defined by both channel 'a' and channel \
+                 'b'",
+                "Service 'broker' is defined by both channel 'a' and step \
+                 'Broker'",
+                "ConfigMap 's-config' is defined by both channel 'c' and step \
+                 'S'",
+                "File 'channel-a-b-0.yaml' is defined by both channel 'a_b' \
+                 and channel 'a-b'",
+            ]
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use async_std::fs::{read_to_string, write};
+use serde::{Deserialize, Serialize};
+
+use crate::channel::Channel;
+use crate::exec::cache::hash_file;
+use crate::plan::Steps;
+use crate::runner::Runner;
+
+/// A step, runner or channel definition as the plan was generated against
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Locked {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The definition file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file:    Option<PathBuf>,
+    /// Sha256 of the content of `file`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash:    Option<String>,
+}
+
+impl Locked {
+    async fn new<V: Display>(version: Option<V>, file: Option<&Path>) -> Self {
+        let hash = match file {
+            Some(file) => hash_file(file).await,
+            None => None,
+        };
+
+        Self {
+            version: version.map(|v| v.to_string()),
+            file: file.map(Path::to_path_buf),
+            hash,
+        }
+    }
+}
+
+/// The steps, runners and channels a plan was generated against, kept next to
+/// the plan in `<plan>.lock`
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Lock {
+    #[serde(default)]
+    pub steps:    BTreeMap<String, Locked>,
+    #[serde(default)]
+    pub runners:  BTreeMap<String, Locked>,
+    #[serde(default)]
+    pub channels: BTreeMap<String, Locked>,
+}
+
+pub fn lock_path<P: AsRef<Path>>(plan: P) -> PathBuf {
+    plan.as_ref().with_extension("lock")
+}
+
+impl Lock {
+    /// Records the definitions `plan` uses, with the hashes of their files
+    pub async fn new(
+        plan: &Steps,
+        runners: &[Runner],
+        channels: &[Channel],
+    ) -> Self {
+        let mut lock = Lock::default();
+
+        for run in &plan.steps {
+            for step in run.steps() {
+                let locked =
+                    Locked::new(step.version.as_ref(), step.file.as_deref())
+                        .await;
+                lock.steps.insert(step.id.clone(), locked);
+
+                if let Some(runner) =
+                    runners.iter().find(|r| r.id == step.runner_id)
+                {
+                    let locked = Locked::new(
+                        runner.version.as_ref(),
+                        runner.file.as_deref(),
+                    )
+                    .await;
+                    lock.runners.insert(runner.id.clone(), locked);
+                }
+            }
+
+            for config in run.readers().chain(run.writers()) {
+                if let Some(channel) =
+                    channels.iter().find(|c| c.id == config.ty())
+                {
+                    let locked = Locked::new(
+                        channel.version.as_ref(),
+                        channel.file.as_deref(),
+                    )
+                    .await;
+                    lock.channels.insert(channel.id.clone(), locked);
+                }
+            }
+        }
+
+        lock
+    }
+
+    pub async fn load<P: AsRef<Path>>(plan: P) -> Option<Result<Self, String>> {
+        let content = read_to_string(lock_path(plan)).await.ok()?;
+        Some(serde_json::from_str(&content).map_err(|e| e.to_string()))
+    }
+
+    pub async fn save<P: AsRef<Path>>(&self, plan: P) -> std::io::Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        write(lock_path(plan), content).await
+    }
+
+    /// Describes what changed since the lockfile was written: the versions
+    /// and files of the runners and channels that are loaded now, and the
+    /// content of every locked file
+    pub async fn changes(
+        &self,
+        runners: &[Runner],
+        channels: &[Channel],
+    ) -> Vec<String> {
+        let mut out = Vec::new();
+
+        let runners = runners
+            .iter()
+            .map(|r| (r.id.as_str(), (r.version.as_ref(), r.file.as_deref())));
+        let channels = channels
+            .iter()
+            .map(|c| (c.id.as_str(), (c.version.as_ref(), c.file.as_deref())));
+        let loaded = [
+            ("runner", &self.runners, runners.collect::<BTreeMap<_, _>>()),
+            ("channel", &self.channels, channels.collect()),
+        ];
+
+        for (kind, locked, loaded) in &loaded {
+            for (id, locked) in locked.iter() {
+                let (version, file) = match loaded.get(id.as_str()) {
+                    Some(current) => *current,
+                    None => {
+                        out.push(format!("{} {} is not found", kind, id));
+                        continue;
+                    }
+                };
+
+                let version = version.map(|v| v.to_string());
+                if version != locked.version {
+                    out.push(format!(
+                        "{} {} is {}, the plan was generated against {}",
+                        kind,
+                        id,
+                        version.as_deref().unwrap_or("no version"),
+                        locked.version.as_deref().unwrap_or("no version")
+                    ));
+                }
+
+                if let (Some(file), Some(locked)) = (file, &locked.file) {
+                    if file != locked {
+                        out.push(format!(
+                            "{} {} is loaded from {}, the plan was generated \
+                             against {}",
+                            kind,
+                            id,
+                            file.display(),
+                            locked.display()
+                        ));
+                    }
+                }
+            }
+        }
+
+        let all = [
+            ("step", &self.steps),
+            ("runner", &self.runners),
+            ("channel", &self.channels),
+        ];
+        for (kind, locked) in all {
+            for (id, locked) in locked {
+                let (file, hash) = match (&locked.file, &locked.hash) {
+                    (Some(file), Some(hash)) => (file, hash),
+                    _ => continue,
+                };
+
+                match hash_file(file).await {
+                    Some(h) if &h == hash => {}
+                    Some(_) => {
+                        out.push(format!(
+                            "{} {}: {} changed",
+                            kind,
+                            id,
+                            file.display()
+                        ))
+                    }
+                    None => {
+                        out.push(format!(
+                            "{} {}: {} is removed",
+                            kind,
+                            id,
+                            file.display()
+                        ))
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Checks the runner requirements of the steps in a plan and compares the
+/// definitions with the lockfile, returns the errors and warnings. With
+/// `locked` a missing lockfile and every change are errors.
+pub async fn check_plan<P: AsRef<Path>>(
+    file: P,
+    plan: &Steps,
+    runners: &[Runner],
+    channels: &[Channel],
+    locked: bool,
+) -> (Vec<String>, Vec<String>) {
+    let mut errors: Vec<String> = plan
+        .steps
+        .iter()
+        .flat_map(|run| run.steps())
+        .filter_map(|step| step.find_runner(runners).err())
+        .collect();
+
+    let path = lock_path(&file);
+    let changes = match Lock::load(&file).await {
+        Some(Ok(lock)) => lock.changes(runners, channels).await,
+        Some(Err(e)) => {
+            vec![format!("Invalid lockfile {}: {}", path.display(), e)]
+        }
+        None if locked => vec![format!("No lockfile {} found", path.display())],
+        None => Vec::new(),
+    };
+
+    if locked {
+        errors.extend(changes);
+        (errors, Vec::new())
+    } else {
+        (errors, changes)
+    }
+}
@@ -0,0 +1,176 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_std::fs::File;
+use async_std::io::prelude::SeekExt;
+use async_std::io::{ReadExt, SeekFrom};
+
+use super::runs::{self, RunInfo};
+use crate::channel::Channel;
+use crate::runner::Runner;
+
+/// Show the logs of the steps of a run
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Only show the logs of this step
+    step:     Option<String>,
+    /// Keep printing new log lines while the run is going
+    #[clap(short, long)]
+    follow:   bool,
+    /// Run id, defaults to the most recent running pipeline
+    #[clap(long)]
+    run:      Option<String>,
+    /// Directory containing the run directories
+    #[clap(long, default_value = runs::DEFAULT_RUNS_DIR)]
+    runs_dir: String,
+}
+
+struct LogFile {
+    name:     String,
+    path:     PathBuf,
+    position: u64,
+    partial:  String,
+}
+
+impl LogFile {
+    /// Prints the complete lines written since the last call
+    async fn print_new_lines(&mut self, prefix: bool) {
+        let mut file = match File::open(&self.path).await {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+
+        if file.seek(SeekFrom::Start(self.position)).await.is_err() {
+            return;
+        }
+
+        let mut content = String::new();
+        if let Ok(n) = file.read_to_string(&mut content).await {
+            self.position += n as u64;
+        }
+
+        self.partial.push_str(&content);
+        while let Some(i) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=i).collect();
+            if prefix {
+                print!("{}: {}", self.name, line);
+            } else {
+                print!("{}", line);
+            }
+        }
+    }
+}
+
+/// The log files of the processes of `step`, or of all of them. A restarted
+/// step keeps appending to the same log file.
+fn log_files(info: &RunInfo, step: Option<&str>) -> Vec<LogFile> {
+    let mut files: Vec<LogFile> = Vec::new();
+    for proc in &info.processes {
+        if step.map(|s| s != proc.name).unwrap_or(false)
+            || files.iter().any(|f| f.path == proc.log)
+        {
+            continue;
+        }
+        files.push(LogFile {
+            name:     proc.name.clone(),
+            path:     proc.log.clone(),
+            position: 0,
+            partial:  String::new(),
+        });
+    }
+    files
+}
+
+impl Command {
+    pub(crate) async fn execute(
+        self,
+        _channels: Vec<Channel>,
+        _runners: Vec<Runner>,
+    ) {
+        let base = Path::new(&self.runs_dir);
+        let found = match self.run.as_deref() {
+            Some(id) => runs::find_run(base, id).await,
+            None => {
+                match runs::find_active_run(base, None).await {
+                    Some(x) => Some(x),
+                    None => runs::find_run(base, "latest").await,
+                }
+            }
+        };
+
+        let (path, mut info) = match found {
+            Some(x) => x,
+            None => {
+                eprintln!("No run found");
+                return;
+            }
+        };
+
+        let step = self.step.as_deref();
+        let prefix = step.is_none();
+        let mut files = log_files(&info, step);
+
+        if files.is_empty() && !self.follow {
+            eprintln!("No logs found for {}", step.unwrap_or(&info.id));
+            return;
+        }
+
+        loop {
+            for file in &mut files {
+                file.print_new_lines(prefix).await;
+            }
+
+            if !self.follow || info.status() != "running" {
+                break;
+            }
+
+            async_std::task::sleep(Duration::from_millis(250)).await;
+
+            // Pick up processes that started since the last check
+            if let Some(new_info) = RunInfo::load(&path).await {
+                info = new_info;
+                for new in log_files(&info, step) {
+                    if !files.iter().any(|f| f.path == new.path) {
+                        files.push(new);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::runs::ProcessInfo;
+
+    fn process(name: &str, started: u64) -> ProcessInfo {
+        serde_json::from_value(json!({
+            "name": name,
+            "kind": "step",
+            "runner": "JsRunner",
+            "config": format!("{}.json", name),
+            "log": format!("logs/{}.log", name),
+            "pid": null,
+            "started": started,
+            "finished": null,
+            "exitCode": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn restarted_steps_have_one_log_file() {
+        let mut info = RunInfo::new("plan.json", false, None);
+        info.processes =
+            vec![process("a", 1), process("b", 2), process("a", 3)];
+
+        let files = log_files(&info, None);
+        let names: Vec<_> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+
+        let files = log_files(&info, Some("a"));
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, PathBuf::from("logs/a.log"));
+    }
+}
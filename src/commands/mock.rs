@@ -0,0 +1,385 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::channel::{mkfifo, Channel, ChannelConfig};
+use crate::control::Client;
+use crate::runner::Runner;
+use crate::step::RunThing;
+
+/// Id of the built-in runner, it runs `nautirust mock`
+pub const MOCK_RUNNER: &str = "nautirust-mock";
+
+/// Channels the mock runner reads and writes, all of them have a `path`
+const MOCK_CHANNELS: [&str; 3] = ["file", "fifo", "unix-socket"];
+
+/// Serializations the mock runner accepts, it handles every message as a line
+/// of text
+const MOCK_SERIALIZATIONS: [&str; 8] = [
+    "json", "jsonld", "turtle", "ntriples", "nquads", "xml", "csv", "text",
+];
+
+/// The built-in mock runner, using the channels of `MOCK_CHANNELS` that exist
+pub fn runner(channels: &[Channel]) -> Option<Runner> {
+    let exe = std::env::current_exe().ok()?;
+    let can_use_channel = MOCK_CHANNELS
+        .iter()
+        .filter(|id| channels.iter().any(|c| &c.id == *id))
+        .collect::<Vec<_>>();
+
+    let runner = json!({
+        "id": MOCK_RUNNER,
+        "runnerScript": format!("exec '{}' mock {{config}}", exe.display()),
+        "canUseChannel": can_use_channel,
+        "canUseSerialization": MOCK_SERIALIZATIONS,
+        "requiredFields": [],
+    });
+    serde_json::from_value(runner).ok()
+}
+
+/// Adds the mock runner, a runner with the same id in `runners` takes
+/// precedence
+pub fn add_runner(runners: &mut Vec<Runner>, channels: &[Channel]) {
+    if !runners.iter().any(|r| r.id == MOCK_RUNNER) {
+        runners.extend(runner(channels));
+    }
+}
+
+/// What the mock does with a message
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+enum Transform {
+    #[default]
+    Echo,
+    Upper,
+    Lower,
+    Reverse,
+    /// Messages are read but not written
+    Drop,
+}
+
+impl Transform {
+    fn apply(self, line: &str) -> Option<String> {
+        match self {
+            Transform::Echo => Some(line.to_string()),
+            Transform::Upper => Some(line.to_uppercase()),
+            Transform::Lower => Some(line.to_lowercase()),
+            Transform::Reverse => Some(line.chars().rev().collect()),
+            Transform::Drop => None,
+        }
+    }
+}
+
+/// The `config` of a step run by the mock runner
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields, default)]
+struct Config {
+    /// Messages written before any message is read
+    emit:        Vec<String>,
+    transform:   Transform,
+    /// Milliseconds to wait before the step starts
+    start_delay: u64,
+    /// Milliseconds to wait for every message
+    delay:       u64,
+    /// Print every message that is written
+    log:         bool,
+    /// Exit with `crash_code` after this many messages, without closing the
+    /// writers
+    crash_after: Option<usize>,
+    crash_code:  Option<i32>,
+    exit_code:   i32,
+    /// Keep running after the readers are closed, until the step is stopped
+    keep_alive:  bool,
+}
+
+/// The path of a channel the mock runner can use
+fn channel_path(config: &ChannelConfig) -> Result<PathBuf, String> {
+    if !MOCK_CHANNELS.contains(&config.ty()) {
+        return Err(format!("Unsupported channel {}", config.ty()));
+    }
+    config
+        .config()
+        .get("path")
+        .and_then(|path| path.as_str())
+        .map(PathBuf::from)
+        .ok_or_else(|| format!("{} channel without a path", config.ty()))
+}
+
+/// Sends the lines of a channel to `lines`. Files are read once they exist,
+/// named pipes until their writer closes them and unix sockets are listened on
+/// until their writer disconnects.
+fn read(
+    config: &ChannelConfig,
+    lines: Sender<String>,
+) -> Result<JoinHandle<()>, String> {
+    let path = channel_path(config)?;
+    let ty = config.ty().to_string();
+    let listener = match ty.as_str() {
+        "fifo" => {
+            mkfifo(&path).map_err(|e| {
+                format!("Failed to create {}: {}", path.display(), e)
+            })?;
+            None
+        }
+        "unix-socket" => {
+            // A socket left by an earlier run refuses connections
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).map_err(|e| {
+                format!("Failed to listen on {}: {}", path.display(), e)
+            })?;
+            Some(listener)
+        }
+        _ => None,
+    };
+
+    Ok(thread::spawn(move || {
+        while ty == "file" && !path.exists() {
+            sleep(Duration::from_millis(100));
+        }
+
+        let input = match &listener {
+            Some(listener) => {
+                listener
+                    .accept()
+                    .map(|(stream, _)| Box::new(stream) as Box<dyn Read>)
+            }
+            None => {
+                File::open(&path).map(|file| Box::new(file) as Box<dyn Read>)
+            }
+        };
+        let input = match input {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("Failed to open {}: {}", path.display(), e);
+                return;
+            }
+        };
+        for line in BufReader::new(input).lines().map_while(Result::ok) {
+            if lines.send(line).is_err() {
+                break;
+            }
+        }
+    }))
+}
+
+/// Writes the lines received on `lines` to a channel, the channel is closed
+/// when all senders are dropped
+fn write(
+    config: &ChannelConfig,
+    lines: Receiver<String>,
+) -> Result<JoinHandle<()>, String> {
+    let path = channel_path(config)?;
+    let socket = config.ty() == "unix-socket";
+    if config.ty() == "fifo" {
+        mkfifo(&path).map_err(|e| {
+            format!("Failed to create {}: {}", path.display(), e)
+        })?;
+    }
+
+    Ok(thread::spawn(move || {
+        // Opening a named pipe blocks until it has a reader, a socket is
+        // connected to once its reader listens
+        let output = if socket {
+            loop {
+                if let Ok(stream) = UnixStream::connect(&path) {
+                    break Ok(Box::new(stream) as Box<dyn Write>);
+                }
+                sleep(Duration::from_millis(100));
+            }
+        } else {
+            File::create(&path).map(|file| Box::new(file) as Box<dyn Write>)
+        };
+        let mut output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!("Failed to open {}: {}", path.display(), e);
+                return;
+            }
+        };
+        for line in lines {
+            if let Err(e) =
+                writeln!(output, "{}", line).and_then(|_| output.flush())
+            {
+                eprintln!("Failed to write {}: {}", path.display(), e);
+                return;
+            }
+        }
+    }))
+}
+
+/// Runs a step of the built-in mock runner, used to test pipelines
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Configuration file of the step, as written by `run`
+    config: PathBuf,
+}
+
+impl Command {
+    pub(crate) async fn execute(self) {
+        let run: RunThing = match std::fs::read_to_string(&self.config)
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                serde_json::from_str(&content).map_err(|e| e.to_string())
+            }) {
+            Ok(run) => run,
+            Err(e) => {
+                eprintln!(
+                    "Failed to read the step config {}: {}",
+                    self.config.display(),
+                    e
+                );
+                std::process::exit(1);
+            }
+        };
+        let config: Config =
+            match serde_json::from_value(run.processor_config.config.clone()) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Invalid mock config: {}", e);
+                    std::process::exit(1);
+                }
+            };
+
+        std::process::exit(mock(&run, &config));
+    }
+}
+
+/// Runs the mock step, returning its exit code
+fn mock(run: &RunThing, config: &Config) -> i32 {
+    sleep(Duration::from_millis(config.start_delay));
+
+    let (lines, received) = channel();
+    let mut outputs = Vec::new();
+    let mut threads = Vec::new();
+    let started = run
+        .readers()
+        .map(|reader| read(reader, lines.clone()))
+        .chain(run.writers().map(|writer| {
+            let (output, lines) = channel();
+            outputs.push(output);
+            write(writer, lines)
+        }))
+        .collect::<Vec<_>>();
+    drop(lines);
+
+    for thread in started {
+        match thread {
+            Ok(thread) => threads.push(thread),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return 1;
+            }
+        }
+    }
+
+    let mut control = Client::from_env().and_then(Result::ok);
+    if let Some(control) = &mut control {
+        let _ = control.ready();
+    }
+
+    let mut count = 0;
+    let mut messages = config.emit.iter().cloned().chain(received);
+    loop {
+        if config.crash_after == Some(count) {
+            eprintln!("Crashing after {} messages", count);
+            return config.crash_code.unwrap_or(1);
+        }
+        let line = match messages.next() {
+            Some(line) => line,
+            None => break,
+        };
+        count += 1;
+
+        sleep(Duration::from_millis(config.delay));
+        if let Some(line) = config.transform.apply(&line) {
+            if config.log {
+                println!("{}", line);
+            }
+            for output in &outputs {
+                let _ = output.send(line.clone());
+            }
+        }
+    }
+
+    drop(outputs);
+    for thread in threads {
+        let _ = thread.join();
+    }
+
+    if let Some(control) = &mut control {
+        let _ = control.done(Some(&format!("{} messages", count)));
+    }
+    if config.keep_alive {
+        loop {
+            sleep(Duration::from_secs(1));
+        }
+    }
+
+    config.exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforms_a_line() {
+        assert_eq!(Transform::Echo.apply("Héllo"), Some("Héllo".into()));
+        assert_eq!(Transform::Upper.apply("Héllo"), Some("HÉLLO".into()));
+        assert_eq!(Transform::Lower.apply("Héllo"), Some("héllo".into()));
+        assert_eq!(Transform::Reverse.apply("Héllo"), Some("olléH".into()));
+        assert_eq!(Transform::Drop.apply("Héllo"), None);
+    }
+
+    #[test]
+    fn parses_the_config() {
+        let config: Config = serde_json::from_value(json!({
+            "emit": ["a", "b"],
+            "transform": "reverse",
+            "startDelay": 10,
+            "crashAfter": 2,
+            "crashCode": 7,
+            "keepAlive": true,
+        }))
+        .unwrap();
+        assert_eq!(config.emit, ["a", "b"]);
+        assert!(matches!(config.transform, Transform::Reverse));
+        assert_eq!(config.start_delay, 10);
+        assert_eq!(config.crash_after, Some(2));
+        assert_eq!(config.crash_code, Some(7));
+        assert_eq!(config.exit_code, 0);
+        assert!(config.keep_alive);
+    }
+
+    #[test]
+    fn defaults_to_echo() {
+        let config: Config = serde_json::from_value(json!({})).unwrap();
+        assert!(matches!(config.transform, Transform::Echo));
+        assert!(config.emit.is_empty());
+        assert_eq!(config.crash_after, None);
+        assert_eq!(config.crash_code, None);
+        assert_eq!(config.exit_code, 0);
+
+        let config: Config =
+            serde_json::from_value(json!({ "exitCode": 3 })).unwrap();
+        assert_eq!(config.exit_code, 3);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let error =
+            serde_json::from_value::<Config>(json!({ "crashafter": 2 }))
+                .unwrap_err();
+        assert!(error.to_string().contains("unknown field `crashafter`"));
+        assert!(serde_json::from_value::<Config>(
+            json!({ "transform": "shout" })
+        )
+        .is_err());
+    }
+}
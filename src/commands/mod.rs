@@ -1,3 +1,4 @@
+use std::env;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{Child, Stdio};
@@ -7,66 +8,108 @@ use clap::Subcommand;
 use jsonpath_rust::JsonPathQuery;
 use serde_json::Value;
 
+use self::hooks::Notifier;
 use crate::channel::Channel;
+pub(crate) use crate::exec::{expand_tilde, is_alive, signal_group};
+use crate::hooks::Hooks;
+use crate::registry::Kind;
 use crate::runner::Runner;
 
+pub mod cache;
+mod compose;
+mod dashboard;
 pub mod docker;
+pub mod down;
+pub mod export;
 pub mod generate;
+mod hooks;
+pub mod k8s;
+mod lock;
+pub mod logs;
+pub mod mock;
 pub mod prepare;
+pub mod registry;
+pub mod replay;
+pub mod restart;
 pub mod run;
+pub mod runs;
+pub mod status;
 pub mod stop;
+pub mod tap;
+pub mod test;
 pub mod validate;
+mod watch;
 
 #[derive(Subcommand, Debug)]
 pub enum Command {
     Generate(generate::Command),
     Run(run::Command),
     Docker(docker::Command),
+    K8s(k8s::Command),
+    Export(export::Command),
     Prepare(prepare::Command),
+    Runs(runs::Command),
+    Cache(cache::Command),
+    Status(status::Command),
+    Logs(logs::Command),
+    Down(down::Command),
+    Restart(restart::Command),
+    Tap(tap::Command),
+    Replay(replay::Command),
     Validate(validate::Command),
+    Test(test::Command),
+    #[clap(hide = true)]
+    Mock(mock::Command),
     Stop(stop::Command),
+    /// Install runners from a registry
+    Runner(registry::Command),
+    /// Install channels from a registry
+    Channel(registry::Command),
 }
 
 impl Command {
-    pub async fn execute(self, channels: Vec<Channel>, runners: Vec<Runner>) {
+    /// Executes the command, `hooks` are the ones of the orchestrator config
+    pub async fn execute(
+        self,
+        channels: Vec<Channel>,
+        runners: Vec<Runner>,
+        hooks: &Hooks,
+    ) {
         match self {
             Command::Generate(gen) => gen.execute(channels, runners).await,
-            Command::Run(run) => run.execute(channels, runners).await,
+            Command::Run(run) => run.execute(channels, runners, hooks).await,
             Command::Docker(docker) => docker.execute(channels, runners).await,
+            Command::K8s(k8s) => k8s.execute(channels, runners).await,
+            Command::Export(export) => export.execute(channels, runners).await,
             Command::Validate(validate) => {
                 validate.execute(channels, runners).await
             }
-            Command::Stop(stop) => stop.execute(channels, runners).await,
+            Command::Test(test) => test.execute(channels, runners).await,
+            Command::Mock(mock) => mock.execute().await,
+            Command::Stop(stop) => stop.execute(channels, runners, hooks).await,
+            Command::Runs(runs) => runs.execute(channels, runners).await,
+            Command::Cache(cache) => cache.execute(channels, runners).await,
+            Command::Status(status) => status.execute(channels, runners).await,
+            Command::Logs(logs) => logs.execute(channels, runners).await,
+            Command::Down(down) => down.execute(channels, runners, hooks).await,
+            Command::Restart(restart) => {
+                restart.execute(channels, runners).await
+            }
+            Command::Tap(tap) => tap.execute().await,
+            Command::Replay(replay) => replay.execute(channels, runners).await,
             Command::Prepare(prepare) => {
-                prepare.execute(channels, runners).await
+                prepare.execute(channels, runners, hooks).await
+            }
+            Command::Runner(registry) => {
+                registry.execute(Kind::Runner, channels, runners).await
+            }
+            Command::Channel(registry) => {
+                registry.execute(Kind::Channel, channels, runners).await
             }
         }
     }
 }
 
-fn expand_tilde<P: AsRef<Path>>(path_user_input: P) -> Option<PathBuf> {
-    let p = path_user_input.as_ref();
-    if !p.starts_with("~") {
-        return Some(p.to_path_buf());
-    }
-    if p == Path::new("~") {
-        return dirs::home_dir();
-    }
-
-    let p = p.strip_prefix("~/").unwrap();
-
-    dirs::home_dir().map(|mut h| {
-        if h == Path::new("/") {
-            // Corner case: `h` root directory;
-            // don't prepend extra `/`, just drop the tilde.
-            p.to_path_buf()
-        } else {
-            h.push(p);
-            h
-        }
-    })
-}
-
 fn get_used_channels<'a>(
     content: &'a str,
     channels: &'a [Channel],
@@ -91,6 +134,7 @@ pub struct OutputConfig {
     stderr: bool,
 }
 
+/// Starts a start, stop or build `script` with `sh`, printing its output
 fn start_subproc<Str: AsRef<str>, S: AsRef<Path>>(
     script: Str,
     location: Option<S>,
@@ -122,7 +166,7 @@ fn start_subproc<Str: AsRef<str>, S: AsRef<Path>>(
                 lines.push(line);
             }
         });
-        lines.into_iter().collect()
+        lines.into_iter().map(|line| line + "\n").collect()
     });
 
     let id2 = name.to_string();
@@ -135,21 +179,102 @@ fn start_subproc<Str: AsRef<str>, S: AsRef<Path>>(
                 lines.push(line);
             }
         });
-        lines.into_iter().collect()
+        lines.into_iter().map(|line| line + "\n").collect()
     });
 
     Some((child, h1, h2))
 }
 
+/// Prints what `start_subproc` would execute with the extra environment
+/// variables `env`, without spawning anything
+fn print_subproc<Str: AsRef<str>, S: AsRef<Path>>(
+    script: Str,
+    location: Option<S>,
+    env: &[(&str, String)],
+    name: &str,
+) {
+    let location = location.and_then(expand_tilde).unwrap_or_else(|| {
+        env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+    });
+
+    println!("\x1b[33mDRY\x1b[39m  {}", name);
+    println!("  command: sh -c {:?}", script.as_ref());
+    println!("  cwd:     {}", location.display());
+    for (key, value) in env {
+        println!("  env:     {}={}", key, value);
+    }
+}
+
 fn add_add_subproc<Str: AsRef<str>, S: AsRef<Path>>(
     script: &Option<Str>,
     location: Option<S>,
     procs: &mut Vec<(Child, JoinHandle<String>, JoinHandle<String>)>,
     id: &str,
     output: OutputConfig,
+    dry_run: bool,
 ) {
     if let Some(stop_script) = script {
-        let proc = start_subproc(stop_script, location, id, output);
-        procs.extend(proc);
+        if dry_run {
+            print_subproc(stop_script, location, &[], id);
+        } else {
+            let proc = start_subproc(stop_script, location, id, output);
+            procs.extend(proc);
+        }
+    }
+}
+
+/// A start, stop or build script started by `start_script`: the kind of its
+/// owner (channel, runner or step), the id of its owner and the process
+type Script<'a> = (
+    &'a str,
+    &'a str,
+    Child,
+    JoinHandle<String>,
+    JoinHandle<String>,
+);
+
+/// Starts `script` of the channel, runner or step `id`, firing the step start
+/// hooks
+fn start_script<'a, S: AsRef<Path>>(
+    script: &Option<String>,
+    location: Option<S>,
+    kind: &'a str,
+    id: &'a str,
+    dry_run: bool,
+    notifier: &mut Notifier,
+    scripts: &mut Vec<Script<'a>>,
+) {
+    let mut procs = Vec::new();
+    add_add_subproc(
+        script,
+        location,
+        &mut procs,
+        id,
+        OutputConfig::default(),
+        dry_run,
+    );
+    for (proc, h1, h2) in procs {
+        notifier.step_start(id, kind, proc.id());
+        scripts.push((kind, id, proc, h1, h2));
+    }
+}
+
+/// Waits for the scripts in the reverse order, firing the step exit hooks,
+/// returns the owners of the scripts that failed
+fn wait_scripts(
+    mut scripts: Vec<Script>,
+    notifier: &mut Notifier,
+) -> Vec<String> {
+    let mut failed = Vec::new();
+    while let Some((kind, id, mut proc, h1, h2)) = scripts.pop() {
+        let exit_code = proc.wait().ok().and_then(|status| status.code());
+        h1.join().unwrap();
+        h2.join().unwrap();
+
+        if exit_code != Some(0) {
+            failed.push(id.to_string());
+        }
+        notifier.step_exit(id, kind, exit_code, None);
     }
+    failed
 }
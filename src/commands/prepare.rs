@@ -1,41 +1,78 @@
+use std::path::Path;
+
 use async_std::fs::read_to_string;
 
-use super::run::Steps;
-use super::OutputConfig;
-use crate::channel::Channel;
+use super::hooks::Notifier;
+use crate::channel::{create_pipe, Channel};
+use crate::hooks::Hooks;
+use crate::plan::Steps;
 use crate::runner::Runner;
-use crate::step::{Step, RunThing};
+use crate::step::RunThing;
 
 /// Prepares the execution pipeline by starting the required channels/runner
 #[derive(clap::Args, Debug)]
 pub struct Command {
     /// Config file
-    file: String,
+    file:    String,
+    /// Run directory in which the fifo and unix-socket channels are created,
+    /// pass the same one to `run --tmp-dir`
+    #[clap(short, long)]
+    tmp_dir: Option<String>,
+    /// Print the scripts that would be executed, without executing them
+    #[clap(long)]
+    dry_run: bool,
+}
+
+/// Creates the `fifo` and `unix-socket` channels of `plan` in `run_dir`
+fn create_pipes(plan: &Steps, run_dir: &Path, dry_run: bool) {
+    let run_file = |name: &str| run_dir.join(name).display().to_string();
+    let pipes = match plan.pipes(&run_file) {
+        Ok(pipes) => pipes,
+        Err(errors) => {
+            eprintln!("Failed to create the channels.");
+            for e in errors {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
+    };
+    for (config, path) in pipes {
+        if dry_run {
+            println!("\x1b[33mDRY\x1b[39m  create {}", path.display());
+        } else if let Err(e) = create_pipe(config, &path) {
+            eprintln!("Failed to create channel {}: {}", path.display(), e);
+        }
+    }
 }
 
 impl Command {
-    pub async fn execute(self, channels: Vec<Channel>, runners: Vec<Runner>) {
-        let content = read_to_string(self.file).await.unwrap();
+    pub async fn execute(
+        self,
+        channels: Vec<Channel>,
+        runners: Vec<Runner>,
+        hooks: &Hooks,
+    ) {
+        let content = read_to_string(&self.file).await.unwrap();
         let values: Steps = serde_json::from_str(&content).unwrap();
+        let mut notifier =
+            Notifier::new(hooks, &values.hooks, "prepare", &self.file);
 
-        let mut procs = Vec::new();
-        let used_channels = super::get_used_channels(&content, &channels);
-        used_channels.for_each(
-            |Channel {
-                 start,
-                 location,
-                 id,
-                 ..
-             }| {
-                super::add_add_subproc(
-                    start,
-                    location.as_ref(),
-                    &mut procs,
-                    id,
-                    OutputConfig::default(),
-                )
-            },
-        );
+        if let Some(run_dir) = &self.tmp_dir {
+            create_pipes(&values, Path::new(run_dir), self.dry_run);
+        }
+
+        let mut scripts = Vec::new();
+        for channel in super::get_used_channels(&content, &channels) {
+            super::start_script(
+                &channel.start,
+                channel.location.as_ref(),
+                "channel",
+                &channel.id,
+                self.dry_run,
+                &mut notifier,
+                &mut scripts,
+            );
+        }
 
         let used_runners = runners.iter().filter(|runner| {
             values
@@ -43,51 +80,38 @@ impl Command {
                 .iter()
                 .any(|v| v.processor_config.runner_id == runner.id)
         });
+        for runner in used_runners {
+            super::start_script(
+                &runner.start,
+                runner.location.as_ref(),
+                "runner",
+                &runner.id,
+                self.dry_run,
+                &mut notifier,
+                &mut scripts,
+            );
+        }
 
-        used_runners.for_each(
-            |Runner {
-                 ref location,
-                 ref start,
-                 id,
-                 ..
-             }| {
-                super::add_add_subproc(
-                    start,
-                    location.as_ref(),
-                    &mut procs,
-                    id,
-                    OutputConfig::default(),
-                )
-            },
-        );
-
-        values.steps.iter().for_each(
-            |RunThing {
-                 processor_config:
-                     Step {
-                         build,
-                         location,
-                         id,
-                         ..
-                     },
-                 ..
-             }| {
-                super::add_add_subproc(
-                    build,
-                    location.as_ref(),
-                    &mut procs,
-                    id,
-                    OutputConfig::default(),
-                );
-            },
-        );
+        for RunThing {
+            processor_config: step,
+            ..
+        } in &values.steps
+        {
+            super::start_script(
+                &step.build,
+                step.location.as_ref(),
+                "step",
+                &step.id,
+                self.dry_run,
+                &mut notifier,
+                &mut scripts,
+            );
+        }
 
         // Stops the processors in the reverse order
-        while !procs.is_empty() {
-            let (mut proc, h1, h2) = procs.pop().unwrap();
-            proc.wait().unwrap();
-            h1.join().unwrap();
-            h2.join().unwrap();
+        let failed = super::wait_scripts(scripts, &mut notifier);
+        if !self.dry_run {
+            notifier.pipeline_end(failed, None);
         }
     }
 }
@@ -0,0 +1,193 @@
+use std::path::PathBuf;
+
+use clap::Subcommand;
+
+use crate::channel::{parse_channel, Channel};
+use crate::registry::{self, Kind, Registry};
+use crate::runner::{parse_runner, Runner};
+use crate::version::split_requirement;
+
+/// Install definitions from a registry into the local cache
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Registry directory or index file (also `file://` URLs)
+    #[clap(long, env = "NAUTIRUST_REGISTRY", global = true)]
+    registry: Option<String>,
+
+    #[clap(subcommand)]
+    action: Action,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// Install the most recent version from the registry, `id@requirement`
+    /// (`JsRunner@^1.2`) picks the most recent matching version
+    Add { id: String },
+    /// Remove an installed definition
+    Remove { id: String },
+    /// List the installed definitions, and the available ones when a registry
+    /// is given
+    List,
+}
+
+impl Command {
+    pub(crate) async fn execute(
+        self,
+        kind: Kind,
+        channels: Vec<Channel>,
+        runners: Vec<Runner>,
+    ) {
+        let cache = match registry::default_cache_dir() {
+            Some(cache) => cache,
+            None => {
+                eprintln!("No data directory found to install to");
+                return;
+            }
+        };
+
+        match self.action {
+            Action::Add { id } => {
+                let (id, req) = match split_requirement(&id) {
+                    Ok(split) => split,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                };
+                let location = match &self.registry {
+                    Some(location) => location,
+                    None => {
+                        eprintln!(
+                            "No registry given, use --registry or \
+                             NAUTIRUST_REGISTRY"
+                        );
+                        return;
+                    }
+                };
+
+                let installed = match Registry::load(location).await {
+                    Ok(registry) => {
+                        registry.install(kind, &id, req.as_ref(), &cache).await
+                    }
+                    Err(e) => Err(e),
+                };
+                let installed = match installed {
+                    Ok(installed) => installed,
+                    Err(e) => {
+                        eprintln!("Failed to install {} {}: {}", kind, id, e);
+                        return;
+                    }
+                };
+
+                let file = match registry::installed(&cache, kind)
+                    .await
+                    .into_iter()
+                    .find(|(i, _)| i.id == installed.id)
+                {
+                    Some((_, file)) => file,
+                    None => {
+                        eprintln!(
+                            "Failed to install {} {}: it is not in {} after \
+                             copying",
+                            kind,
+                            id,
+                            cache.display()
+                        );
+                        return;
+                    }
+                };
+                if let Err(e) = check(kind, file, &channels).await {
+                    let _ = registry::remove(&cache, kind, &id).await;
+                    eprintln!("Invalid {} {}: {}", kind, id, e);
+                    return;
+                }
+
+                println!(
+                    "Installed {} {} {} from {}",
+                    kind, installed.id, installed.version, installed.registry
+                );
+            }
+            Action::Remove { id } => {
+                match registry::remove(&cache, kind, &id).await {
+                    Ok(true) => println!("Removed {} {}", kind, id),
+                    Ok(false) => eprintln!("{} {} is not installed", kind, id),
+                    Err(e) => {
+                        eprintln!("Failed to remove {} {}: {}", kind, id, e)
+                    }
+                }
+            }
+            Action::List => {
+                // Local definitions take precedence over installed ones
+                let local = |id: &str| {
+                    let location = match kind {
+                        Kind::Runner => {
+                            runners
+                                .iter()
+                                .find(|r| r.id == id)
+                                .and_then(|r| r.location.clone())
+                        }
+                        Kind::Channel => {
+                            channels
+                                .iter()
+                                .find(|c| c.id == id)
+                                .and_then(|c| c.location.clone())
+                        }
+                    };
+                    location.map(|l| !l.starts_with(&cache)).unwrap_or_default()
+                };
+
+                for (installed, _) in registry::installed(&cache, kind).await {
+                    println!(
+                        "{:<24} {:<10} {}{}",
+                        installed.id,
+                        installed.version,
+                        installed.registry,
+                        if local(&installed.id) {
+                            " (overridden by a local definition)"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+
+                if let Some(location) = &self.registry {
+                    let registry = match Registry::load(location).await {
+                        Ok(registry) => registry,
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            return;
+                        }
+                    };
+
+                    println!("\nAvailable in {}:", location);
+                    for entry in registry.index.entries(kind) {
+                        println!("  {:<22} {}", entry.id, entry.version);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parses an installed definition, as it will be loaded later on
+async fn check(
+    kind: Kind,
+    file: PathBuf,
+    channels: &[Channel],
+) -> Result<(), String> {
+    match kind {
+        Kind::Runner => {
+            let runner = parse_runner(file).await.map_err(|e| e.to_string())?;
+            if let Err(e) = runner.check_channels(channels) {
+                for e in e {
+                    eprintln!("Warning: {}", e);
+                }
+            }
+        }
+        Kind::Channel => {
+            parse_channel(file).await.map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(())
+}
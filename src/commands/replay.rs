@@ -0,0 +1,775 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::Duration;
+
+use async_std::fs::{self, read_to_string};
+use async_std::future::timeout;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::run::{install_interrupt_handlers, interrupted, print_event};
+use super::runs;
+use super::tap::{
+    follow_file, forward, retry, with_config, with_suffix, ws_address, Framing,
+    Inspect, Tee,
+};
+use crate::channel::{create_pipe, remove_pipe, Channel, ChannelConfig};
+use crate::exec::{Event, Options, Pipeline};
+use crate::plan::{Steps, Target};
+use crate::runner::Runner;
+use crate::step::{RunThing, StepArgument};
+
+/// Directory in the run directory with the recorded messages, a file per link
+pub const RECORDS_DIR: &str = "records";
+
+/// Directory in the run directory in which `replay` runs the step
+pub const REPLAY_DIR: &str = "replay";
+
+/// A message recorded on a link, a line of its records file
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct Recorded {
+    /// Milliseconds since the epoch the message passed the recorder
+    pub(super) time:    u64,
+    pub(super) message: String,
+}
+
+/// The records file of `link` in `run_dir`
+pub(super) fn records_file(run_dir: &Path, link: &Target) -> PathBuf {
+    run_dir.join(RECORDS_DIR).join(format!("{}.jsonl", link))
+}
+
+/// Makes reading and writing `file` block again after it was opened without
+/// blocking
+pub(super) fn set_blocking(file: &File) -> std::io::Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0
+        || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) }
+            < 0
+    {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Opens the named pipe `path` to read what a writer writes to it, once one
+/// opened it. `None` when `stopping` before that.
+fn open_written(
+    path: &Path,
+    stopping: &AtomicBool,
+) -> std::io::Result<Option<File>> {
+    // Opening a named pipe blocks until the other end is opened, unless it is
+    // opened without blocking and polled
+    let pipe = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+    let mut poll = libc::pollfd {
+        fd:      pipe.as_raw_fd(),
+        events:  libc::POLLIN,
+        revents: 0,
+    };
+    while !stopping.load(Ordering::SeqCst) {
+        // Reports nothing until a writer opened the pipe
+        if unsafe { libc::poll(&mut poll, 1, 200) } > 0 {
+            set_blocking(&pipe)?;
+            return Ok(Some(pipe));
+        }
+    }
+    Ok(None)
+}
+
+/// Opens the named pipe `path` to write to its reader, once one opened it.
+/// `None` when `stopping` before that.
+fn open_reader(
+    path: &Path,
+    stopping: &AtomicBool,
+) -> std::io::Result<Option<File>> {
+    while !stopping.load(Ordering::SeqCst) {
+        // Opening it without blocking fails while there is no reader
+        match OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+        {
+            Ok(pipe) => {
+                set_blocking(&pipe)?;
+                return Ok(Some(pipe));
+            }
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                sleep(Duration::from_millis(200))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// Forwards the named pipe `rec` the writer writes to to the named pipe `path`
+/// of the reader, every time the writer opens it, until `stopping`
+fn record_fifo(
+    config: &ChannelConfig,
+    path: PathBuf,
+    rec: PathBuf,
+    inspect: Inspect,
+    stopping: Arc<AtomicBool>,
+) -> Result<JoinHandle<()>, String> {
+    for pipe in [&path, &rec] {
+        create_pipe(config, pipe).map_err(|e| {
+            format!("Failed to create {}: {}", pipe.display(), e)
+        })?;
+    }
+
+    Ok(thread::spawn(move || {
+        loop {
+            let result = open_written(&rec, &stopping).and_then(|from| {
+                let from = match from {
+                    Some(from) => from,
+                    None => return Ok(false),
+                };
+                let mut to = match open_reader(&path, &stopping)? {
+                    Some(to) => to,
+                    None => return Ok(false),
+                };
+                let mut framing = Framing::Lines(Vec::new());
+                forward(from, &mut to, &mut framing, &*inspect).map(|_| true)
+            });
+            match result {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(e) => {
+                    eprintln!("Failed to record {}: {}", rec.display(), e);
+                    return;
+                }
+            }
+        }
+    }))
+}
+
+/// Records the messages on links of a running pipeline: their writers write
+/// to a tee that also writes every message to the records file of the link
+pub(crate) struct Recorder {
+    /// Channel each recorded stream writer field writes to instead
+    redirects: Vec<(Target, ChannelConfig)>,
+    /// Named pipes and sockets of the tees
+    paths:     Vec<PathBuf>,
+    /// Tells the threads recording `file` and `fifo` channels to stop
+    stopping:  Arc<AtomicBool>,
+    /// Threads recording `file` and `fifo` channels
+    threads:   Vec<JoinHandle<()>>,
+}
+
+impl Recorder {
+    /// Starts recording `links` of `plan`, running in `run_dir`. `file`
+    /// channels are followed, `fifo`, `unix-socket` and `ws` ones get a tee.
+    pub(crate) fn start(
+        plan: &Steps,
+        links: &[Target],
+        run_dir: &Path,
+    ) -> Result<Self, String> {
+        let run_file = |name: &str| run_dir.join(name).display().to_string();
+
+        // Checked before anything is started
+        let configs = links
+            .iter()
+            .map(|link| {
+                let config = plan.writer_channel(link).ok_or_else(|| {
+                    format!("{} is no stream writer field", link)
+                })?;
+                match config.ty() {
+                    "file" | "fifo" | "unix-socket" | "ws" => {
+                        Ok((link, config))
+                    }
+                    ty => {
+                        Err(format!(
+                            "{} uses a {} channel, only file, fifo, \
+                             unix-socket and ws ones can be recorded",
+                            link, ty
+                        ))
+                    }
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if !configs.is_empty() {
+            let dir = run_dir.join(RECORDS_DIR);
+            std::fs::create_dir_all(&dir).map_err(|e| {
+                format!("Failed to create {}: {}", dir.display(), e)
+            })?;
+        }
+
+        let mut recorder = Self {
+            redirects: Vec::new(),
+            paths:     Vec::new(),
+            stopping:  Arc::new(AtomicBool::new(false)),
+            threads:   Vec::new(),
+        };
+        for (link, config) in configs {
+            let file = records_file(run_dir, link);
+            let records = File::create(&file).map_err(|e| {
+                format!("Failed to create {}: {}", file.display(), e)
+            })?;
+            let records = Mutex::new(records);
+            let inspect: Inspect = Arc::new(move |message| {
+                let recorded = Recorded {
+                    time:    runs::now_millis(),
+                    message: message.to_string(),
+                };
+                if let (Ok(mut records), Ok(line)) =
+                    (records.lock(), serde_json::to_string(&recorded))
+                {
+                    let _ = writeln!(records, "{}", line);
+                }
+            });
+
+            let path = || {
+                config.pipe_path(&run_file)?.ok_or_else(|| {
+                    format!("{} channel without a path", config.ty())
+                })
+            };
+            let redirect = match config.ty() {
+                "file" => {
+                    let (config, link) = (config.clone(), link.clone());
+                    let stopping = recorder.stopping.clone();
+                    let stop = move || stopping.load(Ordering::SeqCst);
+                    let thread = thread::spawn(move || {
+                        if let Err(e) =
+                            follow_file(&config, false, &stop, &*inspect)
+                        {
+                            eprintln!("Failed to record {}: {}", link, e);
+                        }
+                    });
+                    recorder.threads.push(thread);
+                    continue;
+                }
+                "fifo" => {
+                    let path = path()?;
+                    let rec = with_suffix(&path, ".rec");
+                    let stopping = recorder.stopping.clone();
+                    let thread = record_fifo(
+                        config,
+                        path,
+                        rec.clone(),
+                        inspect,
+                        stopping,
+                    )?;
+                    recorder.threads.push(thread);
+                    recorder.paths.push(rec.clone());
+                    with_config(config, "path", rec.display().to_string())
+                }
+                "unix-socket" => {
+                    let path = path()?;
+                    let rec = with_suffix(&path, ".rec");
+                    create_pipe(config, &rec).map_err(|e| {
+                        format!("Failed to create {}: {}", rec.display(), e)
+                    })?;
+                    let tee = Tee::unix_socket(config, path, rec, inspect)?;
+                    recorder.paths.extend(tee.path);
+                    tee.config
+                }
+                _ => Tee::ws(config, inspect)?.config,
+            };
+            recorder.redirects.push((link.clone(), redirect));
+        }
+        Ok(recorder)
+    }
+
+    /// Points the recorded fields of `run` to their tee
+    pub(crate) fn redirect(&self, run: &mut RunThing) {
+        for (link, config) in &self.redirects {
+            if link.step != run.processor_config.id {
+                continue;
+            }
+            if let Some(StepArgument::StreamWriter { fields }) =
+                run.args.get_mut(&link.arg)
+            {
+                if let Some(field) = fields.get_mut(&link.field) {
+                    *field = config.clone();
+                }
+            }
+        }
+    }
+
+    /// Points the recorded fields of the steps of `plan` to their tee
+    pub(crate) fn redirect_plan(&self, plan: &mut Steps) {
+        for run in &mut plan.steps {
+            self.redirect(run);
+        }
+    }
+
+    /// Stops and joins the recording threads once the writers are gone, then
+    /// removes the named pipes and sockets of the tees
+    pub(crate) fn stop(self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+        for path in &self.paths {
+            if let Err(e) = remove_pipe(path) {
+                eprintln!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Opens a `ws://` url as a WebSocket client
+fn ws_connect(url: &str) -> std::io::Result<TcpStream> {
+    let (address, path) = ws_address(url).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("unsupported url {}, only ws:// is", url),
+        )
+    })?;
+    let mut stream = retry(|| TcpStream::connect(&address))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: \
+         Upgrade\r\nSec-WebSocket-Key: bmF1dGlydXN0LXJlcGxheQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+        if path.is_empty() { "/" } else { &path },
+        address
+    )?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut status = String::new();
+    reader.read_line(&mut status)?;
+    if !status.contains(" 101 ") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            format!("handshake refused: {}", status.trim_end()),
+        ));
+    }
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    Ok(stream)
+}
+
+/// A masked WebSocket frame, as a client sends it
+fn ws_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(0x80 | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(0x80 | 126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+
+    let mask = (runs::now_millis() as u32).to_be_bytes();
+    frame.extend(mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+/// Writes `messages` to the channel `config` the step reads, at `path` for
+/// `fifo` and `unix-socket` channels, keeping the time between them when
+/// `realtime`. The channel is closed after the last message.
+fn feed(
+    config: ChannelConfig,
+    path: Option<PathBuf>,
+    messages: Vec<Recorded>,
+    realtime: bool,
+) -> JoinHandle<Result<usize, String>> {
+    thread::spawn(move || {
+        let ws = config.ty() == "ws";
+        let mut output: Box<dyn Write> = match (config.ty(), &path) {
+            ("fifo", Some(path)) => {
+                create_pipe(&config, path)
+                    .and_then(|_| OpenOptions::new().write(true).open(path))
+                    .map(|pipe| Box::new(pipe) as Box<dyn Write>)
+            }
+            ("unix-socket", Some(path)) => {
+                retry(|| UnixStream::connect(path))
+                    .map(|stream| Box::new(stream) as Box<dyn Write>)
+            }
+            ("ws", _) => {
+                let url = config.config().get("url").and_then(Value::as_str);
+                ws_connect(url.unwrap_or_default())
+                    .map(|stream| Box::new(stream) as Box<dyn Write>)
+            }
+            (ty, _) => return Err(format!("Can not feed a {} channel", ty)),
+        }
+        .map_err(|e| e.to_string())?;
+
+        let mut previous = None;
+        for recorded in &messages {
+            if let (true, Some(previous)) = (realtime, previous) {
+                sleep(Duration::from_millis(
+                    recorded.time.saturating_sub(previous),
+                ));
+            }
+            previous = Some(recorded.time);
+
+            let data = if ws {
+                ws_frame(0x1, recorded.message.as_bytes())
+            } else {
+                format!("{}\n", recorded.message).into_bytes()
+            };
+            output
+                .write_all(&data)
+                .and_then(|_| output.flush())
+                .map_err(|e| e.to_string())?;
+        }
+        if ws {
+            let _ = output.write_all(&ws_frame(0x8, &[]));
+        }
+        Ok(messages.len())
+    })
+}
+
+/// Feed the messages recorded with `run --record` on a link into the step
+/// reading it, running only that step
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Run id, a unique prefix of one or `latest`
+    run:      String,
+    /// Recorded link, as the stream writer field `<step>.<arg>.<field>`
+    #[clap(long)]
+    link:     Target,
+    /// Step to feed, when more than one step reads the link
+    #[clap(long)]
+    step:     Option<String>,
+    /// Keep the time between the messages as they were recorded
+    #[clap(long)]
+    realtime: bool,
+    /// Directory containing the run directories
+    #[clap(long, default_value = runs::DEFAULT_RUNS_DIR)]
+    runs_dir: String,
+}
+
+impl Command {
+    /// The recorded messages of the link
+    async fn messages(
+        &self,
+        run_dir: &Path,
+        id: &str,
+    ) -> Result<Vec<Recorded>, String> {
+        let file = records_file(run_dir, &self.link);
+        let content = read_to_string(&file).await.map_err(|_| {
+            format!(
+                "No messages of {} recorded in run {}, record them with `run \
+                 --record {}`",
+                self.link, id, self.link
+            )
+        })?;
+        content
+            .lines()
+            .map(|line| {
+                serde_json::from_str(line).map_err(|e| {
+                    format!("Failed to parse {}: {}", file.display(), e)
+                })
+            })
+            .collect()
+    }
+
+    /// The stream reader field of the step that reads the link
+    fn reader(
+        &self,
+        plan: &Steps,
+        config: &ChannelConfig,
+    ) -> Result<Target, String> {
+        let mut readers = Vec::new();
+        for run in &plan.steps {
+            for (arg, value) in &run.args {
+                let fields = match value {
+                    StepArgument::StreamReader { fields } => fields,
+                    _ => continue,
+                };
+                // Links are channels with the same type and configuration
+                readers.extend(
+                    fields
+                        .iter()
+                        .filter(|(_, c)| {
+                            c.ty() == config.ty()
+                                && c.config() == config.config()
+                        })
+                        .map(|(field, _)| {
+                            Target::new(&run.processor_config.id, arg, field)
+                        }),
+                );
+            }
+        }
+
+        if let Some(step) = &self.step {
+            readers.retain(|reader| &reader.step == step);
+        }
+        match readers.len() {
+            0 => {
+                Err(match &self.step {
+                    Some(step) => {
+                        format!("Step {} does not read {}", step, self.link)
+                    }
+                    None => format!("No step reads {}", self.link),
+                })
+            }
+            1 => Ok(readers.remove(0)),
+            _ => {
+                let steps = readers
+                    .iter()
+                    .map(|reader| reader.step.clone())
+                    .collect::<Vec<_>>();
+                Err(format!(
+                    "Steps {} read {}, choose one with --step",
+                    steps.join(", "),
+                    self.link
+                ))
+            }
+        }
+    }
+
+    pub(crate) async fn execute(
+        self,
+        _channels: Vec<Channel>,
+        runners: Vec<Runner>,
+    ) {
+        match self.replay(runners).await {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Runs the step reading the link, returns whether it succeeded
+    async fn replay(&self, runners: Vec<Runner>) -> Result<bool, String> {
+        let base = Path::new(&self.runs_dir);
+        let (run_dir, info) = runs::find_run(base, &self.run)
+            .await
+            .ok_or_else(|| format!("No run {} found", self.run))?;
+        // Runners are started in their own directory
+        let run_dir = run_dir.canonicalize().map_err(|e| e.to_string())?;
+
+        let file = run_dir.join("plan.json");
+        let content = read_to_string(&file)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let plan = Steps::from_json(&content).map_err(|e| {
+            format!("Failed to parse {}: {}", file.display(), e)
+        })?;
+        let config = plan.writer_channel(&self.link).ok_or_else(|| {
+            format!("{} is no stream writer field", self.link)
+        })?;
+        let messages = self.messages(&run_dir, &info.id).await?;
+        let reader = self.reader(&plan, config)?;
+
+        let dir = run_dir.join(REPLAY_DIR);
+        let _ = fs::remove_dir_all(&dir).await;
+        fs::create_dir_all(&dir).await.map_err(|e| {
+            format!("Failed to create {}: {}", dir.display(), e)
+        })?;
+
+        let mut run = plan
+            .steps
+            .iter()
+            .find(|run| run.processor_config.id == reader.step)
+            .cloned()
+            .ok_or_else(|| format!("No step {} in the plan", reader.step))?;
+        let count = messages.len();
+        let mut feeder = None;
+        if config.ty() == "file" {
+            // A file is written before the step starts, as a whole
+            let path = dir.join(format!("{}.in", self.link));
+            let content = messages
+                .iter()
+                .map(|recorded| format!("{}\n", recorded.message))
+                .collect::<String>();
+            fs::write(&path, content).await.map_err(|e| {
+                format!("Failed to write {}: {}", path.display(), e)
+            })?;
+            if let Some(StepArgument::StreamReader { fields }) =
+                run.args.get_mut(&reader.arg)
+            {
+                let field = fields.get_mut(&reader.field);
+                if let Some(field) = field {
+                    *field =
+                        with_config(field, "path", path.display().to_string());
+                }
+            }
+        } else {
+            let run_file = |name: &str| dir.join(name).display().to_string();
+            feeder = Some(feed(
+                config.clone(),
+                config.pipe_path(&run_file)?,
+                messages,
+                self.realtime,
+            ));
+        }
+
+        println!(
+            "Replaying {} messages of {} into {}",
+            count, self.link, reader
+        );
+        install_interrupt_handlers();
+        let plan = Steps {
+            steps: vec![run],
+            ..Steps::default()
+        };
+        let options = Options {
+            params: info.params.clone(),
+            ..Options::new(&dir)
+        };
+        let mut handle = Pipeline::start(plan, runners, options);
+        let events = handle.events().expect("the events are taken once");
+
+        let mut stopping = false;
+        loop {
+            if !stopping && interrupted() {
+                handle.stop();
+                stopping = true;
+            }
+            if let Some(done) = feeder.take_if(|f| f.is_finished()) {
+                match done.join() {
+                    Ok(Ok(n)) => {
+                        println!(
+                            "\x1b[32mINFO\x1b[39m replay: fed {} messages",
+                            n
+                        )
+                    }
+                    Ok(Err(e)) => eprintln!("Failed to feed {}: {}", reader, e),
+                    Err(_) => {}
+                }
+            }
+
+            match timeout(Duration::from_millis(100), events.recv()).await {
+                Ok(Ok(Event::Finished)) | Ok(Err(_)) => break,
+                Ok(Ok(event)) => print_event(&event),
+                Err(_) => {}
+            }
+        }
+
+        let exits = handle.wait().await;
+        println!("Run directory: {}", dir.display());
+        Ok(exits
+            .iter()
+            .all(|exit| exit.success() || exit.reason.is_some()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn collect() -> (Inspect, Arc<Mutex<Vec<String>>>) {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let collected = messages.clone();
+        let inspect: Inspect = Arc::new(move |message| {
+            collected.lock().unwrap().push(message.to_string());
+        });
+        (inspect, messages)
+    }
+
+    fn recorder(threads: Vec<JoinHandle<()>>) -> Recorder {
+        Recorder {
+            redirects: Vec::new(),
+            paths: Vec::new(),
+            stopping: Arc::new(AtomicBool::new(false)),
+            threads,
+        }
+    }
+
+    #[test]
+    fn stop_joins_a_fifo_thread_waiting_for_a_writer() {
+        let dir = TempDir::new("recorder").unwrap();
+        let (path, rec) = (dir.path().join("out"), dir.path().join("out.rec"));
+        let config = ChannelConfig::new(
+            "fifo".to_string(),
+            "text".to_string(),
+            json!({ "path": path }),
+        );
+
+        let (inspect, messages) = collect();
+        let mut recorder = recorder(Vec::new());
+        let thread = record_fifo(
+            &config,
+            path.clone(),
+            rec.clone(),
+            inspect,
+            recorder.stopping.clone(),
+        )
+        .unwrap();
+        recorder.threads.push(thread);
+
+        let reader = thread::spawn(move || std::fs::read_to_string(path));
+        std::fs::write(&rec, "hello\n").unwrap();
+        assert_eq!(reader.join().unwrap().unwrap(), "hello\n");
+
+        // The pipeline removes the pipes of the channels before the recorder
+        // stops, the thread waits for the next writer meanwhile
+        std::fs::remove_file(&rec).unwrap();
+        recorder.stop();
+        assert_eq!(*messages.lock().unwrap(), vec!["hello".to_string()]);
+    }
+
+    #[test]
+    fn stop_joins_a_fifo_thread_waiting_for_a_reader() {
+        let dir = TempDir::new("recorder").unwrap();
+        let (path, rec) = (dir.path().join("out"), dir.path().join("out.rec"));
+        let config = ChannelConfig::new(
+            "fifo".to_string(),
+            "text".to_string(),
+            json!({ "path": path }),
+        );
+
+        let (inspect, messages) = collect();
+        let mut recorder = recorder(Vec::new());
+        let thread = record_fifo(
+            &config,
+            path,
+            rec.clone(),
+            inspect,
+            recorder.stopping.clone(),
+        )
+        .unwrap();
+        recorder.threads.push(thread);
+
+        // The reader never opens its pipe
+        std::fs::write(&rec, "hello\n").unwrap();
+        sleep(Duration::from_millis(300));
+        recorder.stop();
+        assert!(messages.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stop_records_what_was_last_appended_to_a_file() {
+        let dir = TempDir::new("recorder").unwrap();
+        let path = dir.path().join("out");
+        std::fs::write(&path, "before\n").unwrap();
+        let config = ChannelConfig::new(
+            "file".to_string(),
+            "text".to_string(),
+            json!({ "path": path }),
+        );
+
+        let (inspect, messages) = collect();
+        let mut recorder = recorder(Vec::new());
+        let stopping = recorder.stopping.clone();
+        let thread = thread::spawn(move || {
+            let stop = || stopping.load(Ordering::SeqCst);
+            follow_file(&config, false, &stop, &*inspect).unwrap();
+        });
+        recorder.threads.push(thread);
+
+        sleep(Duration::from_millis(100));
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "last").unwrap();
+        recorder.stop();
+        assert_eq!(*messages.lock().unwrap(), vec!["last".to_string()]);
+    }
+}
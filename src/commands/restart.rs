@@ -0,0 +1,216 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use async_std::channel::Sender;
+use async_std::fs::{read_to_string, write};
+use async_std::io::prelude::BufReadExt;
+use async_std::io::WriteExt;
+use async_std::os::unix::net::UnixListener;
+use async_std::stream::StreamExt;
+use async_std::task::{self, JoinHandle};
+use serde::{Deserialize, Serialize};
+
+use super::lock::Lock;
+use super::runs;
+use crate::channel::Channel;
+use crate::plan::Steps;
+use crate::runner::Runner;
+use crate::step::{parse_step, RunThing};
+
+/// Socket in the run directory on which `run` takes requests
+pub const SUPERVISOR_SOCKET: &str = "supervisor.sock";
+
+/// Asked of the supervisor of a run over its socket, one JSON object per line
+/// answered with `ok` or an error
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub(crate) enum Request {
+    /// Restart a step with a new definition of it
+    Restart { run: RunThing },
+}
+
+/// Listens on the supervisor socket of `run_dir`, handing the requests to
+/// `requests`
+pub(crate) fn listen(
+    run_dir: &Path,
+    requests: Sender<Request>,
+) -> std::io::Result<JoinHandle<()>> {
+    let path = run_dir.join(SUPERVISOR_SOCKET);
+    let _ = std::fs::remove_file(&path);
+    let listener =
+        UnixListener::from(std::os::unix::net::UnixListener::bind(&path)?);
+
+    Ok(task::spawn(async move {
+        let mut incoming = listener.incoming();
+        while let Some(Ok(mut stream)) = incoming.next().await {
+            let mut line = String::new();
+            let mut reader = async_std::io::BufReader::new(&stream);
+            if reader.read_line(&mut line).await.is_err() {
+                continue;
+            }
+
+            let reply = match serde_json::from_str(&line) {
+                Ok(request) => {
+                    match requests.send(request).await {
+                        Ok(()) => String::from("ok"),
+                        Err(_) => String::from("the run is stopping"),
+                    }
+                }
+                Err(e) => format!("invalid request: {}", e),
+            };
+            let _ = stream.write_all(format!("{}\n", reply).as_bytes()).await;
+        }
+    }))
+}
+
+/// Sends `request` to the supervisor of the run in `run_dir`
+pub(crate) fn send(run_dir: &Path, request: &Request) -> Result<(), String> {
+    let mut stream = UnixStream::connect(run_dir.join(SUPERVISOR_SOCKET))
+        .map_err(|e| e.to_string())?;
+    let line = serde_json::to_string(request).map_err(|e| e.to_string())?;
+    writeln!(stream, "{}", line).map_err(|e| e.to_string())?;
+
+    let mut reply = String::new();
+    BufReader::new(stream)
+        .read_line(&mut reply)
+        .map_err(|e| e.to_string())?;
+    match reply.trim() {
+        "ok" => Ok(()),
+        "" => Err(String::from("no reply")),
+        e => Err(e.to_string()),
+    }
+}
+
+/// Restart a step of a running pipeline, with the current content of its step
+/// file
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Id of the step in the plan
+    step:     String,
+    /// Run id, defaults to the most recent running pipeline
+    #[clap(long)]
+    run:      Option<String>,
+    /// Step file to read, defaults to the one in the lockfile of the plan
+    #[clap(long)]
+    file:     Option<PathBuf>,
+    /// Directory containing the run directories
+    #[clap(long, default_value = runs::DEFAULT_RUNS_DIR)]
+    runs_dir: String,
+}
+
+impl Command {
+    /// The step file of the step, from the lockfile of `plan`
+    async fn step_file(&self, plan: &str) -> Result<PathBuf, String> {
+        if let Some(file) = &self.file {
+            return Ok(file.clone());
+        }
+
+        let lock = match Lock::load(plan).await {
+            Some(lock) => lock?,
+            None => {
+                return Err(format!(
+                    "No lockfile found for {}, pass the step file with --file",
+                    plan
+                ))
+            }
+        };
+        lock.steps
+            .get(&self.step)
+            .and_then(|locked| locked.file.clone())
+            .ok_or_else(|| {
+                format!(
+                    "The lockfile of {} has no file for step {}, pass it with \
+                     --file",
+                    plan, self.step
+                )
+            })
+    }
+
+    pub(crate) async fn execute(
+        self,
+        channels: Vec<Channel>,
+        runners: Vec<Runner>,
+    ) {
+        let base = Path::new(&self.runs_dir);
+        let (path, info) =
+            match runs::find_active_run(base, self.run.as_deref()).await {
+                Some(x) => x,
+                None => {
+                    eprintln!("No running pipeline found");
+                    return;
+                }
+            };
+        if info.status() != "running" {
+            eprintln!("Run {} is not running", info.id);
+            return;
+        }
+
+        let plan_file = path.join("plan.json");
+        let mut plan = match read_to_string(&plan_file).await {
+            Ok(content) => {
+                match Steps::from_json(&content) {
+                    Ok(plan) => plan,
+                    Err(e) => {
+                        eprintln!("Failed to parse the plan of the run: {}", e);
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to read the plan of the run: {}", e);
+                return;
+            }
+        };
+
+        if !plan
+            .steps
+            .iter()
+            .any(|run| run.processor_config.id == self.step)
+        {
+            eprintln!("No step {} in run {}", self.step, info.id);
+            return;
+        }
+
+        let file = match self.step_file(&info.plan).await {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return;
+            }
+        };
+        let mut step = match parse_step(&file).await {
+            Ok(step) => step,
+            Err(e) => {
+                eprintln!("Failed to parse step {}: {}", file.display(), e);
+                return;
+            }
+        };
+        step.id = self.step.clone();
+
+        let run = match plan.replace_step(&step, &runners, &channels) {
+            Ok(run) => run.clone(),
+            Err(errors) => {
+                eprintln!("Failed to update step {}.", self.step);
+                for e in errors {
+                    eprintln!("Error: {}", e);
+                }
+                return;
+            }
+        };
+
+        if let Err(e) = send(&path, &Request::Restart { run }) {
+            eprintln!(
+                "Failed to restart step {} of run {}: {}",
+                self.step, info.id, e
+            );
+            return;
+        }
+
+        // Later restarts and `down` use the new definition
+        if let Err(e) = write(&plan_file, plan.to_json()).await {
+            eprintln!("Failed to write the plan of the run: {}", e);
+        }
+        println!("Restarting step {} of run {}", self.step, info.id);
+    }
+}
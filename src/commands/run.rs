@@ -1,237 +1,515 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::Child;
-use std::thread::JoinHandle;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use async_recursion::async_recursion;
+use async_std::channel::unbounded;
 use async_std::fs::{self, read_to_string, write};
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use tempdir::TempDir;
-
-use super::OutputConfig;
+use async_std::future::timeout;
+use async_std::task;
+
+use super::cache;
+use super::dashboard::{Action, Dashboard};
+use super::hooks::Notifier;
+use super::lock::check_plan;
+use super::replay::Recorder;
+use super::restart::{self, Request};
+use super::runs::{self, ProcessInfo, RunInfo};
+use super::watch::Watcher;
 use crate::channel::Channel;
+use crate::control::{Message, CONTROL_ENV, STEP_ENV};
+use crate::exec::metrics::{self, Metrics};
+use crate::exec::{
+    render_config, runner_command, Event, Options, Params, Pipeline,
+    ProcessKind, Restart, Stream,
+};
+use crate::hooks::Hooks;
+use crate::plan::{Steps, Target};
 use crate::runner::Runner;
-use crate::step::{Output, Step, StepArgument, SubStep, RunThing};
-
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Steps {
-    #[serde(rename = "values")]
-    pub steps:  Vec<RunThing>,
-    pub params: Vec<String>,
-}
+use crate::step::{RunThing, StepArgument};
 
 /// Run a configured pipeline
 #[derive(clap::Args, Debug)]
 pub struct Command {
     /// Config file
-    file:    String,
-    /// temporary directory to put step configuration files
+    file:             String,
+    /// Directory to put step configuration files and logs (defaults to a new
+    /// run directory inside the runs directory)
     #[clap(short, long)]
-    tmp_dir: Option<String>,
+    tmp_dir:          Option<String>,
+    /// Directory containing the run directories
+    #[clap(long, default_value = runs::DEFAULT_RUNS_DIR)]
+    runs_dir:         String,
+    /// Print the processes that would be started, without starting them
+    #[clap(long)]
+    dry_run:          bool,
+    /// Run the pipeline in the background, see `status`, `logs` and `down`
+    #[clap(long)]
+    detach:           bool,
+    /// Always run sub-steps, ignoring and not updating the output cache
+    #[clap(long)]
+    no_cache:         bool,
+    /// Directory containing the cached sub-step outputs
+    #[clap(long, default_value = cache::DEFAULT_CACHE_DIR)]
+    cache_dir:        String,
+    /// Fail when the step, runner or channel files differ from the lockfile
+    /// of the plan
+    #[clap(long)]
+    locked:           bool,
+    /// Start steps again when they exit
+    #[clap(long, default_value = "no", possible_values = [
+        "no", "on-failure", "always",
+    ])]
+    restart:          String,
+    /// Seconds to wait before restarting a step
+    #[clap(long, default_value_t = 1)]
+    restart_sec:      u64,
+    /// Serve Prometheus metrics of the steps on this port of localhost, at
+    /// `/metrics`
+    #[clap(long)]
+    metrics_port:     Option<u16>,
+    /// Write the metrics of the steps to this file
+    #[clap(long)]
+    metrics_file:     Option<PathBuf>,
+    /// Seconds between samples of the CPU and memory usage of the steps
+    #[clap(long, default_value_t = 5)]
+    metrics_interval: u64,
+    /// Show a dashboard of the steps, channels and logs instead of the
+    /// interleaved output of the steps
+    #[clap(long, conflicts_with_all = &["detach", "dry-run"])]
+    tui:              bool,
+    /// Restart the steps whose plan, step or runner files change, start
+    /// added steps and stop removed ones
+    #[clap(long, conflicts_with = "dry-run")]
+    watch:            bool,
+    /// Record the messages on a link, the stream writer field
+    /// `<step>.<arg>.<field>`, in the run directory (see `replay`)
+    #[clap(long, value_name = "LINK")]
+    record:           Vec<Target>,
+    /// Id of the run, set when started with --detach
+    #[clap(long, hide = true)]
+    run_id:           Option<String>,
 }
 
-struct RunHandler<'a> {
-    tmp_dir:              PathBuf,
-    runners:              &'a Vec<Runner>,
-    sub_argument_outputs: HashMap<String, Value>,
-}
+/// Set by SIGINT and SIGTERM, the run then terminates its steps and stops
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
 
-impl<'a> RunHandler<'a> {
-    fn from(command: &Command, runners: &'a Vec<Runner>) -> Self {
-        let path = command
-            .tmp_dir
-            .as_ref()
-            .map(|l| Path::new(l).to_owned())
-            .unwrap_or_else(|| {
-                let tmp = TempDir::new("orchestrator").unwrap();
-                tmp.path().to_owned()
-            });
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
 
-        Self {
-            sub_argument_outputs: HashMap::default(),
-            runners,
-            tmp_dir: path,
-        }
+pub(crate) fn install_interrupt_handlers() {
+    let handler = on_interrupt as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
     }
+}
 
-    fn get_runner(&self, id: &str) -> &'a Runner {
-        self.runners.iter().find(|r| r.id == id).unwrap()
-    }
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
 
-    fn get_tmp_file(&self, id: &str) -> PathBuf {
-        self.tmp_dir.join(format!("{}.json", id))
+/// Starts this same command again as a new session in the background, writing
+/// its output to the run directory
+fn spawn_detached(command: &Command, run_dir: &Path, id: &str) -> Option<u32> {
+    let mut args: Vec<OsString> = env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--detach")
+        .collect();
+
+    args.push("--run-id".into());
+    args.push(id.into());
+    if command.tmp_dir.is_none() {
+        args.push("--tmp-dir".into());
+        args.push(run_dir.into());
     }
 
-    #[async_recursion]
-    async fn arg_to_value(
-        &mut self,
-        arg: StepArgument,
-        params: &Params,
-    ) -> Option<Value> {
-        match arg {
-            StepArgument::Step {
-                sub:
-                    SubStep {
-                        run,
-                        serialization,
-                        output,
-                    },
-            } => {
-                if let Some(value) =
-                    self.sub_argument_outputs.get(&run.processor_config.id)
-                {
-                    return value.clone().into();
-                }
+    let log = File::create(run_dir.join("supervisor.log")).ok()?;
+    let mut proc = std::process::Command::new(env::current_exe().ok()?);
+    proc.args(args)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone().ok()?)
+        .stderr(log);
+
+    unsafe {
+        proc.pre_exec(|| {
+            // Leave the terminal's session so the run outlives it, this also
+            // makes the supervisor the leader of the run's process group
+            libc::setsid();
+            Ok(())
+        });
+    }
 
-                let process_config_id = run.processor_config.id.clone();
-                let (mut child, stdout, stderr) = run_thing(
-                    run,
-                    self,
-                    OutputConfig {
-                        stdout: true,
-                        stderr: true,
-                    },
-                    params,
-                )
-                .await?;
-
-                child.wait().ok()?;
-
-                let stdout = stdout.join().ok()?;
-                let stderr = stderr.join().ok()?;
-
-                let (content, terminator) = match output {
-                    Output::Stdout => (stdout, ".stdout"),
-                    Output::Stderr => (stderr, ".stderr"),
-                };
+    proc.spawn().ok().map(|child| child.id())
+}
 
-                let path = self.get_tmp_file(&format!(
-                    "{}{}",
-                    process_config_id, terminator
-                ));
-                write(&path, content).await.ok()?;
+pub(crate) fn get_params(params: &[String]) -> Result<Params, Vec<String>> {
+    let config = config::Config::builder()
+        .build()
+        .map_err(|_| vec!["building config failed".to_string()])?;
 
-                let out = StepArgument::File {
-                    path: path.to_string_lossy().to_string(),
-                    serialization,
-                };
+    let mut out = HashMap::new();
+    let mut errors = Vec::new();
 
-                let value = serde_json::to_value(out).ok()?;
-                self.sub_argument_outputs
-                    .insert(process_config_id, value.clone());
-                value.into()
+    for p in params {
+        match config.get(p) {
+            Ok(v) => {
+                out.insert(p.to_string(), v);
             }
-            StepArgument::Param { name } => params.get(&name).cloned(),
-            arg => serde_json::to_value(arg).ok(),
+            Err(_) => errors.push(format!("Param {} not found", p)),
         }
     }
-}
 
-async fn run_thing(
-    run: RunThing,
-    handler: &mut RunHandler<'_>,
-    output: OutputConfig,
-    params: &Params,
-) -> Option<(Child, JoinHandle<String>, JoinHandle<String>)> {
-    #[derive(Serialize)]
-    struct SimpleRun<'a> {
-        #[serde(rename = "processorConfig")]
-        pub processor_config: &'a Step,
-        args:                 HashMap<String, Value>,
+    if errors.is_empty() {
+        Ok(out)
+    } else {
+        Err(errors)
     }
+}
 
-    let mut args = HashMap::new();
-    for (k, v) in run.args {
-        args.insert(k, handler.arg_to_value(v, params).await?);
+/// The steps and sub-steps in the order `run` starts them, nested sub-steps
+/// first and each sub-step once
+fn start_order<'a>(
+    run: &'a RunThing,
+    kind: ProcessKind,
+    seen: &mut HashSet<&'a str>,
+    out: &mut Vec<(&'a RunThing, ProcessKind)>,
+) {
+    for arg in run.args.values() {
+        if let StepArgument::Step { sub } = arg {
+            if seen.insert(&sub.run.processor_config.id) {
+                start_order(&sub.run, ProcessKind::SubStep, seen, out);
+            }
+        }
     }
+    out.push((run, kind));
+}
 
-    let name = &run.processor_config.id;
-    let runner = handler.get_runner(&run.processor_config.runner_id);
-
-    let run = SimpleRun {
-        processor_config: &run.processor_config,
-        args,
-    };
+/// Records a process in the run metadata, returns whether it changed
+fn record(info: &mut RunInfo, event: &Event) -> bool {
+    match event {
+        Event::Started {
+            step,
+            kind,
+            runner,
+            pid,
+            config,
+            log,
+        } => {
+            info.processes.push(ProcessInfo {
+                name:      step.clone(),
+                kind:      *kind,
+                runner:    runner.clone(),
+                config:    config.clone(),
+                log:       log.clone(),
+                pid:       Some(*pid),
+                started:   runs::now_millis(),
+                finished:  None,
+                exit_code: None,
+                reason:    None,
+                ready:     None,
+                progress:  None,
+                warnings:  Vec::new(),
+                metrics:   BTreeMap::new(),
+                summary:   None,
+            });
+            true
+        }
+        Event::Exited(exit) => {
+            let proc = info
+                .processes
+                .iter_mut()
+                .rev()
+                .find(|p| p.name == exit.step && p.finished.is_none());
+            if let Some(proc) = proc {
+                proc.finished = Some(runs::now_millis());
+                proc.exit_code = exit.exit_code;
+                proc.reason = exit.reason.clone();
+            }
+            true
+        }
+        Event::Control { step, message } => {
+            let proc =
+                match info.processes.iter_mut().rev().find(|p| &p.name == step)
+                {
+                    Some(proc) => proc,
+                    None => return false,
+                };
+            match message {
+                Message::Ready => proc.ready = Some(runs::now_millis()),
+                Message::Progress { percent, .. } => {
+                    proc.progress = Some(*percent)
+                }
+                Message::Warning { message } => {
+                    proc.warnings.push(message.clone())
+                }
+                Message::Metric { name, value } => {
+                    proc.metrics.insert(name.clone(), *value);
+                }
+                Message::Done { summary } => {
+                    proc.progress = Some(100.0);
+                    proc.summary =
+                        Some(summary.clone().unwrap_or_else(|| "done".into()));
+                }
+            }
+            true
+        }
+        _ => false,
+    }
+}
 
-    let config = serde_json::to_string_pretty(&run).ok()?;
-    run_value(config, handler.get_tmp_file(name), runner, name, output).await
+fn kind_name(kind: ProcessKind) -> String {
+    serde_json::to_value(kind)
+        .ok()
+        .and_then(|kind| kind.as_str().map(String::from))
+        .unwrap_or_default()
 }
 
-async fn run_value(
-    config: String,
-    file: PathBuf,
-    runner: &Runner,
-    name: &str,
-    output: OutputConfig,
-) -> Option<(Child, JoinHandle<String>, JoinHandle<String>)> {
-    write(file.clone(), config).await.unwrap();
-
-    let config_path = format!(
-        "'{}'",
-        file.canonicalize().expect("canonicalize path").display()
-    );
-
-    let current_dir = format!(
-        "'{}'",
-        env::current_dir()
-            .unwrap()
-            .canonicalize()
-            .expect("canonicalize path")
-            .display()
-    );
-
-    let command = runner
-        .script
-        .clone()
-        .replace("{config}", &config_path)
-        .replace("{cwd}", &current_dir);
-
-    super::start_subproc(command, runner.location.as_ref(), name, output)
+/// Fires the step hooks of an event, remembers the steps that failed to start
+/// in `errors` and the errors of the pipeline itself in `pipeline_errors`
+fn notify(
+    notifier: &mut Notifier,
+    event: &Event,
+    errors: &mut Vec<String>,
+    pipeline_errors: &mut Vec<String>,
+) {
+    match event {
+        Event::Started {
+            step, kind, pid, ..
+        } => notifier.step_start(step, &kind_name(*kind), *pid),
+        Event::Exited(exit) => {
+            notifier.step_exit(
+                &exit.step,
+                &kind_name(exit.kind),
+                exit.exit_code,
+                exit.reason.clone(),
+            )
+        }
+        Event::Error { step, .. } => errors.push(step.clone()),
+        Event::PipelineError { message } => {
+            pipeline_errors.push(message.clone())
+        }
+        _ => {}
+    }
 }
 
-type Params = HashMap<String, Value>;
+/// The steps that failed to start, did not exit successfully or were
+/// interrupted
+fn failed_steps(info: &RunInfo, mut errors: Vec<String>) -> Vec<String> {
+    let failed = info
+        .processes
+        .iter()
+        .filter(|p| !p.ok())
+        .map(|p| p.name.clone());
 
-fn get_params(params: &[String]) -> Result<Params, Vec<String>> {
-    let config = config::Config::builder()
-        .build()
-        .map_err(|_| vec!["building config failed".to_string()])?;
+    for name in failed {
+        if !errors.contains(&name) {
+            errors.push(name);
+        }
+    }
+    errors
+}
 
-    params
-        .iter()
-        .map(|p| {
-            config
-                .get(p)
-                .map(|x| (p, x))
-                .map_err(|_| format!("Param {} not found", p))
-        })
-        .fold(Ok(HashMap::new()), |acc, i| {
-            match (acc, i) {
-                (Ok(mut x), Ok((p, v))) => {
-                    x.insert(p.to_string(), v);
-                    Ok(x)
+pub(crate) fn print_event(event: &Event) {
+    match event {
+        Event::Log {
+            step,
+            stream: Stream::Stdout,
+            line,
+        } => println!("\x1b[32mINFO\x1b[39m {}: {}", step, line),
+        Event::Log {
+            step,
+            stream: Stream::Stderr,
+            line,
+        } => println!("\x1b[31mERRO\x1b[39m {}: {}", step, line),
+        Event::Cached { step } => {
+            println!("\x1b[32mINFO\x1b[39m {}: using cached output", step)
+        }
+        Event::Restarted { step, restarts } => {
+            println!(
+                "\x1b[33mWARN\x1b[39m {}: restarting ({} restarts)",
+                step, restarts
+            )
+        }
+        Event::Error { step, message } => {
+            eprintln!("Failed to start step {}: {}", step, message)
+        }
+        Event::PipelineError { message } => eprintln!("Error: {}", message),
+        Event::Control { step, message } => {
+            match message {
+                Message::Ready => {
+                    println!("\x1b[32mINFO\x1b[39m {}: ready", step)
+                }
+                Message::Progress { percent, message } => {
+                    println!(
+                        "\x1b[36mPROG\x1b[39m {}: {:.0}% {}",
+                        step,
+                        percent,
+                        message.as_deref().unwrap_or_default()
+                    )
+                }
+                Message::Warning { message } => {
+                    println!("\x1b[33mWARN\x1b[39m {}: {}", step, message)
+                }
+                Message::Metric { .. } => {}
+                Message::Done { summary: None } => {
+                    println!("\x1b[32mINFO\x1b[39m {}: done", step)
                 }
-                (Ok(_), Err(y)) => Err(vec![y]),
-                (Err(mut x), Err(y)) => {
-                    x.push(y);
-                    Err(x)
+                Message::Done {
+                    summary: Some(summary),
+                } => {
+                    println!("\x1b[32mINFO\x1b[39m {}: done: {}", step, summary)
                 }
-                (x, _) => x,
             }
-        })
+        }
+        _ => {}
+    }
 }
 
 impl Command {
+    fn run_dir(&self, info: &RunInfo) -> PathBuf {
+        let path = self
+            .tmp_dir
+            .as_ref()
+            .map(|l| Path::new(l).to_owned())
+            .unwrap_or_else(|| Path::new(&self.runs_dir).join(&info.id));
+        // Runners are started in their own directory, so paths handed to them
+        // have to be absolute
+        env::current_dir()
+            .map(|cwd| cwd.join(&path))
+            .unwrap_or(path)
+    }
+
+    /// Samples the metrics and writes them to the metrics file, nothing is
+    /// sampled when the metrics are neither served nor written
+    async fn sample_metrics(&self, metrics: &Mutex<Metrics>) {
+        if self.metrics_port.is_none() && self.metrics_file.is_none() {
+            return;
+        }
+        let content = match metrics.lock() {
+            Ok(mut metrics) => {
+                metrics.sample();
+                self.metrics_file.as_ref().map(|_| metrics.render())
+            }
+            Err(_) => return,
+        };
+
+        if let (Some(file), Some(content)) = (&self.metrics_file, content) {
+            if let Err(e) = write(file, content).await {
+                eprintln!(
+                    "Failed to write metrics to {}: {}",
+                    file.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Writes the step configurations and prints the processes that would be
+    /// started
+    async fn dry_run(
+        &self,
+        values: &Steps,
+        runners: &[Runner],
+        params: &Params,
+        run_dir: &Path,
+        info: &mut RunInfo,
+    ) {
+        let mut seen = HashSet::new();
+        let mut order = Vec::new();
+        for run in &values.steps {
+            start_order(run, ProcessKind::Step, &mut seen, &mut order);
+        }
+
+        for (run, kind) in order {
+            let step = &run.processor_config;
+            let runner = match runners.iter().find(|r| r.id == step.runner_id) {
+                Some(runner) => runner,
+                None => continue,
+            };
+            let config = match render_config(run, params, &|file| {
+                run_dir.join(file).display().to_string()
+            }) {
+                Some(config) => config,
+                None => {
+                    eprintln!(
+                        "Failed to render the config of step {}",
+                        step.id
+                    );
+                    continue;
+                }
+            };
+
+            let file = run_dir.join(format!("{}.json", step.id));
+            write(&file, config).await.unwrap();
+            super::print_subproc(
+                runner_command(runner, &file),
+                runner.location.as_ref(),
+                &[
+                    (STEP_ENV, step.id.clone()),
+                    (
+                        CONTROL_ENV,
+                        run_dir
+                            .join("control")
+                            .join(format!("{}.sock", step.id))
+                            .display()
+                            .to_string(),
+                    ),
+                ],
+                &step.id,
+            );
+            println!("  config:  {}", file.display());
+
+            info.processes.push(ProcessInfo {
+                name: step.id.clone(),
+                kind,
+                runner: runner.id.clone(),
+                config: file,
+                log: run_dir.join("logs").join(format!("{}.log", step.id)),
+                pid: None,
+                started: runs::now_millis(),
+                finished: None,
+                exit_code: None,
+                reason: None,
+                ready: None,
+                progress: None,
+                warnings: Vec::new(),
+                metrics: BTreeMap::new(),
+                summary: None,
+            });
+        }
+    }
+
     pub(crate) async fn execute(
         self,
-        _channels: Vec<Channel>,
+        channels: Vec<Channel>,
         runners: Vec<Runner>,
+        hooks: &Hooks,
     ) {
         let content = read_to_string(&self.file).await.unwrap();
         let values: Steps = serde_json::from_str(&content).unwrap();
 
+        let (errors, warnings) =
+            check_plan(&self.file, &values, &runners, &channels, self.locked)
+                .await;
+        for warning in warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        if !errors.is_empty() {
+            eprintln!("Failed to check the plan.");
+            for e in errors {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
+
         let params = match get_params(&values.params) {
             Ok(x) => x,
             Err(e) => {
@@ -243,27 +521,339 @@ impl Command {
             }
         };
 
-        let mut procs = Vec::new();
+        let mut info =
+            RunInfo::new(&self.file, self.dry_run, self.run_id.clone());
+        info.detached = self.run_id.is_some();
+        let run_dir = self.run_dir(&info);
+
+        // Steps get absolute pipe paths, whose length is limited for sockets
+        let absolute = env::current_dir()
+            .map(|cwd| cwd.join(&run_dir))
+            .unwrap_or_else(|_| run_dir.clone());
+        let run_file = |name: &str| absolute.join(name).display().to_string();
+        if let Err(errors) = values.pipes(&run_file) {
+            eprintln!("Failed to check the channels.");
+            for e in errors {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
+
+        fs::create_dir_all(run_dir.join("logs")).await.unwrap();
+
+        if self.detach && !self.dry_run {
+            match spawn_detached(&self, &run_dir, &info.id) {
+                Some(pid) => {
+                    println!(
+                        "Started run {} in the background (pid {})",
+                        info.id, pid
+                    );
+                    println!("Run directory: {}", run_dir.display());
+                }
+                None => eprintln!("Failed to start the run in the background"),
+            }
+            return;
+        }
+
+        write(run_dir.join("plan.json"), &content).await.unwrap();
+        info.params = params.clone();
+        let save = |info: &RunInfo| {
+            let info = info.clone();
+            let run_dir = run_dir.clone();
+            async move {
+                if let Err(e) = info.save(&run_dir).await {
+                    eprintln!("Failed to write run metadata: {}", e);
+                }
+            }
+        };
+        save(&info).await;
+
+        if self.dry_run {
+            self.dry_run(&values, &runners, &params, &run_dir, &mut info)
+                .await;
+            info.finished = Some(runs::now_millis());
+            save(&info).await;
+            println!("Run directory: {}", run_dir.display());
+            return;
+        }
 
-        let mut handler = RunHandler::from(&self, &runners);
-        fs::create_dir_all(&handler.tmp_dir).await.unwrap();
+        let recorder = match Recorder::start(&values, &self.record, &run_dir) {
+            Ok(recorder) => recorder,
+            Err(e) => {
+                eprintln!("Failed to record the links.");
+                eprintln!("Error: {}", e);
+                info.finished = Some(runs::now_millis());
+                save(&info).await;
+                return;
+            }
+        };
 
-        for value in values.steps {
-            let proc = run_thing(
-                value,
-                &mut handler,
-                OutputConfig::default(),
-                &params,
+        install_interrupt_handlers();
+
+        let mut notifier =
+            Notifier::new(hooks, &values.hooks, "run", &self.file);
+        notifier.set_run(&info.id, &run_dir);
+        let mut errors = Vec::new();
+        let mut pipeline_errors = Vec::new();
+
+        let options = Options {
+            run_dir:       run_dir.clone(),
+            params:        params.clone(),
+            cache_dir:     (!self.no_cache)
+                .then(|| self.cache_dir.clone().into()),
+            restart:       match self.restart.as_str() {
+                "on-failure" => Restart::OnFailure,
+                "always" => Restart::Always,
+                _ => Restart::Never,
+            },
+            restart_delay: Duration::from_secs(self.restart_sec),
+            keep_running:  self.watch,
+        };
+        let mut dashboard = None;
+        if self.tui {
+            let mut seen = HashSet::new();
+            let mut order = Vec::new();
+            for run in &values.steps {
+                start_order(run, ProcessKind::Step, &mut seen, &mut order);
+            }
+            let order = order
+                .into_iter()
+                .map(|(run, kind)| (run.processor_config.id.clone(), kind))
+                .collect();
+
+            match Dashboard::start(&values, order) {
+                Ok(d) => dashboard = Some(d),
+                Err(e) => eprintln!("Failed to start the dashboard: {}", e),
+            }
+        }
+
+        let mut watcher = None;
+        if self.watch {
+            let w = Watcher::new(&self.file, &runners).await;
+            if !w.has_step_files() {
+                eprintln!(
+                    "Warning: no lockfile with step files found for {}, only \
+                     the plan and runner files are watched",
+                    self.file
+                );
+            }
+            watcher = Some(w);
+        }
+        let mut watched = Instant::now();
+        // The plan as it is run now, after restarts and reloads
+        let mut plan = values.clone();
+
+        // Writers of recorded links write to the recorder, the plan of the run
+        // keeps their own channel
+        let mut values = values;
+        recorder.redirect_plan(&mut values);
+        let mut handle = Pipeline::start(values, runners, options);
+        let events = handle.events().expect("the events are taken once");
+
+        let metrics = Arc::new(Mutex::new(Metrics::new()));
+        let server = self.metrics_port.map(|port| {
+            let metrics = metrics.clone();
+            task::spawn(async move {
+                if let Err(e) = metrics::serve(metrics, port).await {
+                    eprintln!(
+                        "Failed to serve metrics on port {}: {}",
+                        port, e
+                    );
+                }
+            })
+        });
+        let (sender, requests) = unbounded();
+        let supervisor = match restart::listen(&run_dir, sender) {
+            Ok(task) => Some(task),
+            Err(e) => {
+                eprintln!("Failed to listen for restart requests: {}", e);
+                None
+            }
+        };
+        let interval = Duration::from_secs(self.metrics_interval.max(1));
+        let mut sampled = Instant::now();
+
+        let mut stopping = false;
+        loop {
+            if !stopping && interrupted() {
+                handle.stop();
+                stopping = true;
+            }
+
+            if let Some(dashboard) = &mut dashboard {
+                for action in dashboard.input() {
+                    match action {
+                        Action::Restart(step) => handle.restart_step(&step),
+                        Action::Stop(step) => handle.stop_step(&step),
+                        Action::Quit if !stopping => {
+                            handle.stop();
+                            stopping = true;
+                        }
+                        Action::Quit => {}
+                    }
+                }
+                let _ = dashboard.draw();
+            }
+
+            while let Ok(request) = requests.try_recv() {
+                match request {
+                    Request::Restart { run } => {
+                        let id = &run.processor_config.id;
+                        if let Some(old) = plan
+                            .steps
+                            .iter_mut()
+                            .find(|r| &r.processor_config.id == id)
+                        {
+                            *old = run.clone();
+                        }
+                        let mut run = run;
+                        recorder.redirect(&mut run);
+                        handle.replace_step(run)
+                    }
+                }
+            }
+
+            let poll = watched.elapsed() >= Duration::from_secs(1);
+            match &mut watcher {
+                Some(watcher) if poll && !stopping => {
+                    watched = Instant::now();
+                    match watcher.poll(&plan, &channels).await {
+                        Some(Ok(reload)) => {
+                            let changed = reload
+                                .changed
+                                .iter()
+                                .map(|f| f.display().to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ");
+                            match &mut dashboard {
+                                Some(dashboard) => {
+                                    dashboard.reload(&reload.plan);
+                                    dashboard.set_status(format!(
+                                        "{} changed, reloading",
+                                        changed
+                                    ));
+                                }
+                                None => {
+                                    println!(
+                                        "\x1b[32mINFO\x1b[39m {} changed, \
+                                         reloading",
+                                        changed
+                                    )
+                                }
+                            }
+
+                            plan = reload.plan.clone();
+                            write(run_dir.join("plan.json"), plan.to_json())
+                                .await
+                                .unwrap_or_else(|e| {
+                                    eprintln!(
+                                        "Failed to write the plan of the \
+                                         run: {}",
+                                        e
+                                    )
+                                });
+                            let mut values = reload.plan;
+                            recorder.redirect_plan(&mut values);
+                            handle.update(values, reload.runners);
+                        }
+                        Some(Err(errors)) => {
+                            match &mut dashboard {
+                                Some(dashboard) => {
+                                    dashboard.set_status(format!(
+                                        "Failed to reload the plan: {}",
+                                        errors.join(", ")
+                                    ))
+                                }
+                                None => {
+                                    eprintln!("Failed to reload the plan.");
+                                    for e in errors {
+                                        eprintln!("Error: {}", e);
+                                    }
+                                }
+                            }
+                        }
+                        None => {}
+                    }
+                }
+                _ => {}
+            }
+
+            if sampled.elapsed() >= interval {
+                sampled = Instant::now();
+                self.sample_metrics(&metrics).await;
+            }
+
+            let mut batch = match timeout(
+                Duration::from_millis(100),
+                events.recv(),
             )
             .await
-            .expect("");
-            procs.push(proc);
+            {
+                Ok(Ok(event)) => vec![event],
+                Ok(Err(_)) => break,
+                Err(_) => continue,
+            };
+            while let Ok(event) = events.try_recv() {
+                batch.push(event);
+            }
+
+            let mut changed = false;
+            let mut finished = false;
+            for event in batch {
+                match &mut dashboard {
+                    Some(dashboard) => dashboard.update(&event),
+                    None => print_event(&event),
+                }
+                notify(
+                    &mut notifier,
+                    &event,
+                    &mut errors,
+                    &mut pipeline_errors,
+                );
+                if let Ok(mut metrics) = metrics.lock() {
+                    metrics.record(&event);
+                }
+                changed |= record(&mut info, &event);
+                finished |= matches!(event, Event::Finished);
+            }
+            if changed {
+                save(&info).await;
+            }
+            if finished {
+                break;
+            }
+        }
+        handle.wait().await;
+        recorder.stop();
+        self.sample_metrics(&metrics).await;
+        if let Some(server) = server {
+            server.cancel().await;
+        }
+        if let Some(supervisor) = supervisor {
+            supervisor.cancel().await;
+            let _ =
+                fs::remove_file(run_dir.join(restart::SUPERVISOR_SOCKET)).await;
         }
 
-        for (mut proc, h1, h2) in procs {
-            proc.wait().unwrap();
-            h1.join().unwrap();
-            h2.join().unwrap();
+        info.finished = Some(runs::now_millis());
+        save(&info).await;
+
+        // Keep the dashboard open until it is closed
+        if let Some(mut dashboard) = dashboard {
+            dashboard.finish();
+            while !dashboard.input().iter().any(|a| matches!(a, Action::Quit)) {
+                if interrupted() || dashboard.draw().is_err() {
+                    break;
+                }
+                task::sleep(Duration::from_millis(50)).await;
+            }
         }
+
+        notifier.pipeline_end(
+            failed_steps(&info, errors),
+            (!pipeline_errors.is_empty()).then(|| pipeline_errors.join("; ")),
+        );
+        runs::print_summary(&info);
+        println!("Run directory: {}", run_dir.display());
     }
 }
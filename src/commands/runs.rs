@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_std::fs::{self, read_to_string, write};
+use async_std::stream::StreamExt;
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::channel::Channel;
+use crate::exec::ProcessKind;
+use crate::runner::Runner;
+
+/// Default location of the run directories, relative to the working directory
+pub const DEFAULT_RUNS_DIR: &str = ".nautirust/runs";
+const RUN_FILE: &str = "run.json";
+
+/// Milliseconds since the unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Formats unix seconds as `YYYYMMDD-HHMMSS` (UTC)
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem / 60) % 60,
+        rem % 60
+    )
+}
+
+/// Bookkeeping of a single process started during a run
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessInfo {
+    pub name:      String,
+    pub kind:      ProcessKind,
+    pub runner:    String,
+    pub config:    PathBuf,
+    pub log:       PathBuf,
+    pub pid:       Option<u32>,
+    pub started:   u64,
+    pub finished:  Option<u64>,
+    pub exit_code: Option<i32>,
+    /// Why the process stopped, when it did not exit by itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason:    Option<String>,
+    /// When the step sent `ready` on its control channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ready:     Option<u64>,
+    /// Last progress the step reported, in percent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress:  Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings:  Vec<String>,
+    /// Last value of the metrics the step reported
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics:   BTreeMap<String, f64>,
+    /// Sent by the step with `done`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary:   Option<String>,
+}
+
+impl ProcessInfo {
+    /// Whether the process exited successfully or was stopped or restarted
+    /// on request
+    pub fn ok(&self) -> bool {
+        match self.reason.as_deref() {
+            Some("stopped" | "restarted") => true,
+            Some(_) => false,
+            None => self.exit_code == Some(0),
+        }
+    }
+
+    pub fn duration(&self) -> Option<u64> {
+        self.finished.map(|f| f.saturating_sub(self.started))
+    }
+}
+
+/// Contents of `run.json`, describing one execution of a plan
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RunInfo {
+    pub id:         String,
+    pub plan:       String,
+    #[serde(default)]
+    pub dry_run:    bool,
+    /// Pid of the nautirust process supervising this run
+    pub supervisor: Option<u32>,
+    #[serde(default)]
+    pub detached:   bool,
+    /// Set when the run was brought down with `nautirust down`
+    #[serde(default)]
+    pub stopped:    bool,
+    pub started:    u64,
+    pub finished:   Option<u64>,
+    pub params:     HashMap<String, Value>,
+    pub processes:  Vec<ProcessInfo>,
+}
+
+impl RunInfo {
+    pub fn new(plan: &str, dry_run: bool, id: Option<String>) -> Self {
+        let started = now_millis();
+        let id = id.unwrap_or_else(|| {
+            format!(
+                "{}-{:04x}",
+                format_timestamp(started / 1000),
+                (started as u32 ^ std::process::id()) & 0xffff
+            )
+        });
+
+        Self {
+            id,
+            plan: plan.to_string(),
+            dry_run,
+            supervisor: Some(std::process::id()),
+            detached: false,
+            stopped: false,
+            started,
+            finished: None,
+            params: HashMap::new(),
+            processes: Vec::new(),
+        }
+    }
+
+    pub fn status(&self) -> &'static str {
+        if self.dry_run {
+            "dry-run"
+        } else if self.stopped {
+            "stopped"
+        } else if self.finished.is_none() {
+            match self.supervisor {
+                Some(pid) if !super::is_alive(pid) => "crashed",
+                _ => "running",
+            }
+        } else if self.processes.iter().all(ProcessInfo::ok) {
+            "ok"
+        } else {
+            "failed"
+        }
+    }
+
+    pub async fn save(&self, dir: &Path) -> std::io::Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        write(dir.join(RUN_FILE), content).await
+    }
+
+    pub async fn load(dir: &Path) -> Option<Self> {
+        let content = read_to_string(dir.join(RUN_FILE)).await.ok()?;
+        serde_json::from_str(&content).ok()
+    }
+}
+
+/// Returns all run directories in `base` with their metadata, oldest first
+pub async fn list_runs(base: &Path) -> Vec<(PathBuf, RunInfo)> {
+    let mut out = Vec::new();
+    let mut entries = match fs::read_dir(base).await {
+        Ok(entries) => entries,
+        Err(_) => return out,
+    };
+
+    while let Some(Ok(entry)) = entries.next().await {
+        let path: PathBuf = entry.path().into();
+        if let Some(info) = RunInfo::load(&path).await {
+            out.push((path, info));
+        }
+    }
+
+    out.sort_by(|(_, a), (_, b)| a.id.cmp(&b.id));
+    out
+}
+
+/// Finds a run by (a prefix of) its id, `latest` selects the most recent run
+pub async fn find_run(base: &Path, id: &str) -> Option<(PathBuf, RunInfo)> {
+    let runs = list_runs(base).await;
+    if id == "latest" {
+        return runs.into_iter().next_back();
+    }
+
+    let mut found =
+        runs.into_iter().filter(|(_, info)| info.id.starts_with(id));
+    let first = found.next();
+    if found.next().is_some() {
+        eprintln!("Run id '{}' is ambiguous", id);
+        return None;
+    }
+    first
+}
+
+/// Finds the run a command should act on: the given id, otherwise the most
+/// recent run that is still running
+pub async fn find_active_run(
+    base: &Path,
+    id: Option<&str>,
+) -> Option<(PathBuf, RunInfo)> {
+    if let Some(id) = id {
+        return find_run(base, id).await;
+    }
+
+    list_runs(base)
+        .await
+        .into_iter()
+        .rfind(|(_, info)| info.status() == "running")
+}
+
+pub fn format_duration(millis: Option<u64>) -> String {
+    millis
+        .map(|m| format!("{}.{:03}s", m / 1000, m % 1000))
+        .unwrap_or_else(|| String::from("-"))
+}
+
+/// Prints one line per process with its exit code, duration and, if it did
+/// not exit by itself, the reason it stopped
+pub fn print_processes(info: &RunInfo) {
+    for proc in &info.processes {
+        let code = proc
+            .exit_code
+            .map(|c| c.to_string())
+            .unwrap_or_else(|| String::from("-"));
+        println!(
+            "  {:<24} {:<8} pid {:<8} exit {:<4} {:<10} {}",
+            proc.name,
+            if proc.kind == ProcessKind::Step {
+                "step"
+            } else {
+                "sub-step"
+            },
+            proc.pid.map(|p| p.to_string()).unwrap_or_default(),
+            code,
+            format_duration(proc.duration()),
+            proc.reason.as_deref().unwrap_or_default()
+        );
+
+        // What the step reported on its control channel
+        if let Some(summary) = &proc.summary {
+            println!("      done: {}", summary);
+        } else if let Some(progress) = proc.progress {
+            println!("      progress: {:.0}%", progress);
+        }
+        for (name, value) in &proc.metrics {
+            println!("      {}: {}", name, value);
+        }
+        for warning in &proc.warnings {
+            println!("      \x1b[33mwarning\x1b[39m: {}", warning);
+        }
+    }
+}
+
+/// Printed when a run ends
+pub fn print_summary(info: &RunInfo) {
+    println!("Run {} {}", info.id, info.status());
+    print_processes(info);
+}
+
+/// Manage the run directories of previous runs
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Directory containing the run directories
+    #[clap(long, default_value = DEFAULT_RUNS_DIR)]
+    runs_dir: String,
+
+    #[clap(subcommand)]
+    action: Action,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// List all recorded runs
+    List,
+    /// Show the details of a run
+    Show {
+        /// Run id (or a unique prefix), 'latest' for the most recent run
+        #[clap(default_value = "latest")]
+        id: String,
+    },
+    /// Remove run directories of finished runs
+    Clean {
+        /// Keep this many most recent runs
+        #[clap(short, long, default_value_t = 0)]
+        keep: usize,
+    },
+}
+
+impl Command {
+    pub(crate) async fn execute(
+        self,
+        _channels: Vec<Channel>,
+        _runners: Vec<Runner>,
+    ) {
+        let base = Path::new(&self.runs_dir);
+
+        match self.action {
+            Action::List => {
+                for (_, info) in list_runs(base).await {
+                    println!(
+                        "{}  {:<8} {:>3} processes  {}",
+                        info.id,
+                        info.status(),
+                        info.processes.len(),
+                        info.plan
+                    );
+                }
+            }
+            Action::Show { id } => {
+                let (path, info) = match find_run(base, &id).await {
+                    Some(x) => x,
+                    None => {
+                        eprintln!("No run found for '{}'", id);
+                        return;
+                    }
+                };
+
+                println!("Run:       {}", info.id);
+                println!("Directory: {}", path.display());
+                println!("Plan:      {}", info.plan);
+                println!("Status:    {}", info.status());
+                println!(
+                    "Duration:  {}",
+                    format_duration(
+                        info.finished.map(|f| f.saturating_sub(info.started))
+                    )
+                );
+
+                print_processes(&info);
+            }
+            Action::Clean { keep } => {
+                let runs = list_runs(base).await;
+                let removable = runs.len().saturating_sub(keep);
+
+                for (path, info) in runs.into_iter().take(removable) {
+                    if info.status() == "running" {
+                        eprintln!("Skipping running run {}", info.id);
+                        continue;
+                    }
+
+                    match fs::remove_dir_all(&path).await {
+                        Ok(_) => println!("Removed {}", info.id),
+                        Err(e) => {
+                            eprintln!("Failed to remove {}: {}", info.id, e)
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
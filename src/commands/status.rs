@@ -0,0 +1,74 @@
+use std::path::Path;
+
+use super::runs;
+use crate::channel::Channel;
+use crate::exec::ProcessKind;
+use crate::runner::Runner;
+
+/// Show the state of a (running) pipeline
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Run id, defaults to the most recent running pipeline
+    run:      Option<String>,
+    /// Directory containing the run directories
+    #[clap(long, default_value = runs::DEFAULT_RUNS_DIR)]
+    runs_dir: String,
+}
+
+impl Command {
+    pub(crate) async fn execute(
+        self,
+        _channels: Vec<Channel>,
+        _runners: Vec<Runner>,
+    ) {
+        let base = Path::new(&self.runs_dir);
+        let (path, info) =
+            match runs::find_active_run(base, self.run.as_deref()).await {
+                Some(x) => x,
+                None => {
+                    eprintln!("No running pipeline found");
+                    return;
+                }
+            };
+
+        println!("Run:       {}", info.id);
+        println!("Directory: {}", path.display());
+        println!("Status:    {}", info.status());
+        if let Some(pid) = info.supervisor {
+            println!(
+                "Supervisor: pid {}{}",
+                pid,
+                if info.detached { " (detached)" } else { "" }
+            );
+        }
+
+        let now = runs::now_millis();
+        for proc in info
+            .processes
+            .iter()
+            .filter(|proc| proc.kind == ProcessKind::Step)
+        {
+            let state = match (proc.exit_code, proc.finished, proc.pid) {
+                (Some(code), _, _) => format!("exited {}", code),
+                (None, Some(_), _) => {
+                    proc.reason.clone().unwrap_or_else(|| "killed".into())
+                }
+                (None, None, Some(pid)) if super::is_alive(pid) => {
+                    String::from("running")
+                }
+                _ => String::from("gone"),
+            };
+
+            let uptime =
+                proc.finished.unwrap_or(now).saturating_sub(proc.started);
+
+            println!(
+                "  {:<24} {:<10} pid {:<8} {}",
+                proc.name,
+                state,
+                proc.pid.map(|p| p.to_string()).unwrap_or_default(),
+                runs::format_duration(Some(uptime))
+            );
+        }
+    }
+}
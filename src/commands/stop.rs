@@ -1,69 +1,123 @@
+use std::path::Path;
+
 use async_std::fs::read_to_string;
 
-use super::run::Steps;
-use super::OutputConfig;
-use crate::channel::Channel;
+use super::hooks::Notifier;
+use crate::channel::{remove_pipe, Channel, PIPES_DIR};
+use crate::hooks::Hooks;
+use crate::plan::Steps;
 use crate::runner::Runner;
 
 /// Gracefully stop the runners and channels specified in the config
 #[derive(clap::Args, Debug)]
 pub struct Command {
     /// Config file
-    file: String,
+    file:    String,
+    /// Run directory from which the fifo and unix-socket channels are removed
+    #[clap(short, long)]
+    tmp_dir: Option<String>,
+    /// Print the scripts that would be executed, without executing them
+    #[clap(long)]
+    dry_run: bool,
 }
 
 impl Command {
-    pub async fn execute(self, _channels: Vec<Channel>, runners: Vec<Runner>) {
-        let content = read_to_string(self.file).await.unwrap();
-        let values: Steps = serde_json::from_str(&content).unwrap();
+    pub async fn execute(
+        self,
+        channels: Vec<Channel>,
+        runners: Vec<Runner>,
+        hooks: &Hooks,
+    ) {
+        let content = read_to_string(&self.file).await.unwrap();
+        stop_pipeline(
+            &self.file,
+            &content,
+            self.tmp_dir.as_ref().map(Path::new),
+            &channels,
+            &runners,
+            hooks,
+            self.dry_run,
+        );
+    }
+}
+
+/// Removes the `fifo` and `unix-socket` channels of `plan` from `run_dir`
+fn remove_pipes(plan: &Steps, run_dir: &Path, dry_run: bool) {
+    let run_file = |name: &str| run_dir.join(name).display().to_string();
+    let pipes = match plan.pipes(&run_file) {
+        Ok(pipes) => pipes,
+        Err(errors) => {
+            eprintln!("Failed to remove the channels.");
+            for e in errors {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
+    };
+    for (_, path) in pipes {
+        if dry_run {
+            println!("\x1b[33mDRY\x1b[39m  remove {}", path.display());
+        } else if let Err(e) = remove_pipe(&path) {
+            eprintln!("Failed to remove channel {}: {}", path.display(), e);
+        }
+    }
+    if !dry_run {
+        let _ = std::fs::remove_dir(run_dir.join(PIPES_DIR));
+    }
+}
 
-        let mut procs = Vec::new();
-        let used_channels = super::get_used_channels(&content, &_channels);
+/// Runs the stop scripts of the channels and runners used by a plan, and
+/// removes its `fifo` and `unix-socket` channels from `run_dir`
+pub(crate) fn stop_pipeline(
+    file: &str,
+    content: &str,
+    run_dir: Option<&Path>,
+    channels: &[Channel],
+    runners: &[Runner],
+    hooks: &Hooks,
+    dry_run: bool,
+) {
+    let values: Steps = serde_json::from_str(content).unwrap();
+    let mut notifier = Notifier::new(hooks, &values.hooks, "stop", file);
 
-        used_channels.for_each(
-            |Channel {
-                 stop, location, id, ..
-             }| {
-                super::add_add_subproc(
-                    stop,
-                    location.as_ref(),
-                    &mut procs,
-                    id,
-                    OutputConfig::default(),
-                )
-            },
-        );
+    if let Some(run_dir) = run_dir {
+        remove_pipes(&values, run_dir, dry_run);
+    }
 
-        let used_runners = runners.iter().filter(|runner| {
-            values
-                .steps
-                .iter()
-                .any(|v| v.processor_config.runner_id == runner.id)
-        });
+    let mut scripts = Vec::new();
+    for channel in super::get_used_channels(content, channels) {
+        super::start_script(
+            &channel.stop,
+            channel.location.as_ref(),
+            "channel",
+            &channel.id,
+            dry_run,
+            &mut notifier,
+            &mut scripts,
+        );
+    }
 
-        used_runners.for_each(
-            |Runner {
-                 ref location,
-                 ref stop,
-                 id,
-                 ..
-             }| {
-                super::add_add_subproc(
-                    stop,
-                    location.as_ref(),
-                    &mut procs,
-                    id,
-                    OutputConfig::default(),
-                )
-            },
+    let used_runners = runners.iter().filter(|runner| {
+        values
+            .steps
+            .iter()
+            .any(|v| v.processor_config.runner_id == runner.id)
+    });
+    for runner in used_runners {
+        super::start_script(
+            &runner.stop,
+            runner.location.as_ref(),
+            "runner",
+            &runner.id,
+            dry_run,
+            &mut notifier,
+            &mut scripts,
         );
+    }
 
-        // Stops the processors in the reverse order
-        while !procs.is_empty() {
-            let (mut proc, h1, h2) = procs.pop().unwrap();
-            proc.wait().unwrap();
-            h1.join().unwrap();
-            h2.join().unwrap();
-        }
+    // Stops the processors in the reverse order
+    let failed = super::wait_scripts(scripts, &mut notifier);
+    if !dry_run {
+        notifier.pipeline_end(failed, None);
     }
 }
@@ -0,0 +1,852 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+use async_std::fs::read_to_string;
+use async_std::task;
+use serde_json::Value;
+
+use super::replay::{records_file, set_blocking, Recorded};
+use super::restart::{self, Request};
+use super::run::{install_interrupt_handlers, interrupted};
+use super::runs::{self, RunInfo};
+use crate::channel::{mkfifo, ChannelConfig};
+use crate::plan::{Steps, Target};
+use crate::step::{RunThing, StepArgument};
+
+/// Seconds the tap keeps forwarding after it gave the channel back to the
+/// writer, until the restarted writer opened the channel itself
+const DETACH_GRACE: u64 = 2;
+
+/// Seconds a tee waits for the reader to accept a connection
+const CONNECT_TIMEOUT: u64 = 10;
+
+/// Print the messages a step writes to a channel, as they are written
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Plan file
+    plan:           String,
+    /// Stream writer field to tap, as `<step>.<arg>.<field>`
+    target:         Target,
+    /// Run id, defaults to the most recent running pipeline of the plan
+    #[clap(long)]
+    run:            Option<String>,
+    /// Also print what a file channel already contains
+    #[clap(long)]
+    from_start:     bool,
+    /// Print the messages as they are, without pretty-printing them
+    #[clap(long)]
+    raw:            bool,
+    /// Directory containing the run directories
+    #[clap(long, default_value = runs::DEFAULT_RUNS_DIR)]
+    runs_dir:       String,
+    /// Tap a fifo, unix-socket or ws channel that is not recorded by
+    /// restarting its writer, once to write to the tap and once more to
+    /// write to the reader again
+    #[clap(long)]
+    restart_writer: bool,
+}
+
+/// Prints the messages of a channel with the time they passed the tap, or
+/// the recorder (UTC)
+struct Printer {
+    serialization: String,
+    raw:           bool,
+}
+
+impl Printer {
+    fn print(&self, message: &str) {
+        self.print_at(runs::now_millis(), message)
+    }
+
+    fn print_at(&self, millis: u64, message: &str) {
+        let secs = millis / 1000;
+        let time = format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            (secs / 3600) % 24,
+            (secs / 60) % 60,
+            secs % 60,
+            millis % 1000
+        );
+
+        let body = if self.raw {
+            message.to_string()
+        } else {
+            pretty(&self.serialization, message)
+        };
+        // Following lines are aligned with the first one
+        let body = body.replace('\n', &format!("\n{:13}", ""));
+        println!("\x1b[2m{}\x1b[0m {}", time, body);
+    }
+}
+
+/// Pretty-prints `message` according to its serialization, it is left as it
+/// is when it does not parse
+fn pretty(serialization: &str, message: &str) -> String {
+    match serialization {
+        "json" | "jsonld" => {
+            serde_json::from_str::<Value>(message)
+                .ok()
+                .and_then(|value| serde_json::to_string_pretty(&value).ok())
+                .unwrap_or_else(|| message.to_string())
+        }
+        "turtle" | "ntriples" | "nquads" | "trig" => highlight_rdf(message),
+        _ => message.to_string(),
+    }
+}
+
+/// Colors the IRIs and literals of an RDF message
+fn highlight_rdf(message: &str) -> String {
+    let mut out = String::new();
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '<' => {
+                out.push_str("\x1b[36m<");
+                for c in chars.by_ref() {
+                    out.push(c);
+                    if c == '>' {
+                        break;
+                    }
+                }
+                out.push_str("\x1b[39m");
+            }
+            '"' => {
+                out.push_str("\x1b[32m\"");
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    out.push(c);
+                    if c == '"' && !escaped {
+                        break;
+                    }
+                    escaped = c == '\\' && !escaped;
+                }
+                out.push_str("\x1b[39m");
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Splits what a writer sends in messages
+pub(super) enum Framing {
+    /// A message per line
+    Lines(Vec<u8>),
+    /// A message per WebSocket message, after the opening handshake
+    WebSocket {
+        buffer:    Vec<u8>,
+        handshake: bool,
+        message:   Vec<u8>,
+    },
+}
+
+impl Framing {
+    pub(super) fn websocket() -> Self {
+        Framing::WebSocket {
+            buffer:    Vec::new(),
+            handshake: false,
+            message:   Vec::new(),
+        }
+    }
+
+    /// The messages completed by `data`
+    fn push(&mut self, data: &[u8]) -> Vec<String> {
+        let mut messages = Vec::new();
+        match self {
+            Framing::Lines(buffer) => {
+                buffer.extend_from_slice(data);
+                while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                    let line = buffer.drain(..=end).collect::<Vec<_>>();
+                    let line = String::from_utf8_lossy(&line);
+                    messages.push(line.trim_end_matches(['\r', '\n']).into());
+                }
+            }
+            Framing::WebSocket {
+                buffer,
+                handshake,
+                message,
+            } => {
+                buffer.extend_from_slice(data);
+                if !*handshake {
+                    match buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                        Some(end) => {
+                            buffer.drain(..end + 4);
+                            *handshake = true;
+                        }
+                        None => return messages,
+                    }
+                }
+
+                while let Some((fin, opcode, payload, len)) =
+                    parse_frame(buffer)
+                {
+                    buffer.drain(..len);
+                    // Control frames (close, ping, pong) are no messages
+                    if opcode <= 2 {
+                        message.extend(payload);
+                        if fin {
+                            let data = std::mem::take(message);
+                            messages
+                                .push(String::from_utf8_lossy(&data).into());
+                        }
+                    }
+                }
+            }
+        }
+        messages
+    }
+
+    /// What is left when the stream ends, a last line without a newline
+    fn finish(&mut self) -> Option<String> {
+        match self {
+            Framing::Lines(buffer) if !buffer.is_empty() => {
+                let line = std::mem::take(buffer);
+                Some(String::from_utf8_lossy(&line).into())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses the WebSocket frame at the start of `data`: whether it is the last
+/// fragment of its message, its opcode, its unmasked payload and its length
+fn parse_frame(data: &[u8]) -> Option<(bool, u8, Vec<u8>, usize)> {
+    let fin = data.first()? & 0x80 != 0;
+    let opcode = data[0] & 0x0f;
+    let masked = data.get(1)? & 0x80 != 0;
+    let (len, mut offset) = match data[1] & 0x7f {
+        126 => {
+            (
+                u16::from_be_bytes(data.get(2..4)?.try_into().ok()?) as usize,
+                4,
+            )
+        }
+        127 => {
+            let len = u64::from_be_bytes(data.get(2..10)?.try_into().ok()?);
+            (usize::try_from(len).ok()?, 10)
+        }
+        len => (len as usize, 2),
+    };
+
+    let mask = if masked {
+        let mask = data.get(offset..offset + 4)?.to_vec();
+        offset += 4;
+        Some(mask)
+    } else {
+        None
+    };
+    let end = offset.checked_add(len)?;
+    let payload = data
+        .get(offset..end)?
+        .iter()
+        .enumerate()
+        .map(|(i, b)| mask.as_ref().map_or(*b, |mask| b ^ mask[i % 4]))
+        .collect();
+    Some((fin, opcode, payload, end))
+}
+
+/// Called with every message that passes a tee
+pub(super) type Inspect = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Copies `from` to `to` until `from` ends, inspecting the messages in it
+pub(super) fn forward(
+    mut from: impl Read,
+    to: &mut impl Write,
+    framing: &mut Framing,
+    inspect: &dyn Fn(&str),
+) -> std::io::Result<()> {
+    let mut buffer = [0; 8192];
+    loop {
+        let n = from.read(&mut buffer)?;
+        if n == 0 {
+            if let Some(rest) = framing.finish() {
+                inspect(&rest);
+            }
+            return Ok(());
+        }
+
+        for message in framing.push(&buffer[..n]) {
+            inspect(&message);
+        }
+        to.write_all(&buffer[..n])?;
+        to.flush()?;
+    }
+}
+
+/// A connection over a socket channel
+trait Connection: Read + Write + Send + Sized + 'static {
+    fn duplicate(&self) -> std::io::Result<Self>;
+    fn close(&self);
+}
+
+impl Connection for UnixStream {
+    fn duplicate(&self) -> std::io::Result<Self> {
+        self.try_clone()
+    }
+
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+impl Connection for TcpStream {
+    fn duplicate(&self) -> std::io::Result<Self> {
+        self.try_clone()
+    }
+
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+/// Forwards a connection of the writer to the reader and the replies of the
+/// reader back, inspecting the messages of the writer
+fn proxy<C: Connection>(
+    writer: C,
+    reader: C,
+    mut framing: Framing,
+    inspect: Inspect,
+) -> std::io::Result<()> {
+    let mut replies = reader.duplicate()?;
+    let mut writer_out = writer.duplicate()?;
+    thread::spawn(move || {
+        let _ = std::io::copy(&mut replies, &mut writer_out);
+        writer_out.close();
+    });
+
+    thread::spawn(move || {
+        let mut reader = reader;
+        let _ = forward(writer, &mut reader, &mut framing, &*inspect);
+        reader.close();
+    });
+    Ok(())
+}
+
+/// Accepts the connections of the writer, each one is forwarded to a new
+/// connection to the reader. The reader gets `CONNECT_TIMEOUT` seconds to
+/// start listening.
+fn serve<C: Connection>(
+    accept: impl Fn() -> std::io::Result<C> + Send + 'static,
+    connect: impl Fn() -> std::io::Result<C> + Send + 'static,
+    framing: fn() -> Framing,
+    inspect: Inspect,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        while let Ok(writer) = accept() {
+            let proxied = retry(&connect).and_then(|reader| {
+                proxy(writer, reader, framing(), inspect.clone())
+            });
+            if let Err(e) = proxied {
+                eprintln!("Failed to connect to the reader: {}", e);
+            }
+        }
+    })
+}
+
+/// Calls `connect` until it succeeds or `CONNECT_TIMEOUT` seconds passed
+pub(super) fn retry<C>(
+    connect: impl Fn() -> std::io::Result<C>,
+) -> std::io::Result<C> {
+    let deadline = Instant::now() + Duration::from_secs(CONNECT_TIMEOUT);
+    loop {
+        match connect() {
+            Err(_) if Instant::now() < deadline => {
+                sleep(Duration::from_millis(100))
+            }
+            result => return result,
+        }
+    }
+}
+
+/// `config` with `key` set to `value`
+pub(super) fn with_config(
+    config: &ChannelConfig,
+    key: &str,
+    value: String,
+) -> ChannelConfig {
+    let mut inner = config.config().clone();
+    inner[key] = Value::String(value);
+    ChannelConfig::new(
+        config.ty().to_string(),
+        config.serialization().to_string(),
+        inner,
+    )
+}
+
+/// `path` with `suffix` appended
+pub(super) fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    name.into()
+}
+
+/// Splits a `ws://host:port/path` url in its address and path
+pub(super) fn ws_address(url: &str) -> Option<(String, String)> {
+    let rest = url.strip_prefix("ws://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    Some((address, path.to_string()))
+}
+
+/// A channel taken over by a tee, the writer writes to the tee and the tee to
+/// the reader
+pub(super) struct Tee {
+    /// The channel the writer is redirected to
+    pub(super) config: ChannelConfig,
+    /// Named pipe or socket of the tee, removed when it detaches
+    pub(super) path:   Option<PathBuf>,
+    /// Forwards the messages, a named pipe is forwarded until the writer
+    /// closes it
+    handle:            JoinHandle<()>,
+    /// Holds the reader's end of a named pipe open while the writer restarts
+    #[allow(dead_code)]
+    holding:           Option<File>,
+}
+
+impl Tee {
+    /// Takes over a `fifo` channel at `path`: the writer writes to a pipe of
+    /// the tap, which copies to the pipe of the reader
+    fn fifo(
+        config: &ChannelConfig,
+        path: PathBuf,
+        inspect: Inspect,
+    ) -> Result<Self, String> {
+        let tap = with_suffix(&path, ".tap");
+        mkfifo(&tap).map_err(|e| {
+            format!("Failed to create {}: {}", tap.display(), e)
+        })?;
+
+        // The reader would see the end of the stream when the writer restarts
+        // while nothing else has the pipe open. Opening it without blocking
+        // fails when there is no reader.
+        let holding = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        set_blocking(&holding)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut upstream = holding.try_clone().map_err(|e| e.to_string())?;
+
+        let input = tap.clone();
+        let handle = thread::spawn(move || {
+            let result = File::open(&input).and_then(|from| {
+                let mut framing = Framing::Lines(Vec::new());
+                forward(from, &mut upstream, &mut framing, &*inspect)
+            });
+            if let Err(e) = result {
+                eprintln!("Failed to forward {}: {}", input.display(), e);
+            }
+        });
+
+        Ok(Self {
+            config: with_config(config, "path", tap.display().to_string()),
+            path: Some(tap),
+            handle,
+            holding: Some(holding),
+        })
+    }
+
+    /// Takes over a `unix-socket` channel at `path`: the writer connects to
+    /// socket `tap`, which connects to the reader
+    pub(super) fn unix_socket(
+        config: &ChannelConfig,
+        path: PathBuf,
+        tap: PathBuf,
+        inspect: Inspect,
+    ) -> Result<Self, String> {
+        let _ = std::fs::remove_file(&tap);
+        let listener = UnixListener::bind(&tap).map_err(|e| {
+            format!("Failed to listen on {}: {}", tap.display(), e)
+        })?;
+
+        let handle = serve(
+            move || listener.accept().map(|(stream, _)| stream),
+            move || UnixStream::connect(&path),
+            || Framing::Lines(Vec::new()),
+            inspect,
+        );
+
+        Ok(Self {
+            config: with_config(config, "path", tap.display().to_string()),
+            path: Some(tap),
+            handle,
+            holding: None,
+        })
+    }
+
+    /// Takes over a `ws` channel: the writer connects to a port of the tee,
+    /// which connects to the `url` of the channel
+    pub(super) fn ws(
+        config: &ChannelConfig,
+        inspect: Inspect,
+    ) -> Result<Self, String> {
+        let url = config
+            .config()
+            .get("url")
+            .and_then(Value::as_str)
+            .ok_or("ws channel without a url")?;
+        let (address, path) = ws_address(url)
+            .ok_or_else(|| format!("Unsupported url {}, only ws:// is", url))?;
+
+        let listener =
+            TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+
+        let handle = serve(
+            move || listener.accept().map(|(stream, _)| stream),
+            move || TcpStream::connect(&address),
+            Framing::websocket,
+            inspect,
+        );
+
+        Ok(Self {
+            config: with_config(
+                config,
+                "url",
+                format!("ws://127.0.0.1:{}{}", port, path),
+            ),
+            path: None,
+            handle,
+            holding: None,
+        })
+    }
+}
+
+/// Inspects what is appended to the file of a `file` channel until `stop`, the
+/// whole file when the channel replaces it for every message (`onReplace`).
+/// What the file contains at first is skipped unless `from_start`, what was
+/// appended when `stop` turns true is still inspected.
+pub(super) fn follow_file(
+    config: &ChannelConfig,
+    from_start: bool,
+    stop: &dyn Fn() -> bool,
+    inspect: &dyn Fn(&str),
+) -> Result<(), String> {
+    let path = config
+        .config()
+        .get("path")
+        .and_then(Value::as_str)
+        .ok_or("file channel without a path")?;
+    let on_replace = config
+        .config()
+        .get("onReplace")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    let metadata = std::fs::metadata(path).ok();
+    let (mut offset, mut modified) = match (&metadata, from_start) {
+        (Some(metadata), false) => (metadata.len(), metadata.modified().ok()),
+        _ => (0, None),
+    };
+    let mut framing = Framing::Lines(Vec::new());
+
+    loop {
+        let stopping = stop();
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) if stopping => break,
+            Err(_) => {
+                sleep(Duration::from_millis(200));
+                continue;
+            }
+        };
+
+        if on_replace {
+            let changed = metadata.modified().ok();
+            if changed != modified || modified.is_none() {
+                modified = changed;
+                if let Ok(content) = std::fs::read_to_string(path) {
+                    inspect(content.trim_end());
+                }
+            }
+        } else {
+            // The file was truncated or replaced
+            if metadata.len() < offset {
+                offset = 0;
+            }
+            if metadata.len() > offset {
+                let mut data = Vec::new();
+                let read = File::open(path).and_then(|mut file| {
+                    file.seek(SeekFrom::Start(offset))?;
+                    file.read_to_end(&mut data)
+                });
+                if let Ok(n) = read {
+                    offset += n as u64;
+                    for message in framing.push(&data) {
+                        inspect(&message);
+                    }
+                }
+            }
+        }
+        if stopping {
+            break;
+        }
+        sleep(Duration::from_millis(200));
+    }
+    Ok(())
+}
+
+/// Restarts `writer` with `config` as the channel of the field `target`
+fn redirect(
+    run_dir: &Path,
+    writer: &RunThing,
+    target: &Target,
+    config: ChannelConfig,
+) -> Result<(), String> {
+    let mut run = writer.clone();
+    if let Some(StepArgument::StreamWriter { fields }) =
+        run.args.get_mut(&target.arg)
+    {
+        fields.insert(target.field.clone(), config);
+    }
+    restart::send(run_dir, &Request::Restart { run })
+}
+
+/// Whether `step` of the run in `run_dir` is running
+async fn is_running(run_dir: &Path, step: &str) -> bool {
+    RunInfo::load(run_dir).await.is_some_and(|info| {
+        info.processes
+            .iter()
+            .rev()
+            .find(|proc| proc.name == step)
+            .is_some_and(|proc| proc.finished.is_none())
+    })
+}
+
+impl Command {
+    /// The run directory of the running pipeline of the plan and its plan, or
+    /// the plan file when it is not running
+    async fn plan(&self) -> Result<(Option<PathBuf>, Steps), String> {
+        let base = Path::new(&self.runs_dir);
+        let run = match &self.run {
+            Some(id) => {
+                let run = runs::find_run(base, id).await;
+                Some(run.ok_or_else(|| format!("No run {} found", id))?)
+            }
+            None => {
+                let plan = Path::new(&self.plan).canonicalize().ok();
+                runs::list_runs(base).await.into_iter().rfind(|(_, info)| {
+                    info.status() == "running"
+                        && Path::new(&info.plan).canonicalize().ok() == plan
+                })
+            }
+        };
+
+        let file = match &run {
+            Some((dir, _)) => dir.join("plan.json"),
+            None => PathBuf::from(&self.plan),
+        };
+        let content = read_to_string(&file)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let plan = Steps::from_json(&content).map_err(|e| {
+            format!("Failed to parse {}: {}", file.display(), e)
+        })?;
+        Ok((run.map(|(dir, _)| dir), plan))
+    }
+
+    /// Redirects the writer to the tap until the tap is interrupted, then
+    /// gives the channel back to the writer
+    async fn tee(
+        &self,
+        run_dir: &Path,
+        writer: &RunThing,
+        config: &ChannelConfig,
+        printer: Arc<Printer>,
+    ) -> Result<(), String> {
+        // The tap's paths are given to the writer, they must be absolute
+        let run_dir = &run_dir.canonicalize().map_err(|e| e.to_string())?;
+        let run_file = |name: &str| run_dir.join(name).display().to_string();
+        let path = || {
+            config.pipe_path(&run_file)?.ok_or_else(|| {
+                format!("{} channel without a path", config.ty())
+            })
+        };
+        let inspect: Inspect = Arc::new(move |message| printer.print(message));
+        let tee = match config.ty() {
+            "fifo" => Tee::fifo(config, path()?, inspect)?,
+            "unix-socket" => {
+                let path = path()?;
+                let tap = with_suffix(&path, ".tap");
+                Tee::unix_socket(config, path, tap, inspect)?
+            }
+            _ => Tee::ws(config, inspect)?,
+        };
+
+        let step = &writer.processor_config.id;
+        let result =
+            redirect(run_dir, writer, &self.target, tee.config.clone());
+        if result.is_ok() {
+            while !interrupted() && !tee.handle.is_finished() {
+                task::sleep(Duration::from_millis(100)).await;
+            }
+
+            if tee.handle.is_finished() {
+                println!("Step {} closed the channel", step);
+            } else if is_running(run_dir, step).await {
+                if let Err(e) =
+                    redirect(run_dir, writer, &self.target, config.clone())
+                {
+                    eprintln!("Failed to restart step {}: {}", step, e);
+                }
+                task::sleep(Duration::from_secs(DETACH_GRACE)).await;
+            }
+        }
+
+        if let Some(path) = &tee.path {
+            let _ = std::fs::remove_file(path);
+        }
+        result.map_err(|e| format!("Failed to restart step {}: {}", step, e))
+    }
+
+    pub(crate) async fn execute(self) {
+        if let Err(e) = self.tap().await {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    async fn tap(&self) -> Result<(), String> {
+        let (run_dir, plan) = self.plan().await?;
+        let writer = plan
+            .steps
+            .iter()
+            .find(|run| run.processor_config.id == self.target.step)
+            .ok_or_else(|| {
+                format!("No step {} in the plan", self.target.step)
+            })?;
+        let config = plan.writer_channel(&self.target).ok_or_else(|| {
+            format!("{} is no stream writer field", self.target)
+        })?;
+
+        let printer = Arc::new(Printer {
+            serialization: config.serialization().to_string(),
+            raw:           self.raw,
+        });
+        install_interrupt_handlers();
+        println!(
+            "Tapping {} ({} channel, {}), stop with Ctrl-C",
+            self.target,
+            config.ty(),
+            config.serialization()
+        );
+
+        let records = run_dir
+            .as_ref()
+            .map(|dir| records_file(dir, &self.target))
+            .filter(|file| file.is_file());
+
+        match (config.ty(), &run_dir) {
+            ("file", _) => {
+                follow_file(config, self.from_start, &interrupted, &|message| {
+                    printer.print(message)
+                })
+            }
+            // Recorded by `run --record`, the records are followed instead
+            ("fifo" | "unix-socket" | "ws", Some(_)) if records.is_some() => {
+                let records = ChannelConfig::new(
+                    String::from("file"),
+                    String::from("json"),
+                    json!({ "path": records }),
+                );
+                follow_file(&records, self.from_start, &interrupted, &|line| {
+                    match serde_json::from_str::<Recorded>(line) {
+                        Ok(recorded) => {
+                            printer.print_at(recorded.time, &recorded.message)
+                        }
+                        Err(_) => printer.print(line),
+                    }
+                })
+            }
+            ("fifo" | "unix-socket" | "ws", Some(run_dir))
+                if self.restart_writer =>
+            {
+                self.tee(run_dir, writer, config, printer).await
+            }
+            ("fifo" | "unix-socket" | "ws", Some(_)) => {
+                Err(format!(
+                    "{} is not recorded, tapping its {} channel restarts step \
+                     {} twice, pass --restart-writer to do so or record it \
+                     with `run --record {}`",
+                    self.target,
+                    config.ty(),
+                    self.target.step,
+                    self.target
+                ))
+            }
+            ("fifo" | "unix-socket" | "ws", None) => {
+                Err(format!(
+                    "Tapping a {} channel needs a running pipeline of the plan",
+                    config.ty()
+                ))
+            }
+            (ty, _) => {
+                Err(format!(
+                    "{} channels can not be tapped, file, fifo, unix-socket \
+                     and ws ones can",
+                    ty
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_websocket_frames() {
+        // Unmasked text frame followed by the start of another one
+        let frame = [0x81, 0x02, b'h', b'i', 0x81];
+        assert_eq!(parse_frame(&frame), Some((true, 1, b"hi".to_vec(), 4)));
+
+        // Masked continuation fragment
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![0x00, 0x83];
+        frame.extend(mask);
+        frame.extend(b"abc".iter().zip(mask).map(|(b, m)| b ^ m));
+        assert_eq!(parse_frame(&frame), Some((false, 0, b"abc".to_vec(), 9)));
+
+        // 16 bit length
+        let mut frame = vec![0x82, 126, 0x01, 0x00];
+        frame.extend([7; 256]);
+        assert_eq!(parse_frame(&frame), Some((true, 2, vec![7; 256], 260)));
+    }
+
+    #[test]
+    fn waits_for_incomplete_frames() {
+        assert_eq!(parse_frame(&[]), None);
+        assert_eq!(parse_frame(&[0x81]), None);
+        assert_eq!(parse_frame(&[0x81, 0x05, b'h', b'i']), None);
+        assert_eq!(parse_frame(&[0x81, 0x85, 1, 2]), None);
+    }
+
+    #[test]
+    fn rejects_lengths_past_the_address_space() {
+        let mut frame = vec![0x81, 127];
+        frame.extend(u64::MAX.to_be_bytes());
+        frame.extend(b"data");
+        assert_eq!(parse_frame(&frame), None);
+
+        let mut frame = vec![0x81, 0xff];
+        frame.extend((u64::MAX - 2).to_be_bytes());
+        frame.extend([0; 8]);
+        assert_eq!(parse_frame(&frame), None);
+    }
+}
@@ -0,0 +1,670 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use async_std::fs::{read_to_string, write};
+use async_std::future::timeout;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use tempdir::TempDir;
+
+use crate::channel::{Channel, ChannelConfig};
+use crate::exec::{Event, Options, Pipeline, ProcessKind, Stream};
+use crate::plan::{PlanBuilder, Steps, Target};
+use crate::runner::Runner;
+use crate::step::{parse_step, Step};
+
+/// Lines of step output shown for a failed test
+const LOG_LINES: usize = 20;
+
+/// Data handed to a stream reader
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Data {
+    Text(String),
+    /// A file, relative to the spec
+    File {
+        file: PathBuf,
+    },
+    /// Any other JSON value, written as JSON
+    Json(Value),
+}
+
+/// What a stream writer has to write
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+enum Expected {
+    /// Exactly this text
+    Exact(String),
+    /// JSON equal to this value, a writer that writes a JSON value per line
+    /// is compared as an array
+    Json(Value),
+    /// Text that matches this regular expression
+    Regex(String),
+}
+
+/// A test of a step, in JSON or YAML
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Spec {
+    /// Shown in the report, defaults to the name of the spec file
+    name:          Option<String>,
+    /// Values of the arguments that are not stream readers or writers
+    #[serde(default)]
+    args:          HashMap<String, Value>,
+    /// Data per stream reader field (`<arg>.<field>`)
+    #[serde(default)]
+    inputs:        HashMap<String, Data>,
+    /// Expected data per stream writer field (`<arg>.<field>`)
+    #[serde(default)]
+    outputs:       HashMap<String, Expected>,
+    /// Serialization of the channels, defaults to the first one the runner
+    /// can use
+    serialization: Option<String>,
+    /// Exit code the step has to exit with by itself, otherwise it is stopped
+    /// as soon as its outputs are as expected
+    exit_code:     Option<i32>,
+    /// Seconds after which the step is stopped
+    #[serde(default = "default_timeout")]
+    timeout:       u64,
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+/// Parses a JSON value, or a JSON value per line as an array
+fn parse_json(content: &str) -> Option<Value> {
+    serde_json::from_str(content).ok().or_else(|| {
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).ok())
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array)
+    })
+}
+
+/// A line diff of `expected` and `actual`
+fn diff(expected: &str, actual: &str) -> String {
+    let a = expected.lines().collect::<Vec<_>>();
+    let b = actual.lines().collect::<Vec<_>>();
+
+    // Longest common subsequence of the lines, from the end
+    let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out.push(format!("  {}", a[i]));
+            i += 1;
+            j += 1;
+        } else if j < b.len()
+            && (i == a.len() || lcs[i][j + 1] >= lcs[i + 1][j])
+        {
+            out.push(format!("\x1b[32m+ {}\x1b[39m", b[j]));
+            j += 1;
+        } else {
+            out.push(format!("\x1b[31m- {}\x1b[39m", a[i]));
+            i += 1;
+        }
+    }
+    if expected.ends_with('\n') != actual.ends_with('\n') {
+        out.push(String::from("  (the trailing newline differs)"));
+    }
+    out.join("\n")
+}
+
+impl Expected {
+    /// How `actual` differs from what is expected, `None` when it matches
+    fn check(&self, actual: &str) -> Option<String> {
+        match self {
+            Expected::Exact(expected) if expected == actual => None,
+            Expected::Exact(expected) => Some(diff(expected, actual)),
+            Expected::Json(expected) => {
+                match parse_json(actual) {
+                    Some(value) if &value == expected => None,
+                    Some(value) => {
+                        Some(diff(
+                            &serde_json::to_string_pretty(expected).unwrap(),
+                            &serde_json::to_string_pretty(&value).unwrap(),
+                        ))
+                    }
+                    None => Some(format!("not JSON:\n{}", actual)),
+                }
+            }
+            Expected::Regex(pattern) => {
+                match Regex::new(pattern) {
+                    Ok(regex) if regex.is_match(actual) => None,
+                    Ok(_) => {
+                        Some(format!(
+                            "does not match /{}/:\n{}",
+                            pattern, actual
+                        ))
+                    }
+                    Err(e) => Some(format!("invalid regex: {}", e)),
+                }
+            }
+        }
+    }
+}
+
+/// An output of the step under test
+struct Output {
+    name:     String,
+    path:     PathBuf,
+    expected: Expected,
+}
+
+impl Output {
+    async fn check(&self) -> Option<String> {
+        let actual = read_to_string(&self.path).await.unwrap_or_default();
+        self.expected.check(&actual)
+    }
+}
+
+/// How a test went
+struct Outcome {
+    failures: Vec<String>,
+    /// The last lines the step wrote
+    logs:     Vec<String>,
+    duration: Duration,
+}
+
+async fn load_spec(path: &Path) -> Result<Spec, String> {
+    let content = read_to_string(path).await.map_err(|e| e.to_string())?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => {
+            // Through JSON, so both have the same shape
+            serde_yaml::from_str::<Value>(&content)
+                .map_err(|e| e.to_string())
+                .and_then(|v| {
+                    serde_json::from_value(v).map_err(|e| e.to_string())
+                })
+        }
+        _ => serde_json::from_str(&content).map_err(|e| e.to_string()),
+    }
+}
+
+/// Run a step through its runner with fixture inputs, checking its outputs
+#[derive(clap::Args, Debug)]
+pub struct Command {
+    /// Step file
+    step:  PathBuf,
+    /// Test specs (JSON or YAML) with the values of the arguments, the input
+    /// of the stream readers and the expected output of the stream writers
+    #[clap(required = true)]
+    specs: Vec<PathBuf>,
+    /// Keep the run directories of the tests
+    #[clap(long)]
+    keep:  bool,
+}
+
+impl Command {
+    /// Builds a plan of only `step` in `dir`, with file channels for its
+    /// stream readers and writers, returning the outputs to check
+    async fn plan(
+        step: &Step,
+        spec: &Spec,
+        spec_dir: &Path,
+        dir: &Path,
+        runners: &[Runner],
+        channels: &[Channel],
+    ) -> Result<(Steps, Vec<Output>), Vec<String>> {
+        let runner = step.find_runner(runners).map_err(|e| vec![e])?;
+        let serialization = spec
+            .serialization
+            .clone()
+            .or_else(|| runner.can_use_serialization.first().cloned())
+            .unwrap_or_else(|| String::from("json"));
+        let file_channel = |path: &Path| {
+            ChannelConfig::new(
+                String::from("file"),
+                serialization.clone(),
+                json!({ "path": path }),
+            )
+        };
+
+        let mut builder = PlanBuilder::new(runners, channels);
+        builder.add_step(step).map_err(|e| vec![e])?;
+
+        let mut errors = Vec::new();
+        let mut outputs = Vec::new();
+        let mut inputs = Vec::new();
+        for arg in &step.args {
+            for field in arg.fields() {
+                let target = Target::new(&step.id, &arg.id, field);
+                let name = format!("{}.{}", arg.id, field);
+
+                let linked = if arg.ty == "streamReader" {
+                    let path = dir.join(format!("{}.in", name));
+                    let content = match spec.inputs.get(&name) {
+                        Some(Data::Text(text)) => Ok(text.clone()),
+                        Some(Data::File { file }) => {
+                            read_to_string(spec_dir.join(file)).await.map_err(
+                                |e| {
+                                    format!(
+                                        "Failed to read {}: {}",
+                                        file.display(),
+                                        e
+                                    )
+                                },
+                            )
+                        }
+                        Some(Data::Json(value)) => Ok(value.to_string()),
+                        None => Ok(String::new()),
+                    };
+                    inputs.push(name);
+
+                    match content {
+                        Ok(content) => {
+                            write(&path, content)
+                                .await
+                                .map_err(|e| e.to_string())
+                                .and_then(|_| {
+                                    builder
+                                        .read_from(&target, file_channel(&path))
+                                        .map(|_| ())
+                                })
+                        }
+                        Err(e) => Err(e),
+                    }
+                } else {
+                    let path = dir.join(format!("{}.out", name));
+                    if let Some(expected) = spec.outputs.get(&name) {
+                        outputs.push(Output {
+                            name,
+                            path: path.clone(),
+                            expected: expected.clone(),
+                        });
+                    }
+                    builder.write_to(&target, file_channel(&path)).map(|_| ())
+                };
+                if let Err(e) = linked {
+                    errors.push(e);
+                }
+            }
+
+            if let Some(value) = spec.args.get(&arg.id) {
+                if let Err(e) =
+                    builder.set_value(&step.id, &arg.id, value.clone())
+                {
+                    errors.push(e);
+                }
+            }
+        }
+
+        for name in spec.inputs.keys().filter(|name| !inputs.contains(name)) {
+            errors.push(format!("The step has no stream reader {}", name));
+        }
+        for name in spec
+            .outputs
+            .keys()
+            .filter(|name| !outputs.iter().any(|o| &o.name == *name))
+        {
+            errors.push(format!("The step has no stream writer {}", name));
+        }
+        for name in spec
+            .args
+            .keys()
+            .filter(|name| !step.args.iter().any(|a| &a.id == *name))
+        {
+            errors.push(format!("The step has no argument {}", name));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok((builder.build()?, outputs))
+    }
+
+    /// Runs `step` as `spec` describes in `dir`
+    async fn run(
+        step: &Step,
+        spec: &Spec,
+        spec_dir: &Path,
+        dir: &Path,
+        runners: &[Runner],
+        channels: &[Channel],
+    ) -> Result<Outcome, Vec<String>> {
+        let (plan, outputs) =
+            Self::plan(step, spec, spec_dir, dir, runners, channels).await?;
+
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(spec.timeout);
+        let mut handle =
+            Pipeline::start(plan, runners.to_vec(), Options::new(dir));
+        let events = handle.events().expect("the events are taken once");
+
+        let mut failures = Vec::new();
+        let mut logs = Vec::new();
+        let mut exit = None;
+        let mut stopped = false;
+        let mut checked = start;
+        loop {
+            match timeout(Duration::from_millis(200), events.recv()).await {
+                Ok(Ok(Event::Log { stream, line, .. })) => {
+                    let prefix = match stream {
+                        Stream::Stdout => "\x1b[32mINFO\x1b[39m",
+                        Stream::Stderr => "\x1b[31mERRO\x1b[39m",
+                    };
+                    logs.push(format!("{} {}", prefix, line));
+                    if logs.len() > LOG_LINES {
+                        logs.remove(0);
+                    }
+                }
+                Ok(Ok(Event::Exited(e))) if e.kind == ProcessKind::Step => {
+                    exit = Some(e)
+                }
+                Ok(Ok(Event::Error { message, .. })) => {
+                    failures.push(format!("Failed to start: {}", message))
+                }
+                Ok(Ok(Event::PipelineError { message })) => {
+                    failures.push(message)
+                }
+                Ok(Ok(Event::Finished)) | Ok(Err(_)) => break,
+                Ok(Ok(_)) | Err(_) => {}
+            }
+
+            if stopped || checked.elapsed() < Duration::from_millis(200) {
+                continue;
+            }
+            checked = Instant::now();
+            if checked >= deadline {
+                failures.push(format!("Timed out after {}s", spec.timeout));
+                handle.stop();
+                stopped = true;
+            } else if spec.exit_code.is_none() && !outputs.is_empty() {
+                let mut done = true;
+                for output in &outputs {
+                    done &= output.check().await.is_none();
+                }
+                if done {
+                    handle.stop();
+                    stopped = true;
+                }
+            }
+        }
+        handle.wait().await;
+        let duration = start.elapsed();
+
+        match (spec.exit_code, exit) {
+            (Some(code), Some(exit)) if exit.exit_code != Some(code) => {
+                failures.push(format!(
+                    "Exited with {}, expected {}",
+                    exit.exit_code
+                        .map(|c| c.to_string())
+                        .unwrap_or_else(|| String::from("a signal")),
+                    code
+                ))
+            }
+            (None, Some(exit)) if exit.reason.is_none() && !exit.success() => {
+                failures.push(format!(
+                    "Exited with {}",
+                    exit.exit_code
+                        .map(|c| c.to_string())
+                        .unwrap_or_else(|| String::from("a signal"))
+                ))
+            }
+            _ => {}
+        }
+
+        for output in &outputs {
+            if let Some(diff) = output.check().await {
+                failures.push(format!("{} differs:\n{}", output.name, diff));
+            }
+        }
+
+        Ok(Outcome {
+            failures,
+            logs,
+            duration,
+        })
+    }
+
+    pub(crate) async fn execute(
+        self,
+        channels: Vec<Channel>,
+        runners: Vec<Runner>,
+    ) {
+        let step = match parse_step(&self.step).await {
+            Ok(step) => step,
+            Err(e) => {
+                eprintln!(
+                    "Failed to parse step {}: {}",
+                    self.step.display(),
+                    e
+                );
+                std::process::exit(1);
+            }
+        };
+        let mut failed = 0;
+        for path in &self.specs {
+            let spec = match load_spec(path).await {
+                Ok(spec) => spec,
+                Err(e) => {
+                    println!("\x1b[31mFAIL\x1b[39m {}", path.display());
+                    println!("  Failed to read the spec: {}", e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            let name = spec.name.clone().unwrap_or_else(|| {
+                path.file_stem()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or_default()
+            });
+            let spec_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+            let dir = match TempDir::new("nautirust-test") {
+                Ok(dir) => dir,
+                Err(e) => {
+                    eprintln!("Failed to create a run directory: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let outcome = Self::run(
+                &step,
+                &spec,
+                spec_dir,
+                dir.path(),
+                &runners,
+                &channels,
+            )
+            .await;
+
+            match outcome {
+                Ok(outcome) if outcome.failures.is_empty() => {
+                    println!(
+                        "\x1b[32mPASS\x1b[39m {}: {} ({})",
+                        step.id,
+                        name,
+                        super::runs::format_duration(Some(
+                            outcome.duration.as_millis() as u64
+                        ))
+                    );
+                }
+                Ok(outcome) => {
+                    failed += 1;
+                    println!(
+                        "\x1b[31mFAIL\x1b[39m {}: {} ({})",
+                        step.id,
+                        name,
+                        super::runs::format_duration(Some(
+                            outcome.duration.as_millis() as u64
+                        ))
+                    );
+                    for failure in &outcome.failures {
+                        println!("  {}", failure.replace('\n', "\n    "));
+                    }
+                    if !outcome.logs.is_empty() {
+                        println!("  Output of the step:");
+                        for line in &outcome.logs {
+                            println!("    {}", line);
+                        }
+                    }
+                }
+                Err(errors) => {
+                    failed += 1;
+                    println!("\x1b[31mFAIL\x1b[39m {}: {}", step.id, name);
+                    for e in errors {
+                        println!("  Error: {}", e);
+                    }
+                }
+            }
+
+            if self.keep {
+                println!("  Run directory: {}", dir.into_path().display());
+            }
+        }
+
+        println!(
+            "{} tests, {} passed, {} failed",
+            self.specs.len(),
+            self.specs.len() - failed,
+            failed
+        );
+        if failed > 0 {
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn added(line: &str) -> String {
+        format!("\x1b[32m+ {}\x1b[39m", line)
+    }
+
+    fn removed(line: &str) -> String {
+        format!("\x1b[31m- {}\x1b[39m", line)
+    }
+
+    #[test]
+    fn diff_keeps_the_common_lines_in_order() {
+        let diff = diff("a\nb\nc\nd\n", "a\nc\nx\nd\n");
+        let lines: Vec<&str> = diff.lines().collect();
+        assert_eq!(lines, ["  a", &removed("b"), "  c", &added("x"), "  d"]);
+    }
+
+    #[test]
+    fn diff_notes_a_different_trailing_newline() {
+        assert_eq!(
+            diff("a\nb\n", "a\nb"),
+            "  a\n  b\n  (the trailing newline differs)"
+        );
+        assert_eq!(diff("a\n", "a\n"), "  a");
+    }
+
+    #[test]
+    fn parse_json_reads_a_value_per_line_as_an_array() {
+        assert_eq!(parse_json(r#"{"a": [1, 2]}"#), Some(json!({"a": [1, 2]})));
+        assert_eq!(
+            parse_json("{\"a\": 1}\n\n{\"a\": 2}\n"),
+            Some(json!([{"a": 1}, {"a": 2}]))
+        );
+        assert_eq!(parse_json("{\"a\": 1}\nnot json\n"), None);
+    }
+
+    #[test]
+    fn expected_checks_the_output() {
+        let exact = Expected::Exact(String::from("hello\n"));
+        assert_eq!(exact.check("hello\n"), None);
+        assert_eq!(
+            exact.check("hello"),
+            Some(String::from("  hello\n  (the trailing newline differs)"))
+        );
+
+        let json = Expected::Json(json!([{"a": 1}, {"a": 2}]));
+        assert_eq!(json.check("{\"a\":1}\n{\"a\":2}\n"), None);
+        assert_eq!(json.check("[{\"a\": 1}, {\"a\": 2}]"), None);
+        assert!(json
+            .check("{\"a\":1}\n")
+            .unwrap()
+            .contains(&removed("  },")));
+        assert_eq!(json.check("oops"), Some(String::from("not JSON:\noops")));
+
+        let regex = Expected::Regex(String::from("^count: \\d+$"));
+        assert_eq!(regex.check("count: 12"), None);
+        assert_eq!(
+            regex.check("count: x"),
+            Some(String::from("does not match /^count: \\d+$/:\ncount: x"))
+        );
+        assert!(Expected::Regex(String::from("("))
+            .check("")
+            .unwrap()
+            .starts_with("invalid regex"));
+    }
+
+    #[async_std::test]
+    async fn load_spec_reads_json_and_yaml() {
+        let dir = TempDir::new("spec").unwrap();
+        let json_spec = dir.path().join("spec.json");
+        let yaml_spec = dir.path().join("spec.yaml");
+        write(
+            &json_spec,
+            r#"{
+                "args": { "count": 3 },
+                "inputs": { "in.data": { "file": "data.json" } },
+                "outputs": { "out.data": { "json": [1, 2] } },
+                "exitCode": 0
+            }"#,
+        )
+        .await
+        .unwrap();
+        write(
+            &yaml_spec,
+            "name: yaml\ninputs:\n  in.data: text\noutputs:\n  out.data:\n    \
+             regex: '^\\d+$'\ntimeout: 5\n",
+        )
+        .await
+        .unwrap();
+
+        let spec = load_spec(&json_spec).await.unwrap();
+        assert_eq!(spec.args["count"], json!(3));
+        assert!(matches!(
+            &spec.inputs["in.data"],
+            Data::File { file } if file == Path::new("data.json")
+        ));
+        assert!(matches!(
+            &spec.outputs["out.data"],
+            Expected::Json(value) if value == &json!([1, 2])
+        ));
+        assert_eq!(spec.exit_code, Some(0));
+        assert_eq!(spec.timeout, 30);
+
+        let spec = load_spec(&yaml_spec).await.unwrap();
+        assert_eq!(spec.name.as_deref(), Some("yaml"));
+        assert!(matches!(
+            &spec.inputs["in.data"],
+            Data::Text(text) if text == "text"
+        ));
+        assert!(matches!(
+            &spec.outputs["out.data"],
+            Expected::Regex(pattern) if pattern == "^\\d+$"
+        ));
+        assert_eq!(spec.timeout, 5);
+
+        write(
+            &json_spec,
+            r#"{ "outputs": { "out.data": { "xml": "" } } }"#,
+        )
+        .await
+        .unwrap();
+        assert!(load_spec(&json_spec).await.is_err());
+        assert!(load_spec(&dir.path().join("missing.json")).await.is_err());
+    }
+}
@@ -1,15 +1,63 @@
+use async_std::fs::read_to_string;
+
+use super::lock::{check_plan, lock_path};
 use crate::channel::Channel;
+use crate::plan::Steps;
 use crate::runner::Runner;
 
 /// Validate configureations for runners and channels
 #[derive(clap::Args, Debug)]
-pub struct Command {}
+pub struct Command {
+    /// Pipeline file to check against the runners and channels, and against
+    /// its lockfile when present
+    file:   Option<String>,
+    /// Fail when the step, runner or channel files differ from the lockfile
+    #[clap(long, requires = "file")]
+    locked: bool,
+}
 impl Command {
     pub async fn execute(&self, channels: Vec<Channel>, runners: Vec<Runner>) {
-        let pretty = serde_json::to_string_pretty(&channels).unwrap();
-        println!("channels\n{}", pretty);
-        // things are valid
-        let pretty = serde_json::to_string_pretty(&runners).unwrap();
-        println!("runners\n{}", pretty);
+        let file = match &self.file {
+            Some(file) => file,
+            None => {
+                let pretty = serde_json::to_string_pretty(&channels).unwrap();
+                println!("channels\n{}", pretty);
+                // things are valid
+                let pretty = serde_json::to_string_pretty(&runners).unwrap();
+                println!("runners\n{}", pretty);
+                return;
+            }
+        };
+
+        let plan: Steps = match read_to_string(file)
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|c| serde_json::from_str(&c).map_err(|e| e.to_string()))
+        {
+            Ok(plan) => plan,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", file, e);
+                return;
+            }
+        };
+
+        let (errors, warnings) =
+            check_plan(file, &plan, &runners, &channels, self.locked).await;
+        for warning in &warnings {
+            println!("Warning: {}", warning);
+        }
+        for error in &errors {
+            println!("Error: {}", error);
+        }
+
+        if errors.is_empty() && warnings.is_empty() {
+            if lock_path(file).exists() {
+                println!("{} is valid and matches its lockfile", file);
+            } else {
+                println!("{} is valid", file);
+            }
+        } else if !errors.is_empty() {
+            std::process::exit(1);
+        }
     }
 }
@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use async_std::fs::read_to_string;
+
+use super::lock::{check_plan, Lock};
+use super::mock;
+use crate::channel::Channel;
+use crate::exec::cache::hash_file;
+use crate::plan::Steps;
+use crate::runner::{parse_runner_files, Runner};
+use crate::step::parse_step;
+
+/// A new version of the plan, after its files changed
+pub(crate) struct Reload {
+    pub plan:    Steps,
+    pub runners: Vec<Runner>,
+    pub changed: Vec<PathBuf>,
+}
+
+/// The plan file, step files and runner files of a plan, as polled by `run
+/// --watch`
+pub(crate) struct Watcher {
+    plan:    PathBuf,
+    /// Step files per step id, from the lockfile of the plan
+    steps:   BTreeMap<String, PathBuf>,
+    runners: Vec<PathBuf>,
+    /// The hash of every file when it was last seen, `None` when it is missing
+    hashes:  HashMap<PathBuf, Option<String>>,
+}
+
+/// The step files in the lockfile of `plan`
+async fn locked_steps(plan: &Path) -> BTreeMap<String, PathBuf> {
+    match Lock::load(plan).await {
+        Some(Ok(lock)) => {
+            lock.steps
+                .into_iter()
+                .filter_map(|(id, locked)| Some((id, locked.file?)))
+                .collect()
+        }
+        _ => BTreeMap::new(),
+    }
+}
+
+impl Watcher {
+    pub async fn new<P: AsRef<Path>>(plan: P, runners: &[Runner]) -> Self {
+        let plan = plan.as_ref().to_path_buf();
+        let mut watcher = Self {
+            steps: locked_steps(&plan).await,
+            plan,
+            runners: runners.iter().filter_map(|r| r.file.clone()).collect(),
+            hashes: HashMap::new(),
+        };
+        watcher.changed().await;
+        watcher
+    }
+
+    /// Whether the step files are known, they are taken from the lockfile
+    pub fn has_step_files(&self) -> bool {
+        !self.steps.is_empty()
+    }
+
+    /// The files that changed since the last call
+    async fn changed(&mut self) -> Vec<PathBuf> {
+        let files = std::iter::once(&self.plan)
+            .chain(self.steps.values())
+            .chain(&self.runners)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut changed = Vec::new();
+        for file in files {
+            let hash = hash_file(&file).await;
+            if self.hashes.get(&file) != Some(&hash) {
+                if self.hashes.contains_key(&file) {
+                    changed.push(file.clone());
+                }
+                self.hashes.insert(file, hash);
+            }
+        }
+        changed
+    }
+
+    /// Checks the files, returning the new version of `plan` when one of them
+    /// changed. A changed plan file replaces the plan, a changed step file
+    /// replaces its step in it.
+    pub async fn poll(
+        &mut self,
+        plan: &Steps,
+        channels: &[Channel],
+    ) -> Option<Result<Reload, Vec<String>>> {
+        let changed = self.changed().await;
+        if changed.is_empty() {
+            return None;
+        }
+
+        let mut plan = plan.clone();
+        if changed.contains(&self.plan) {
+            let content = match read_to_string(&self.plan).await {
+                Ok(content) => content,
+                Err(e) => {
+                    return Some(Err(vec![format!(
+                        "Failed to read {}: {}",
+                        self.plan.display(),
+                        e
+                    )]))
+                }
+            };
+            plan = match Steps::from_json(&content) {
+                Ok(plan) => plan,
+                Err(e) => {
+                    return Some(Err(vec![format!(
+                        "Failed to parse {}: {}",
+                        self.plan.display(),
+                        e
+                    )]))
+                }
+            };
+
+            // The plan may be generated again with other steps
+            self.steps = locked_steps(&self.plan).await;
+            self.changed().await;
+        }
+
+        let mut runners =
+            parse_runner_files(self.runners.iter().cloned(), channels).await;
+        mock::add_runner(&mut runners, channels);
+
+        let mut errors = Vec::new();
+        for (id, file) in &self.steps {
+            let in_plan =
+                plan.steps.iter().any(|r| &r.processor_config.id == id);
+            if !in_plan || !changed.contains(file) {
+                continue;
+            }
+
+            match parse_step(file).await {
+                Ok(mut step) => {
+                    step.id = id.clone();
+                    if let Err(e) = plan.replace_step(&step, &runners, channels)
+                    {
+                        errors.extend(e);
+                    }
+                }
+                Err(e) => {
+                    errors.push(format!(
+                        "Failed to parse step {}: {}",
+                        file.display(),
+                        e
+                    ))
+                }
+            }
+        }
+
+        let (check, _) =
+            check_plan(&self.plan, &plan, &runners, channels, false).await;
+        errors.extend(check);
+
+        if errors.is_empty() {
+            Some(Ok(Reload {
+                plan,
+                runners,
+                changed,
+            }))
+        } else {
+            Some(Err(errors))
+        }
+    }
+}
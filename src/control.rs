@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+
+/// Environment variable with the path of the control socket of a step
+pub const CONTROL_ENV: &str = "NAUTIRUST_CONTROL";
+/// Environment variable with the id of the step
+pub const STEP_ENV: &str = "NAUTIRUST_STEP";
+
+/// A message a step sends on its control channel, one JSON object per line
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Message {
+    /// The step is ready to process data, steps after it in the plan with
+    /// `waitReady` are started now
+    Ready,
+    /// How far the step is, between 0 and 100
+    Progress {
+        percent: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+    /// Something went wrong without the step failing
+    Warning { message: String },
+    /// A value of a metric of the step itself
+    Metric { name: String, value: f64 },
+    /// The step finished its work, it can still take a while to exit
+    Done {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        summary: Option<String>,
+    },
+}
+
+#[cfg(feature = "io")]
+pub use io::*;
+
+#[cfg(feature = "io")]
+mod io {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::path::Path;
+
+    use super::{Message, CONTROL_ENV};
+
+    /// Sends messages to nautirust from inside a step
+    pub struct Client {
+        stream: UnixStream,
+    }
+
+    impl Client {
+        pub fn connect<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+            Ok(Self {
+                stream: UnixStream::connect(path)?,
+            })
+        }
+
+        /// Connects to the socket in `NAUTIRUST_CONTROL`, `None` when the step
+        /// is not started by `nautirust run`
+        pub fn from_env() -> Option<std::io::Result<Self>> {
+            let path = std::env::var_os(CONTROL_ENV)?;
+            Some(Self::connect(path))
+        }
+
+        pub fn send(&mut self, message: &Message) -> std::io::Result<()> {
+            let line = serde_json::to_string(message)?;
+            writeln!(self.stream, "{}", line)
+        }
+
+        pub fn ready(&mut self) -> std::io::Result<()> {
+            self.send(&Message::Ready)
+        }
+
+        pub fn progress(
+            &mut self,
+            percent: f64,
+            message: Option<&str>,
+        ) -> std::io::Result<()> {
+            self.send(&Message::Progress {
+                percent,
+                message: message.map(String::from),
+            })
+        }
+
+        pub fn warning(&mut self, message: &str) -> std::io::Result<()> {
+            self.send(&Message::Warning {
+                message: message.to_string(),
+            })
+        }
+
+        pub fn metric(
+            &mut self,
+            name: &str,
+            value: f64,
+        ) -> std::io::Result<()> {
+            self.send(&Message::Metric {
+                name: name.to_string(),
+                value,
+            })
+        }
+
+        pub fn done(&mut self, summary: Option<&str>) -> std::io::Result<()> {
+            self.send(&Message::Done {
+                summary: summary.map(String::from),
+            })
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+
+use async_std::fs::{self, read, read_to_string, write};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Hex encoded sha256 of `bytes`
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub async fn hash_file<P: AsRef<Path>>(path: P) -> Option<String> {
+    read(path.as_ref())
+        .await
+        .ok()
+        .map(|bytes| hash_bytes(&bytes))
+}
+
+/// Replaces the path of a resolved `File` argument with the hash of its
+/// content, so the key does not depend on where the file was written
+pub async fn file_arg_to_hash(value: Value) -> Value {
+    let path = match (value.get("type"), value.get("path")) {
+        (Some(Value::String(ty)), Some(Value::String(path)))
+            if ty == "File" =>
+        {
+            path.clone()
+        }
+        _ => return value,
+    };
+
+    let mut value = value;
+    if let Some(hash) = hash_file(&path).await {
+        value["path"] = Value::String(format!("sha256:{}", hash));
+    }
+    value
+}
+
+/// Content-addressed store of sub-step outputs
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_owned(),
+        }
+    }
+
+    fn entry(&self, key: &str) -> PathBuf {
+        self.dir.join(key)
+    }
+
+    pub async fn get(&self, key: &str) -> Option<String> {
+        read_to_string(self.entry(key)).await.ok()
+    }
+
+    pub async fn put(&self, key: &str, content: &str) -> std::io::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        write(self.entry(key), content).await
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_std::io::prelude::BufReadExt;
+use async_std::io::BufReader;
+use async_std::os::unix::net::UnixListener;
+use async_std::stream::StreamExt;
+use async_std::task::{self, JoinHandle};
+
+use super::{Event, Events};
+use crate::control::Message;
+
+/// Steps that sent `ready` since they were last started
+pub(crate) type Ready = Arc<Mutex<HashSet<String>>>;
+
+/// Listens on the control socket of `step` at `path`, turning the messages
+/// into events
+pub(crate) fn listen(
+    path: &Path,
+    step: &str,
+    events: &Events,
+    ready: &Ready,
+) -> std::io::Result<JoinHandle<()>> {
+    // A socket left behind by an earlier run of the step
+    let _ = std::fs::remove_file(path);
+    let listener =
+        UnixListener::from(std::os::unix::net::UnixListener::bind(path)?);
+
+    let step = step.to_string();
+    let events = events.clone();
+    let ready = ready.clone();
+
+    Ok(task::spawn(async move {
+        let mut incoming = listener.incoming();
+        while let Some(Ok(stream)) = incoming.next().await {
+            let step = step.clone();
+            let events = events.clone();
+            let ready = ready.clone();
+
+            task::spawn(async move {
+                let mut lines = BufReader::new(stream).lines();
+                while let Some(Ok(line)) = lines.next().await {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+
+                    let message =
+                        serde_json::from_str(&line).unwrap_or_else(|e| {
+                            Message::Warning {
+                                message: format!(
+                                    "Invalid control message '{}': {}",
+                                    line, e
+                                ),
+                            }
+                        });
+                    if message == Message::Ready {
+                        if let Ok(mut ready) = ready.lock() {
+                            ready.insert(step.clone());
+                        }
+                    }
+                    events.emit(Event::Control {
+                        step: step.clone(),
+                        message,
+                    });
+                }
+            });
+        }
+    }))
+}
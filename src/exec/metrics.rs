@@ -0,0 +1,429 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use async_std::io::{ReadExt, WriteExt};
+use async_std::net::TcpListener;
+use async_std::task;
+
+use super::{Event, ProcessKind, Stream};
+use crate::control::Message;
+
+/// What is known of one step or sub-step
+#[derive(Debug, Clone)]
+struct StepMetrics {
+    kind:          ProcessKind,
+    /// Process group of the current process and when it started
+    running:       Option<(u32, Instant)>,
+    restarts:      u32,
+    last_exit:     Option<i32>,
+    /// Exits per exit code, -1 when the process was killed by a signal
+    exits:         BTreeMap<i32, u64>,
+    stdout_lines:  u64,
+    stderr_lines:  u64,
+    /// Lines per second of stdout and stderr over the last sample
+    stdout_rate:   f64,
+    stderr_rate:   f64,
+    /// Line counts at the last sample
+    sampled_lines: (u64, u64),
+    /// CPU time of the earlier processes of the step and of the current one
+    cpu_done:      f64,
+    cpu_current:   f64,
+    rss_bytes:     u64,
+    /// Reported by the step on its control channel
+    progress:      Option<f64>,
+    custom:        BTreeMap<String, f64>,
+}
+
+impl StepMetrics {
+    fn new(kind: ProcessKind) -> Self {
+        Self {
+            kind,
+            running: None,
+            restarts: 0,
+            last_exit: None,
+            exits: BTreeMap::new(),
+            stdout_lines: 0,
+            stderr_lines: 0,
+            stdout_rate: 0.0,
+            stderr_rate: 0.0,
+            sampled_lines: (0, 0),
+            cpu_done: 0.0,
+            cpu_current: 0.0,
+            rss_bytes: 0,
+            progress: None,
+            custom: BTreeMap::new(),
+        }
+    }
+}
+
+/// CPU time in seconds and resident memory in bytes of every process in the
+/// process group `pgid`, read from `/proc`
+fn sample_group(pgid: u32) -> (f64, u64) {
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as f64;
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return (0.0, 0),
+    };
+
+    let mut cpu = 0.0;
+    let mut rss = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if !name.to_string_lossy().chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+
+        // The command name can contain spaces, the fields start after it
+        let fields = match stat.rfind(')') {
+            Some(end) => stat[end + 1..].split_whitespace().collect::<Vec<_>>(),
+            None => continue,
+        };
+        let field =
+            |n: usize| fields.get(n).and_then(|f| f.parse::<u64>().ok());
+
+        // Numbered from the state, the third field of the file
+        if field(2) != Some(pgid as u64) {
+            continue;
+        }
+        let (utime, stime) = (field(11), field(12));
+        cpu += (utime.unwrap_or(0) + stime.unwrap_or(0)) as f64 / ticks;
+        rss += field(21).unwrap_or(0) * page;
+    }
+
+    (cpu, rss)
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Metrics of the steps of a running pipeline, updated from its events and
+/// by sampling the step processes
+#[derive(Debug, Default)]
+pub struct Metrics {
+    steps:       BTreeMap<String, StepMetrics>,
+    last_sample: Option<Instant>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the metrics with an event of the pipeline
+    pub fn record(&mut self, event: &Event) {
+        match event {
+            Event::Started {
+                step, kind, pid, ..
+            } => {
+                let metrics = self
+                    .steps
+                    .entry(step.clone())
+                    .or_insert_with(|| StepMetrics::new(*kind));
+                metrics.running = Some((*pid, Instant::now()));
+            }
+            Event::Exited(exit) => {
+                if let Some(metrics) = self.steps.get_mut(&exit.step) {
+                    let code = exit.exit_code.unwrap_or(-1);
+                    metrics.running = None;
+                    metrics.last_exit = Some(code);
+                    *metrics.exits.entry(code).or_default() += 1;
+                    metrics.cpu_done += metrics.cpu_current;
+                    metrics.cpu_current = 0.0;
+                    metrics.rss_bytes = 0;
+                }
+            }
+            Event::Restarted { step, restarts } => {
+                if let Some(metrics) = self.steps.get_mut(step) {
+                    metrics.restarts = *restarts;
+                }
+            }
+            Event::Control { step, message } => {
+                if let Some(metrics) = self.steps.get_mut(step) {
+                    match message {
+                        Message::Progress { percent, .. } => {
+                            metrics.progress = Some(*percent)
+                        }
+                        Message::Done { .. } => metrics.progress = Some(100.0),
+                        Message::Metric { name, value } => {
+                            metrics.custom.insert(name.clone(), *value);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Event::Log { step, stream, .. } => {
+                if let Some(metrics) = self.steps.get_mut(step) {
+                    match stream {
+                        Stream::Stdout => metrics.stdout_lines += 1,
+                        Stream::Stderr => metrics.stderr_lines += 1,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Samples CPU time and memory of the running steps and the log line
+    /// rates since the previous sample
+    pub fn sample(&mut self) {
+        let now = Instant::now();
+        let elapsed = self
+            .last_sample
+            .map(|last| now.duration_since(last).as_secs_f64())
+            .filter(|secs| *secs > 0.0);
+        self.last_sample = Some(now);
+
+        for metrics in self.steps.values_mut() {
+            let (out, err) = metrics.sampled_lines;
+            if let Some(secs) = elapsed {
+                metrics.stdout_rate =
+                    (metrics.stdout_lines - out) as f64 / secs;
+                metrics.stderr_rate =
+                    (metrics.stderr_lines - err) as f64 / secs;
+            }
+            metrics.sampled_lines =
+                (metrics.stdout_lines, metrics.stderr_lines);
+
+            if let Some((pid, _)) = metrics.running {
+                let (cpu, rss) = sample_group(pid);
+                metrics.cpu_current = cpu;
+                metrics.rss_bytes = rss;
+            }
+        }
+    }
+
+    /// The metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric =
+            |name: &str,
+             ty: &str,
+             help: &str,
+             values: Vec<(String, String)>| {
+                let _ = writeln!(out, "# HELP nautirust_{} {}", name, help);
+                let _ = writeln!(out, "# TYPE nautirust_{} {}", name, ty);
+                for (labels, value) in values {
+                    let _ = writeln!(
+                        out,
+                        "nautirust_{}{{{}}} {}",
+                        name, labels, value
+                    );
+                }
+            };
+
+        let per_step = |f: &dyn Fn(&StepMetrics) -> String| {
+            self.steps
+                .iter()
+                .map(|(id, m)| (format!("step=\"{}\"", escape(id)), f(m)))
+                .collect::<Vec<_>>()
+        };
+
+        metric(
+            "step_up",
+            "gauge",
+            "Whether the process of the step is running",
+            self.steps
+                .iter()
+                .map(|(id, m)| {
+                    let kind = match m.kind {
+                        ProcessKind::Step => "step",
+                        ProcessKind::SubStep => "subStep",
+                    };
+                    (
+                        format!("step=\"{}\",kind=\"{}\"", escape(id), kind),
+                        u8::from(m.running.is_some()).to_string(),
+                    )
+                })
+                .collect(),
+        );
+        metric(
+            "step_uptime_seconds",
+            "gauge",
+            "Seconds since the current process of the step started",
+            per_step(&|m| {
+                let uptime = m.running.map(|(_, started)| started.elapsed());
+                format!("{:.3}", uptime.unwrap_or_default().as_secs_f64())
+            }),
+        );
+        metric(
+            "step_restarts_total",
+            "counter",
+            "Times the step was restarted",
+            per_step(&|m| m.restarts.to_string()),
+        );
+        metric(
+            "step_last_exit_code",
+            "gauge",
+            "Exit code of the last exit of the step, -1 when killed",
+            self.steps
+                .iter()
+                .filter_map(|(id, m)| {
+                    let code = m.last_exit?;
+                    Some((format!("step=\"{}\"", escape(id)), code.to_string()))
+                })
+                .collect(),
+        );
+        metric(
+            "step_exits_total",
+            "counter",
+            "Exits of the step per exit code, -1 when killed",
+            self.steps
+                .iter()
+                .flat_map(|(id, m)| {
+                    m.exits.iter().map(move |(code, count)| {
+                        (
+                            format!(
+                                "step=\"{}\",code=\"{}\"",
+                                escape(id),
+                                code
+                            ),
+                            count.to_string(),
+                        )
+                    })
+                })
+                .collect(),
+        );
+
+        let per_stream = |f: &dyn Fn(&StepMetrics, Stream) -> String| {
+            self.steps
+                .iter()
+                .flat_map(|(id, m)| {
+                    [("stdout", Stream::Stdout), ("stderr", Stream::Stderr)]
+                        .into_iter()
+                        .map(move |(name, stream)| {
+                            (
+                                format!(
+                                    "step=\"{}\",stream=\"{}\"",
+                                    escape(id),
+                                    name
+                                ),
+                                f(m, stream),
+                            )
+                        })
+                })
+                .collect::<Vec<_>>()
+        };
+        metric(
+            "step_log_lines_total",
+            "counter",
+            "Lines written by the step",
+            per_stream(&|m, stream| {
+                match stream {
+                    Stream::Stdout => m.stdout_lines.to_string(),
+                    Stream::Stderr => m.stderr_lines.to_string(),
+                }
+            }),
+        );
+        metric(
+            "step_log_lines_per_second",
+            "gauge",
+            "Lines written by the step per second since the previous sample",
+            per_stream(&|m, stream| {
+                match stream {
+                    Stream::Stdout => format!("{:.3}", m.stdout_rate),
+                    Stream::Stderr => format!("{:.3}", m.stderr_rate),
+                }
+            }),
+        );
+        metric(
+            "step_cpu_seconds_total",
+            "counter",
+            "CPU time used by the process group of the step",
+            per_step(&|m| format!("{:.2}", m.cpu_done + m.cpu_current)),
+        );
+        metric(
+            "step_resident_memory_bytes",
+            "gauge",
+            "Resident memory of the process group of the step",
+            per_step(&|m| m.rss_bytes.to_string()),
+        );
+
+        metric(
+            "step_progress_percent",
+            "gauge",
+            "Progress the step reported on its control channel",
+            self.steps
+                .iter()
+                .filter_map(|(id, m)| {
+                    let progress = m.progress?;
+                    Some((
+                        format!("step=\"{}\"", escape(id)),
+                        progress.to_string(),
+                    ))
+                })
+                .collect(),
+        );
+        metric(
+            "step_metric",
+            "gauge",
+            "Metrics the step reported on its control channel",
+            self.steps
+                .iter()
+                .flat_map(|(id, m)| {
+                    m.custom.iter().map(move |(name, value)| {
+                        (
+                            format!(
+                                "step=\"{}\",name=\"{}\"",
+                                escape(id),
+                                escape(name)
+                            ),
+                            value.to_string(),
+                        )
+                    })
+                })
+                .collect(),
+        );
+
+        out
+    }
+}
+
+/// Answers requests for `/metrics` on `port` of localhost, until the task is
+/// dropped
+pub async fn serve(
+    metrics: Arc<Mutex<Metrics>>,
+    port: u16,
+) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let metrics = metrics.clone();
+
+        task::spawn(async move {
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+            let (status, body) = if path == "/metrics" {
+                let body =
+                    metrics.lock().map(|m| m.render()).unwrap_or_default();
+                ("200 OK", body)
+            } else {
+                ("404 Not Found", String::from("Not found\n"))
+            };
+
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
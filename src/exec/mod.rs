@@ -0,0 +1,1378 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_recursion::async_recursion;
+use async_std::channel::{unbounded, Receiver, Sender};
+use async_std::fs::{self, write};
+use async_std::task::{self, JoinHandle};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use self::cache::Cache;
+use self::control::Ready;
+use self::process::{Running, Termination};
+use crate::channel::{create_pipe, remove_pipe, ChannelConfig, PIPES_DIR};
+use crate::control::{Message, CONTROL_ENV, STEP_ENV};
+use crate::plan::Steps;
+use crate::runner::Runner;
+use crate::step::{Output, RunThing, Step, StepArgument, SubStep};
+
+pub mod cache;
+mod control;
+pub mod metrics;
+mod process;
+
+pub use self::process::{is_alive, signal_group};
+
+/// Values of the params of a plan
+pub type Params = HashMap<String, Value>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ProcessKind {
+    Step,
+    SubStep,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Whether a step is started again after it exited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Restart {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+/// How a pipeline is run
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Directory for the step configuration files, sub-step outputs and logs
+    pub run_dir:       PathBuf,
+    pub params:        Params,
+    /// Directory of the sub-step output cache, sub-steps are always run
+    /// without one
+    pub cache_dir:     Option<PathBuf>,
+    pub restart:       Restart,
+    /// Time between the exit of a step and its restart
+    pub restart_delay: Duration,
+    /// Keep the pipeline running when every process exited, until `stop`, so
+    /// steps can still be restarted or added
+    pub keep_running:  bool,
+}
+
+impl Options {
+    pub fn new<P: AsRef<Path>>(run_dir: P) -> Self {
+        Self {
+            run_dir:       run_dir.as_ref().to_path_buf(),
+            params:        Params::new(),
+            cache_dir:     None,
+            restart:       Restart::Never,
+            restart_delay: Duration::from_secs(1),
+            keep_running:  false,
+        }
+    }
+}
+
+/// How a step or sub-step process exited
+#[derive(Debug, Clone)]
+pub struct Exit {
+    pub step:      String,
+    pub kind:      ProcessKind,
+    pub exit_code: Option<i32>,
+    /// Why the process stopped, when it did not exit by itself
+    pub reason:    Option<String>,
+}
+
+impl Exit {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A step or sub-step process is started
+    Started {
+        step:   String,
+        kind:   ProcessKind,
+        runner: String,
+        pid:    u32,
+        config: PathBuf,
+        log:    PathBuf,
+    },
+    /// A line written by a process
+    Log {
+        step:   String,
+        stream: Stream,
+        line:   String,
+    },
+    /// The output of a sub-step is taken from the cache
+    Cached {
+        step: String,
+    },
+    Exited(Exit),
+    /// A step is started again, `Started` follows
+    Restarted {
+        step:     String,
+        restarts: u32,
+    },
+    /// A message a step sent on its control channel
+    Control {
+        step:    String,
+        message: Message,
+    },
+    /// A step could not be started
+    Error {
+        step:    String,
+        message: String,
+    },
+    /// Something the steps share, like the run directory or the pipes of the
+    /// channels, could not be set up
+    PipelineError {
+        message: String,
+    },
+    /// Every process exited, this is the last event
+    Finished,
+}
+
+/// Hands the events to every subscriber
+#[derive(Clone, Default)]
+pub(crate) struct Events {
+    subscribers: Arc<Mutex<Vec<Sender<Event>>>>,
+}
+
+impl Events {
+    fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = unbounded();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        receiver
+    }
+
+    pub(crate) fn emit(&self, event: Event) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|s| s.try_send(event.clone()).is_ok());
+        }
+    }
+}
+
+/// The configuration file handed to a runner
+#[derive(Serialize)]
+struct SimpleRun<'a> {
+    #[serde(rename = "processorConfig")]
+    pub processor_config: &'a Step,
+    args:                 HashMap<String, Value>,
+}
+
+/// Name of the file a sub-step writes its output to
+fn sub_output_name(sub: &SubStep) -> String {
+    let terminator = match sub.output {
+        Output::Stdout => ".stdout",
+        Output::Stderr => ".stderr",
+    };
+    format!("{}{}", sub.run.processor_config.id, terminator)
+}
+
+/// The arguments handed to a runner, files of the run are expected at
+/// `run_file(name)`: the output of a sub-step at `run_file("<id>.stdout")` (or
+/// `.stderr`) and `fifo` and `unix-socket` channels with a relative path in
+/// `run_file("channels")`
+fn resolve_args(
+    run: &RunThing,
+    params: &Params,
+    run_file: &dyn Fn(&str) -> String,
+) -> Result<HashMap<String, Value>, String> {
+    let resolve_pipes = |fields: &HashMap<String, ChannelConfig>| {
+        fields
+            .iter()
+            .map(|(field, config)| {
+                (field.clone(), config.resolve_pipe(run_file))
+            })
+            .collect()
+    };
+
+    let mut args = HashMap::new();
+    for (k, v) in &run.args {
+        let value = match v {
+            StepArgument::Step { sub } => {
+                let out = StepArgument::File {
+                    path:          run_file(&sub_output_name(sub)),
+                    serialization: sub.serialization.clone(),
+                };
+                serde_json::to_value(out).map_err(|e| e.to_string())?
+            }
+            StepArgument::Param { name } => {
+                params
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| format!("Param {} not found", name))?
+            }
+            StepArgument::StreamReader { fields } => {
+                let arg = StepArgument::StreamReader {
+                    fields: resolve_pipes(fields),
+                };
+                serde_json::to_value(arg).map_err(|e| e.to_string())?
+            }
+            StepArgument::StreamWriter { fields } => {
+                let arg = StepArgument::StreamWriter {
+                    fields: resolve_pipes(fields),
+                };
+                serde_json::to_value(arg).map_err(|e| e.to_string())?
+            }
+            arg => serde_json::to_value(arg).map_err(|e| e.to_string())?,
+        };
+        args.insert(k.clone(), value);
+    }
+    Ok(args)
+}
+
+/// Renders the configuration a runner gets for a step, without starting
+/// anything, files of the run are expected at `run_file(name)`, see
+/// `resolve_args`
+pub fn render_config(
+    run: &RunThing,
+    params: &Params,
+    run_file: &dyn Fn(&str) -> String,
+) -> Option<String> {
+    let run = SimpleRun {
+        processor_config: &run.processor_config,
+        args:             resolve_args(run, params, run_file).ok()?,
+    };
+    serde_json::to_string_pretty(&run).ok()
+}
+
+/// The command starting `runner` with the configuration file `config`
+pub fn runner_command(runner: &Runner, config: &Path) -> String {
+    let config_path = format!(
+        "'{}'",
+        config
+            .canonicalize()
+            .unwrap_or_else(|_| config.to_path_buf())
+            .display()
+    );
+
+    let current_dir = format!(
+        "'{}'",
+        env::current_dir()
+            .and_then(|d| d.canonicalize())
+            .unwrap_or_default()
+            .display()
+    );
+
+    runner
+        .script
+        .replace("{config}", &config_path)
+        .replace("{cwd}", &current_dir)
+}
+
+/// Expands a leading `~` to the home directory
+pub(crate) fn expand_tilde<P: AsRef<Path>>(
+    path_user_input: P,
+) -> Option<PathBuf> {
+    let p = path_user_input.as_ref();
+    if !p.starts_with("~") {
+        return Some(p.to_path_buf());
+    }
+    if p == Path::new("~") {
+        return dirs::home_dir();
+    }
+
+    let p = p.strip_prefix("~/").unwrap();
+
+    dirs::home_dir().map(|mut h| {
+        if h == Path::new("/") {
+            // Corner case: `h` root directory;
+            // don't prepend extra `/`, just drop the tilde.
+            p.to_path_buf()
+        } else {
+            h.push(p);
+            h
+        }
+    })
+}
+
+/// A step waiting to be started again
+struct PendingRestart {
+    at:    Instant,
+    index: usize,
+}
+
+/// Asked of a running pipeline through its handle
+enum Request {
+    Stop(String),
+    Restart(String),
+    /// Restart a step with a new definition of it
+    Replace(Box<RunThing>),
+    /// Run a new version of the plan
+    Update(Box<Steps>, Vec<Runner>),
+}
+
+/// The processes of a running pipeline and what happens when they exit
+#[derive(Default)]
+struct Supervised {
+    /// (index in the plan, process)
+    procs:      Vec<(usize, Running)>,
+    pending:    Vec<PendingRestart>,
+    /// Times each step was restarted
+    restarts:   Vec<u32>,
+    /// Steps stopped on request, they are not restarted when they exit
+    stopped:    HashSet<usize>,
+    /// Steps to start again as soon as they exit
+    restarting: HashSet<usize>,
+    /// Steps that are no longer in the plan
+    removed:    HashSet<usize>,
+}
+
+impl Supervised {
+    /// Terminates step `index`, it is not restarted
+    fn stop(&mut self, index: usize) {
+        self.pending.retain(|p| p.index != index);
+        self.restarting.remove(&index);
+        if let Some((_, running)) =
+            self.procs.iter_mut().find(|(i, _)| *i == index)
+        {
+            self.stopped.insert(index);
+            running.terminate(Termination::Stopped);
+        }
+    }
+
+    /// Terminates step `index` and starts it again, or starts it when it is
+    /// not running
+    fn restart(&mut self, index: usize) {
+        self.pending.retain(|p| p.index != index);
+        self.stopped.remove(&index);
+        match self.procs.iter_mut().find(|(i, _)| *i == index) {
+            Some((_, running)) => {
+                self.restarting.insert(index);
+                running.terminate(Termination::Restarted);
+            }
+            None => {
+                self.pending.push(PendingRestart {
+                    at: Instant::now(),
+                    index,
+                })
+            }
+        }
+    }
+}
+
+struct Engine {
+    steps:       Vec<RunThing>,
+    runners:     Vec<Runner>,
+    options:     Options,
+    events:      Events,
+    stop:        Arc<AtomicBool>,
+    cache:       Option<Cache>,
+    /// Sub-steps whose output is written
+    sub_outputs: HashSet<String>,
+    exits:       Vec<Exit>,
+    /// Tasks listening on the control sockets, per step
+    control:     HashMap<String, JoinHandle<()>>,
+    ready:       Ready,
+    requests:    Receiver<Request>,
+}
+
+impl Engine {
+    fn stopping(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    fn get_runner(&self, id: &str) -> Result<&Runner, String> {
+        self.runners
+            .iter()
+            .find(|r| r.id == id)
+            .ok_or_else(|| format!("No runner found for id {}", id))
+    }
+
+    fn get_run_file(&self, name: &str) -> PathBuf {
+        self.options.run_dir.join(name)
+    }
+
+    fn get_log_file(&self, id: &str) -> PathBuf {
+        self.options
+            .run_dir
+            .join("logs")
+            .join(format!("{}.log", id))
+    }
+
+    fn get_control_socket(&self, id: &str) -> PathBuf {
+        self.options
+            .run_dir
+            .join("control")
+            .join(format!("{}.sock", id))
+    }
+
+    /// Listens on the control socket of `step`, once for all its restarts,
+    /// returning the environment variables that point the step to it
+    fn control_env(&mut self, step: &str) -> Vec<(&'static str, String)> {
+        let mut env = vec![(STEP_ENV, step.to_string())];
+        let path = self.get_control_socket(step);
+
+        if !self.control.contains_key(step) {
+            let listening =
+                std::fs::create_dir_all(self.options.run_dir.join("control"))
+                    .and_then(|_| {
+                        control::listen(&path, step, &self.events, &self.ready)
+                    });
+            match listening {
+                Ok(task) => {
+                    self.control.insert(step.to_string(), task);
+                }
+                Err(e) => {
+                    self.events.emit(Event::Control {
+                        step:    step.to_string(),
+                        message: Message::Warning {
+                            message: format!(
+                                "Failed to create control socket {}: {}",
+                                path.display(),
+                                e
+                            ),
+                        },
+                    });
+                    return env;
+                }
+            }
+        }
+
+        env.push((CONTROL_ENV, path.display().to_string()));
+        env
+    }
+
+    fn sub_output(&self) -> impl Fn(&str) -> String + '_ {
+        |name| self.get_run_file(name).to_string_lossy().to_string()
+    }
+
+    fn exited(
+        &mut self,
+        running: Running,
+        status: Option<std::process::ExitStatus>,
+    ) -> (String, String) {
+        let (exit, stdout, stderr) = running.exited(status);
+        self.events.emit(Event::Exited(exit.clone()));
+        self.exits.push(exit);
+        (stdout, stderr)
+    }
+
+    /// Writes the configuration of `run` and starts its runner
+    fn start(
+        &mut self,
+        run: &RunThing,
+        kind: ProcessKind,
+    ) -> Result<Running, String> {
+        let step = &run.processor_config;
+        let runner = self.get_runner(&step.runner_id)?;
+        let limits = runner.limits.merge(&step.limits);
+
+        let config = SimpleRun {
+            processor_config: step,
+            args:             resolve_args(
+                run,
+                &self.options.params,
+                &self.sub_output(),
+            )?,
+        };
+        let config =
+            serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
+        let config_file = self.get_run_file(&format!("{}.json", step.id));
+        std::fs::write(&config_file, config).map_err(|e| {
+            format!("Failed to write {}: {}", config_file.display(), e)
+        })?;
+
+        let script = runner_command(runner, &config_file);
+        let location = runner.location.as_ref().and_then(expand_tilde);
+        if let Ok(mut ready) = self.ready.lock() {
+            ready.remove(&step.id);
+        }
+        let env = self.control_env(&step.id);
+
+        let log = self.get_log_file(&step.id);
+        let running = Running::start(
+            &script,
+            location,
+            env,
+            &step.id,
+            kind,
+            limits,
+            &log,
+            kind == ProcessKind::SubStep,
+            &self.events,
+        )
+        .map_err(|e| {
+            format!("Failed to start runner {}: {}", step.runner_id, e)
+        })?;
+
+        self.events.emit(Event::Started {
+            step: step.id.clone(),
+            kind,
+            runner: step.runner_id.clone(),
+            pid: running.pid(),
+            config: config_file,
+            log,
+        });
+
+        Ok(running)
+    }
+
+    /// Key of the output of a sub-step, derived from its resolved config, the
+    /// runner and the content of its input files
+    async fn cache_key(&self, sub: &SubStep) -> Option<String> {
+        let run = &sub.run;
+        let mut args = HashMap::new();
+        for (k, v) in
+            resolve_args(run, &self.options.params, &self.sub_output()).ok()?
+        {
+            args.insert(k, cache::file_arg_to_hash(v).await);
+        }
+
+        let runner = self.get_runner(&run.processor_config.runner_id).ok()?;
+        let key = json!({
+            "processorConfig": run.processor_config,
+            "args": args,
+            "runner": runner,
+            "output": sub.output,
+        });
+
+        Some(cache::hash_bytes(&serde_json::to_vec(&key).ok()?))
+    }
+
+    /// Runs the sub-steps of `run`, nested ones first, writing their output to
+    /// the run directory
+    #[async_recursion]
+    async fn sub_steps(&mut self, run: &RunThing) -> Result<(), String> {
+        for arg in run.args.values() {
+            let sub = match arg {
+                StepArgument::Step { sub } => sub,
+                _ => continue,
+            };
+            let id = &sub.run.processor_config.id;
+            if self.sub_outputs.contains(id) {
+                continue;
+            }
+
+            self.sub_steps(&sub.run).await?;
+            if self.stopping() {
+                return Err(String::from("interrupted"));
+            }
+
+            let path = self.get_run_file(&sub_output_name(sub));
+            let key = match &self.cache {
+                Some(_) if sub.cache => self.cache_key(sub).await,
+                _ => None,
+            };
+            let cached = match (&self.cache, &key) {
+                (Some(cache), Some(key)) => cache.get(key).await,
+                _ => None,
+            };
+
+            if let Some(content) = cached {
+                self.events.emit(Event::Cached { step: id.clone() });
+                write(&path, content).await.map_err(|e| e.to_string())?;
+            } else {
+                let mut running = self.start(&sub.run, ProcessKind::SubStep)?;
+                let status = loop {
+                    if self.stopping() {
+                        running.terminate(Termination::Interrupted);
+                    }
+                    if let Some(status) = running.poll() {
+                        break status;
+                    }
+                    task::sleep(Duration::from_millis(100)).await;
+                };
+                let (stdout, stderr) = self.exited(running, status);
+
+                let content = match sub.output {
+                    Output::Stdout => stdout,
+                    Output::Stderr => stderr,
+                };
+
+                let success = status.map(|s| s.success());
+                if let (Some(cache), Some(key), Some(true)) =
+                    (&self.cache, &key, success)
+                {
+                    if let Err(e) = cache.put(key, &content).await {
+                        self.events.emit(Event::Error {
+                            step:    id.clone(),
+                            message: format!("Failed to cache output: {}", e),
+                        });
+                    }
+                }
+                write(&path, content).await.map_err(|e| e.to_string())?;
+            }
+
+            self.sub_outputs.insert(id.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Starts step `index`, after its sub-steps
+    async fn start_step(&mut self, index: usize) -> Option<Running> {
+        let run = self.steps[index].clone();
+        let started = match self.sub_steps(&run).await {
+            Ok(()) => self.start(&run, ProcessKind::Step),
+            Err(e) => Err(e),
+        };
+
+        match started {
+            Ok(running) => Some(running),
+            Err(message) => {
+                self.events.emit(Event::Error {
+                    step: run.processor_config.id.clone(),
+                    message,
+                });
+                None
+            }
+        }
+    }
+
+    /// Waits until step `index` sent `ready`, exited or did not get ready in
+    /// its `waitReady` seconds
+    async fn wait_ready(&self, index: usize, running: &mut Running) {
+        let step = &self.steps[index].processor_config;
+        let secs = match step.wait_ready {
+            Some(secs) => secs,
+            None => return,
+        };
+
+        let until = Instant::now() + Duration::from_secs(secs);
+        loop {
+            let ready = self
+                .ready
+                .lock()
+                .map(|ready| ready.contains(&step.id))
+                .unwrap_or(true);
+            if ready || self.stopping() || running.poll().is_some() {
+                return;
+            }
+
+            if Instant::now() >= until {
+                self.events.emit(Event::Control {
+                    step:    step.id.clone(),
+                    message: Message::Warning {
+                        message: format!(
+                            "Not ready after {}s, starting the next steps",
+                            secs
+                        ),
+                    },
+                });
+                return;
+            }
+            task::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Stops listening on the control sockets and removes them
+    async fn close_control(&mut self) {
+        for (step, task) in self.control.drain() {
+            task.cancel().await;
+            let _ = std::fs::remove_file(
+                self.options
+                    .run_dir
+                    .join("control")
+                    .join(format!("{}.sock", step)),
+            );
+        }
+        let _ = std::fs::remove_dir(self.options.run_dir.join("control"));
+    }
+
+    /// Creates the `fifo` and `unix-socket` channels of the steps, see
+    /// `ChannelConfig::pipe_path`
+    fn create_pipes(&self) {
+        let run_file = self.sub_output();
+        let configs = self
+            .steps
+            .iter()
+            .flat_map(|run| run.readers().chain(run.writers()));
+        for config in configs {
+            let path = match config.pipe_path(&run_file) {
+                Ok(Some(path)) => path,
+                Ok(None) => continue,
+                Err(e) => {
+                    self.events.emit(Event::PipelineError { message: e });
+                    continue;
+                }
+            };
+            if let Err(e) = create_pipe(config, &path) {
+                self.events.emit(Event::PipelineError {
+                    message: format!(
+                        "Failed to create channel {}: {}",
+                        path.display(),
+                        e
+                    ),
+                });
+            }
+        }
+    }
+
+    /// Removes the `fifo` and `unix-socket` channels of the steps
+    fn remove_pipes(&self) {
+        let run_file = self.sub_output();
+        let paths = self
+            .steps
+            .iter()
+            .flat_map(|run| run.readers().chain(run.writers()))
+            .filter_map(|config| config.pipe_path(&run_file).ok().flatten());
+        for path in paths {
+            let _ = remove_pipe(&path);
+        }
+        let _ = std::fs::remove_dir(self.options.run_dir.join(PIPES_DIR));
+    }
+
+    fn should_restart(&self, exit: &Exit) -> bool {
+        match self.options.restart {
+            _ if self.stopping() => false,
+            Restart::Never => false,
+            Restart::OnFailure => !exit.success(),
+            Restart::Always => true,
+        }
+    }
+
+    /// The index of `step` in the plan, emits an error when it is not (or no
+    /// longer) in it
+    fn find_step(&self, step: &str, supervised: &Supervised) -> Option<usize> {
+        let index = (0..self.steps.len()).find(|i| {
+            self.steps[*i].processor_config.id == step
+                && !supervised.removed.contains(i)
+        });
+        if index.is_none() {
+            self.events.emit(Event::Error {
+                step:    step.to_string(),
+                message: String::from("No such step in the plan"),
+            });
+        }
+        index
+    }
+
+    /// Uses `run` for step `index` from now on, its sub-steps are run again
+    fn replace(&mut self, index: usize, run: RunThing) {
+        for step in run.steps() {
+            self.sub_outputs.remove(&step.id);
+        }
+        self.steps[index] = run;
+    }
+
+    /// Whether `run` with `runners` differs from step `index` in its
+    /// definition, its arguments or the runners of it and its sub-steps
+    fn changed(
+        &self,
+        index: usize,
+        run: &RunThing,
+        runners: &[Runner],
+    ) -> bool {
+        let runners_of = |runners: &[Runner], run: &RunThing| {
+            run.steps()
+                .into_iter()
+                .map(|step| {
+                    runners
+                        .iter()
+                        .find(|r| r.id == step.runner_id)
+                        .and_then(|r| serde_json::to_value(r).ok())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let old = &self.steps[index];
+        serde_json::to_value(old).ok() != serde_json::to_value(run).ok()
+            || runners_of(&self.runners, old) != runners_of(runners, run)
+    }
+
+    async fn request(&mut self, request: Request, supervised: &mut Supervised) {
+        match request {
+            Request::Stop(step) => {
+                if let Some(index) = self.find_step(&step, supervised) {
+                    supervised.stop(index);
+                }
+            }
+            Request::Restart(step) => {
+                if let Some(index) = self.find_step(&step, supervised) {
+                    supervised.restart(index);
+                }
+            }
+            Request::Replace(run) => {
+                let id = &run.processor_config.id;
+                if let Some(index) = self.find_step(id, supervised) {
+                    self.replace(index, *run);
+                    supervised.restart(index);
+                }
+            }
+            Request::Update(plan, runners) => {
+                self.update(*plan, runners, supervised).await
+            }
+        }
+    }
+
+    /// Runs `plan` with `runners` from now on, see `PipelineHandle::update`
+    async fn update(
+        &mut self,
+        plan: Steps,
+        runners: Vec<Runner>,
+        supervised: &mut Supervised,
+    ) {
+        for index in 0..self.steps.len() {
+            let id = &self.steps[index].processor_config.id;
+            if !supervised.removed.contains(&index)
+                && !plan.steps.iter().any(|run| &run.processor_config.id == id)
+            {
+                supervised.removed.insert(index);
+                supervised.stop(index);
+            }
+        }
+
+        let mut added = Vec::new();
+        for run in plan.steps {
+            let index = self
+                .steps
+                .iter()
+                .position(|s| s.processor_config.id == run.processor_config.id);
+            match index {
+                Some(index) => {
+                    let removed = supervised.removed.remove(&index);
+                    if removed || self.changed(index, &run, &runners) {
+                        self.replace(index, run);
+                        supervised.restart(index);
+                    }
+                }
+                None => {
+                    self.steps.push(run);
+                    supervised.restarts.push(0);
+                    added.push(self.steps.len() - 1);
+                }
+            }
+        }
+        self.runners = runners;
+        self.create_pipes();
+
+        for index in added {
+            if let Some(running) = self.start_step(index).await {
+                supervised.procs.push((index, running));
+            }
+        }
+    }
+
+    async fn run(mut self) -> Vec<Exit> {
+        if let Err(e) =
+            fs::create_dir_all(self.options.run_dir.join("logs")).await
+        {
+            self.events.emit(Event::PipelineError {
+                message: format!(
+                    "Failed to create {}: {}",
+                    self.options.run_dir.display(),
+                    e
+                ),
+            });
+            self.events.emit(Event::Finished);
+            return self.exits;
+        }
+        self.create_pipes();
+
+        let mut supervised = Supervised {
+            restarts: vec![0; self.steps.len()],
+            ..Supervised::default()
+        };
+        for index in 0..self.steps.len() {
+            if self.stopping() {
+                break;
+            }
+
+            if let Some(mut running) = self.start_step(index).await {
+                self.wait_ready(index, &mut running).await;
+                supervised.procs.push((index, running));
+            }
+        }
+
+        // Poll the processes so exits are reported as they happen, not in
+        // launch order
+        while !supervised.procs.is_empty()
+            || !supervised.pending.is_empty()
+            || (self.options.keep_running && !self.stopping())
+        {
+            let stopping = self.stopping();
+            if stopping {
+                supervised.pending.clear();
+            }
+
+            while let Ok(request) = self.requests.try_recv() {
+                if !stopping {
+                    self.request(request, &mut supervised).await;
+                }
+            }
+
+            let mut i = 0;
+            while i < supervised.procs.len() {
+                if stopping {
+                    supervised.procs[i].1.terminate(Termination::Interrupted);
+                }
+
+                match supervised.procs[i].1.poll() {
+                    None => i += 1,
+                    Some(status) => {
+                        let (index, running) = supervised.procs.remove(i);
+                        self.exited(running, status);
+
+                        let exit = self.exits.last().unwrap();
+                        let at = if supervised.restarting.remove(&index)
+                            && !stopping
+                        {
+                            Some(Instant::now())
+                        } else if !supervised.stopped.contains(&index)
+                            && self.should_restart(exit)
+                        {
+                            Some(Instant::now() + self.options.restart_delay)
+                        } else {
+                            None
+                        };
+                        if let Some(at) = at {
+                            supervised
+                                .pending
+                                .push(PendingRestart { at, index });
+                        }
+                    }
+                }
+            }
+
+            let now = Instant::now();
+            let (due, waiting) = std::mem::take(&mut supervised.pending)
+                .into_iter()
+                .partition(|p| p.at <= now);
+            supervised.pending = waiting;
+            for restart in due as Vec<PendingRestart> {
+                supervised.restarts[restart.index] += 1;
+                self.events.emit(Event::Restarted {
+                    step:     self.steps[restart.index]
+                        .processor_config
+                        .id
+                        .clone(),
+                    restarts: supervised.restarts[restart.index],
+                });
+                if let Some(running) = self.start_step(restart.index).await {
+                    supervised.procs.push((restart.index, running));
+                }
+            }
+
+            task::sleep(Duration::from_millis(100)).await;
+        }
+
+        self.close_control().await;
+        self.remove_pipes();
+        self.events.emit(Event::Finished);
+        self.exits
+    }
+}
+
+/// Runs plans, see `Pipeline::start`
+pub struct Pipeline;
+
+impl Pipeline {
+    /// Starts the steps of `plan` in the background: sub-steps first, then
+    /// every step with the runner it names
+    pub fn start(
+        plan: Steps,
+        runners: Vec<Runner>,
+        mut options: Options,
+    ) -> PipelineHandle {
+        // Runners are started in their own directory, so paths handed to them
+        // have to be absolute
+        options.run_dir = env::current_dir()
+            .map(|cwd| cwd.join(&options.run_dir))
+            .unwrap_or(options.run_dir);
+
+        let events = Events::default();
+        let receiver = events.subscribe();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (requests, requested) = unbounded();
+
+        let engine = Engine {
+            steps: plan.steps,
+            runners,
+            cache: options.cache_dir.as_ref().map(Cache::new),
+            options: options.clone(),
+            events: events.clone(),
+            stop: stop.clone(),
+            sub_outputs: HashSet::new(),
+            exits: Vec::new(),
+            control: HashMap::new(),
+            ready: Ready::default(),
+            requests: requested,
+        };
+
+        PipelineHandle {
+            run_dir: options.run_dir,
+            receiver: Some(receiver),
+            events,
+            stop,
+            requests,
+            task: task::spawn(engine.run()),
+        }
+    }
+}
+
+/// A running pipeline
+pub struct PipelineHandle {
+    run_dir:  PathBuf,
+    receiver: Option<Receiver<Event>>,
+    events:   Events,
+    stop:     Arc<AtomicBool>,
+    requests: Sender<Request>,
+    task:     JoinHandle<Vec<Exit>>,
+}
+
+impl PipelineHandle {
+    /// The events since the start, ending with `Event::Finished`. They are
+    /// buffered until taken, by a single consumer: later calls get `None`,
+    /// use `subscribe` for more.
+    pub fn events(&mut self) -> Option<Receiver<Event>> {
+        self.receiver.take()
+    }
+
+    /// A new stream of the events from now on
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// The absolute run directory
+    pub fn run_dir(&self) -> &Path {
+        &self.run_dir
+    }
+
+    /// Terminates every process, steps are killed when they did not exit
+    /// after their grace period
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    /// Terminates a single step, it is not restarted
+    pub fn stop_step(&self, step: &str) {
+        let _ = self.requests.try_send(Request::Stop(step.to_string()));
+    }
+
+    /// Terminates a single step and starts it again, or starts it when it
+    /// already exited. A pipeline whose processes all exited is finished,
+    /// unless `Options::keep_running` is set.
+    pub fn restart_step(&self, step: &str) {
+        let _ = self.requests.try_send(Request::Restart(step.to_string()));
+    }
+
+    /// Terminates the step with the id of `run` and starts `run` instead, its
+    /// configuration file is written again
+    pub fn replace_step(&self, run: RunThing) {
+        let _ = self.requests.try_send(Request::Replace(Box::new(run)));
+    }
+
+    /// Runs `plan` with `runners` from now on: steps whose definition,
+    /// arguments or runner changed are restarted, new steps are started and
+    /// steps that are no longer in the plan are stopped
+    pub fn update(&self, plan: Steps, runners: Vec<Runner>) {
+        let _ = self
+            .requests
+            .try_send(Request::Update(Box::new(plan), runners));
+    }
+
+    /// Waits until every process exited, returning how they exited
+    pub async fn wait(self) -> Vec<Exit> {
+        self.task.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn runner(script: &str) -> Runner {
+        serde_json::from_value(json!({
+            "id": "Sh",
+            "runnerScript": script,
+            "canUseChannel": [],
+            "requiredFields": [],
+            "canUseSerialization": [],
+        }))
+        .unwrap()
+    }
+
+    fn step(id: &str, version: u32) -> RunThing {
+        serde_json::from_value(json!({
+            "processorConfig": {
+                "id": id,
+                "runnerId": "Sh",
+                "config": { "version": version },
+                "args": [],
+            },
+            "args": {},
+        }))
+        .unwrap()
+    }
+
+    fn plan(steps: &[RunThing]) -> Steps {
+        Steps {
+            steps: steps.to_vec(),
+            ..Steps::default()
+        }
+    }
+
+    fn engine(dir: &Path, steps: &[RunThing]) -> (Engine, Receiver<Event>) {
+        let events = Events::default();
+        let receiver = events.subscribe();
+        let (_, requests) = unbounded();
+        let engine = Engine {
+            steps: steps.to_vec(),
+            runners: vec![runner("sleep 30")],
+            options: Options::new(dir),
+            events,
+            stop: Arc::new(AtomicBool::new(false)),
+            cache: None,
+            sub_outputs: HashSet::new(),
+            exits: Vec::new(),
+            control: HashMap::new(),
+            ready: Ready::default(),
+            requests,
+        };
+        (engine, receiver)
+    }
+
+    /// Starts every step of `engine` like `Engine::run` does
+    async fn start(engine: &mut Engine) -> Supervised {
+        fs::create_dir_all(engine.options.run_dir.join("logs"))
+            .await
+            .unwrap();
+        let mut supervised = Supervised {
+            restarts: vec![0; engine.steps.len()],
+            ..Supervised::default()
+        };
+        for index in 0..engine.steps.len() {
+            let running = engine.start_step(index).await.unwrap();
+            supervised.procs.push((index, running));
+        }
+        supervised
+    }
+
+    /// Waits until the process of step `index` exited, it is no longer
+    /// supervised
+    async fn wait_exit(supervised: &mut Supervised, index: usize) {
+        let i = supervised
+            .procs
+            .iter()
+            .position(|(i, _)| *i == index)
+            .unwrap();
+        while supervised.procs[i].1.poll().is_none() {
+            task::sleep(Duration::from_millis(50)).await;
+        }
+        supervised.procs.remove(i);
+    }
+
+    /// Terminates the processes that are still running
+    async fn finish(mut engine: Engine, mut supervised: Supervised) {
+        while let Some((index, running)) = supervised.procs.last_mut() {
+            running.terminate(Termination::Interrupted);
+            let index = *index;
+            wait_exit(&mut supervised, index).await;
+        }
+        engine.close_control().await;
+    }
+
+    fn version(engine: &Engine, index: usize) -> &Value {
+        &engine.steps[index].processor_config.config["version"]
+    }
+
+    #[async_std::test]
+    async fn update_restarts_changed_steps() {
+        let dir = TempDir::new("engine").unwrap();
+        let (mut engine, _events) =
+            engine(dir.path(), &[step("a", 1), step("b", 1)]);
+        let mut supervised = start(&mut engine).await;
+        engine.sub_outputs.insert(String::from("a"));
+
+        let runners = vec![runner("sleep 30")];
+        engine
+            .update(
+                plan(&[step("a", 2), step("b", 1)]),
+                runners,
+                &mut supervised,
+            )
+            .await;
+        assert_eq!(supervised.restarting, HashSet::from([0]));
+        assert!(supervised.stopped.is_empty() && supervised.pending.is_empty());
+        assert_eq!(version(&engine, 0), 2);
+        // The sub-steps of a replaced step run again
+        assert!(!engine.sub_outputs.contains("a"));
+
+        // A changed runner restarts the steps using it
+        let runners = vec![runner("sleep 31")];
+        engine
+            .update(
+                plan(&[step("a", 2), step("b", 1)]),
+                runners,
+                &mut supervised,
+            )
+            .await;
+        assert_eq!(supervised.restarting, HashSet::from([0, 1]));
+
+        finish(engine, supervised).await;
+    }
+
+    #[async_std::test]
+    async fn update_keeps_unchanged_steps_running() {
+        let dir = TempDir::new("engine").unwrap();
+        let steps = [step("a", 1), step("b", 1)];
+        let (mut engine, _events) = engine(dir.path(), &steps);
+        let mut supervised = start(&mut engine).await;
+
+        let runners = vec![runner("sleep 30")];
+        engine.update(plan(&steps), runners, &mut supervised).await;
+        assert!(supervised.restarting.is_empty());
+        assert!(supervised.stopped.is_empty());
+        assert!(supervised.pending.is_empty());
+        assert!(supervised.removed.is_empty());
+        assert_eq!(supervised.procs.len(), 2);
+
+        finish(engine, supervised).await;
+    }
+
+    #[async_std::test]
+    async fn update_starts_added_steps() {
+        let dir = TempDir::new("engine").unwrap();
+        let (mut engine, events) = engine(dir.path(), &[step("a", 1)]);
+        let mut supervised = start(&mut engine).await;
+
+        let runners = vec![runner("sleep 30")];
+        engine
+            .update(
+                plan(&[step("a", 1), step("b", 1)]),
+                runners,
+                &mut supervised,
+            )
+            .await;
+        assert_eq!(engine.steps.len(), 2);
+        assert_eq!(supervised.restarts, [0, 0]);
+        assert!(supervised.procs.iter().any(|(i, _)| *i == 1));
+        assert!(supervised.restarting.is_empty());
+
+        let started: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+            .filter_map(|event| {
+                match event {
+                    Event::Started { step, .. } => Some(step),
+                    _ => None,
+                }
+            })
+            .collect();
+        assert_eq!(started, ["a", "b"]);
+
+        finish(engine, supervised).await;
+    }
+
+    #[async_std::test]
+    async fn update_stops_removed_steps() {
+        let dir = TempDir::new("engine").unwrap();
+        let (mut engine, events) =
+            engine(dir.path(), &[step("a", 1), step("b", 1)]);
+        let mut supervised = start(&mut engine).await;
+
+        let runners = vec![runner("sleep 30")];
+        engine
+            .update(plan(&[step("a", 1)]), runners, &mut supervised)
+            .await;
+        assert_eq!(supervised.removed, HashSet::from([1]));
+        assert_eq!(supervised.stopped, HashSet::from([1]));
+        assert!(supervised.restarting.is_empty());
+
+        // Requests no longer find it
+        while events.try_recv().is_ok() {}
+        assert_eq!(engine.find_step("b", &supervised), None);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(Event::Error { step, .. }) if step == "b"
+        ));
+        assert_eq!(engine.find_step("a", &supervised), Some(0));
+
+        finish(engine, supervised).await;
+    }
+
+    #[async_std::test]
+    async fn update_starts_readded_steps_again() {
+        let dir = TempDir::new("engine").unwrap();
+        let (mut engine, _events) =
+            engine(dir.path(), &[step("a", 1), step("b", 1)]);
+        let mut supervised = start(&mut engine).await;
+
+        let runners = vec![runner("sleep 30")];
+        engine
+            .update(plan(&[step("a", 1)]), runners.clone(), &mut supervised)
+            .await;
+        wait_exit(&mut supervised, 1).await;
+
+        // Unchanged, but started again as it was stopped
+        engine
+            .update(
+                plan(&[step("a", 1), step("b", 1)]),
+                runners,
+                &mut supervised,
+            )
+            .await;
+        assert!(supervised.removed.is_empty());
+        assert!(supervised.stopped.is_empty());
+        assert_eq!(engine.steps.len(), 2);
+        let pending: Vec<_> =
+            supervised.pending.iter().map(|p| p.index).collect();
+        assert_eq!(pending, [1]);
+
+        finish(engine, supervised).await;
+    }
+
+    #[async_std::test]
+    async fn supervised_stop_and_restart() {
+        let dir = TempDir::new("engine").unwrap();
+        let (mut engine, _events) =
+            engine(dir.path(), &[step("a", 1), step("b", 1)]);
+        let mut supervised = start(&mut engine).await;
+
+        // A running step is terminated and marked
+        supervised.stop(0);
+        assert_eq!(supervised.stopped, HashSet::from([0]));
+        supervised.restart(0);
+        assert!(supervised.stopped.is_empty());
+        assert_eq!(supervised.restarting, HashSet::from([0]));
+
+        // A step that exited is started again later, unless it is stopped
+        // before that
+        supervised.procs[1].1.terminate(Termination::Interrupted);
+        wait_exit(&mut supervised, 1).await;
+        supervised.restart(1);
+        assert_eq!(supervised.pending.len(), 1);
+        supervised.stop(1);
+        assert!(supervised.pending.is_empty());
+        assert!(!supervised.stopped.contains(&1));
+
+        finish(engine, supervised).await;
+    }
+
+    #[async_std::test]
+    async fn failing_to_create_the_run_dir_is_a_pipeline_error() {
+        let dir = TempDir::new("engine").unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+
+        let mut handle = Pipeline::start(
+            plan(&[]),
+            Vec::new(),
+            Options::new(file.join("run")),
+        );
+        let events = handle.events().unwrap();
+        assert!(matches!(
+            events.recv().await,
+            Ok(Event::PipelineError { message }) if message.starts_with("Failed to create")
+        ));
+        assert!(matches!(events.recv().await, Ok(Event::Finished)));
+        assert!(handle.wait().await.is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::{Event, Events, ProcessKind, Stream};
+use crate::limits::Limits;
+
+/// Whether a process with this pid is still alive
+pub fn is_alive(pid: u32) -> bool {
+    // Signal 0 only performs the permission and existence checks
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+/// Sends `signal` to the process group led by `pid`
+pub fn signal_group(pid: u32, signal: libc::c_int) -> bool {
+    unsafe { libc::kill(-(pid as libc::pid_t), signal) == 0 }
+}
+
+/// Sets a resource limit of the current process, used after forking; the
+/// type of `resource` differs between libcs, so it's cast at the call
+fn set_rlimit(
+    resource: libc::c_int,
+    soft: u64,
+    hard: u64,
+) -> std::io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: soft as libc::rlim_t,
+        rlim_max: hard as libc::rlim_t,
+    };
+
+    if unsafe { libc::setrlimit(resource as _, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Termination {
+    Timeout,
+    Interrupted,
+    /// The step alone is stopped on request
+    Stopped,
+    /// The step is started again on request
+    Restarted,
+}
+
+/// A started step or sub-step, tracked until it exits
+pub(crate) struct Running {
+    pub step:    String,
+    pub kind:    ProcessKind,
+    child:       Child,
+    stdout:      JoinHandle<String>,
+    stderr:      JoinHandle<String>,
+    limits:      Limits,
+    started:     Instant,
+    terminating: Option<(Instant, Termination)>,
+    killed:      bool,
+}
+
+/// Forwards the lines of a stream as events and to the log, returning them
+/// when `capture` is set
+fn forward<R: Read + Send + 'static>(
+    from: R,
+    step: &str,
+    stream: Stream,
+    log: Option<Arc<Mutex<File>>>,
+    capture: bool,
+    events: &Events,
+) -> JoinHandle<String> {
+    let step = step.to_string();
+    let events = events.clone();
+    let level = match stream {
+        Stream::Stdout => "INFO",
+        Stream::Stderr => "ERRO",
+    };
+
+    spawn(move || {
+        let mut lines = Vec::new();
+        BufReader::new(from).lines().for_each(|line| {
+            let line = line.unwrap_or_else(|_| String::from("error"));
+            if let Some(file) = &log {
+                if let Ok(mut file) = file.lock() {
+                    let _ = writeln!(file, "{} {}", level, line);
+                }
+            }
+            if capture {
+                lines.push(line.clone());
+            }
+            events.emit(Event::Log {
+                step: step.clone(),
+                stream,
+                line,
+            });
+        });
+        lines.into_iter().map(|line| line + "\n").collect()
+    })
+}
+
+impl Running {
+    /// Starts `script` with `sh` in its own process group with the extra
+    /// environment variables `env`, appending its output to `log`
+    // the RLIMIT_* casts are no-ops with musl
+    #[allow(clippy::too_many_arguments, clippy::unnecessary_cast)]
+    pub fn start(
+        script: &str,
+        location: Option<PathBuf>,
+        env: Vec<(&str, String)>,
+        step: &str,
+        kind: ProcessKind,
+        limits: Limits,
+        log: &Path,
+        capture: bool,
+        events: &Events,
+    ) -> std::io::Result<Self> {
+        let mut proc = std::process::Command::new("sh");
+        proc.stdout(Stdio::piped());
+        proc.stderr(Stdio::piped());
+        proc.args(["-c", script]);
+        proc.process_group(0);
+        proc.envs(env);
+
+        if let Some(location) = location {
+            proc.current_dir(location);
+        }
+
+        unsafe {
+            proc.pre_exec(move || {
+                if let Some(bytes) = limits.max_memory {
+                    set_rlimit(libc::RLIMIT_AS as libc::c_int, bytes, bytes)?;
+                }
+                if let Some(secs) = limits.cpu_time {
+                    // SIGXCPU at the soft limit, SIGKILL a second later
+                    set_rlimit(
+                        libc::RLIMIT_CPU as libc::c_int,
+                        secs,
+                        secs + 1,
+                    )?;
+                }
+                if let Some(n) = limits.open_files {
+                    set_rlimit(libc::RLIMIT_NOFILE as libc::c_int, n, n)?;
+                }
+                if let Some(n) = limits.nice {
+                    libc::nice(n);
+                }
+                Ok(())
+            });
+        }
+
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log)
+            .ok()
+            .map(|file| Arc::new(Mutex::new(file)));
+
+        let mut child = proc.spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+
+        Ok(Self {
+            step: step.to_string(),
+            kind,
+            stdout: forward(
+                stdout,
+                step,
+                Stream::Stdout,
+                log.clone(),
+                capture,
+                events,
+            ),
+            stderr: forward(stderr, step, Stream::Stderr, log, capture, events),
+            child,
+            limits,
+            started: Instant::now(),
+            terminating: None,
+            killed: false,
+        })
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Asks the process group to stop, it is killed after the grace period
+    pub fn terminate(&mut self, why: Termination) {
+        if self.terminating.is_none() {
+            signal_group(self.child.id(), libc::SIGTERM);
+            self.terminating = Some((Instant::now(), why));
+        }
+    }
+
+    /// Returns the exit status once the process exited, enforcing the timeout
+    /// and grace period while it has not
+    pub fn poll(&mut self) -> Option<Option<ExitStatus>> {
+        match self.child.try_wait() {
+            Ok(Some(status)) => return Some(Some(status)),
+            Ok(None) => {}
+            Err(_) => return Some(None),
+        }
+
+        if let Some(timeout) = self.limits.timeout {
+            if self.started.elapsed() >= Duration::from_secs(timeout) {
+                self.terminate(Termination::Timeout);
+            }
+        }
+
+        if let Some((since, _)) = self.terminating {
+            let grace = Duration::from_secs(self.limits.grace_period());
+            if !self.killed && since.elapsed() >= grace {
+                signal_group(self.child.id(), libc::SIGKILL);
+                self.killed = true;
+            }
+        }
+
+        None
+    }
+
+    /// Why the process stopped, if it did not simply exit
+    fn reason(&self, status: Option<&ExitStatus>) -> Option<String> {
+        match self.terminating {
+            Some((_, Termination::Timeout)) => {
+                return Some(format!(
+                    "timeout after {}s",
+                    self.limits.timeout.unwrap_or_default()
+                ));
+            }
+            Some((_, Termination::Interrupted)) => {
+                return Some(String::from("interrupted"));
+            }
+            Some((_, Termination::Stopped)) => {
+                return Some(String::from("stopped"));
+            }
+            Some((_, Termination::Restarted)) => {
+                return Some(String::from("restarted"));
+            }
+            None => {}
+        }
+
+        let reason = match status?.signal()? {
+            libc::SIGXCPU => String::from("cpu time limit exceeded"),
+            libc::SIGKILL => String::from("killed"),
+            signal => format!("killed by signal {}", signal),
+        };
+        Some(reason)
+    }
+
+    /// Waits for the output of the exited process, returning how it exited
+    /// and its captured stdout and stderr
+    pub fn exited(
+        self,
+        status: Option<ExitStatus>,
+    ) -> (super::Exit, String, String) {
+        let exit = super::Exit {
+            step:      self.step.clone(),
+            kind:      self.kind,
+            exit_code: status.and_then(|s| s.code()),
+            reason:    self.reason(status.as_ref()),
+        };
+        let stdout = self.stdout.join().unwrap_or_default();
+        let stderr = self.stderr.join().unwrap_or_default();
+
+        (exit, stdout, stderr)
+    }
+}
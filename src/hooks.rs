@@ -0,0 +1,285 @@
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+/// How the details of an event are handed to a hook
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum HookInput {
+    /// As a JSON object on stdin
+    #[default]
+    Stdin,
+    /// As `NAUTIRUST_*` environment variables
+    Env,
+}
+
+/// A command run with `sh` when an event happens
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "RawHook")]
+pub struct Hook {
+    pub command: String,
+    pub input:   HookInput,
+}
+
+/// A hook is either just its command or a table with the command and input
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawHook {
+    Command(String),
+    Full {
+        command: String,
+        #[serde(default)]
+        input:   HookInput,
+    },
+}
+
+impl From<RawHook> for Hook {
+    fn from(raw: RawHook) -> Self {
+        match raw {
+            RawHook::Command(command) => {
+                Hook {
+                    command,
+                    input: HookInput::default(),
+                }
+            }
+            RawHook::Full { command, input } => Hook { command, input },
+        }
+    }
+}
+
+/// Accepts a single hook or a list of hooks
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Hook>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Hook),
+        Many(Vec<Hook>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(hook) => vec![hook],
+        OneOrMany::Many(hooks) => hooks,
+    })
+}
+
+/// The lifecycle events hooks can be attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    StepStart,
+    StepExit,
+    PipelineFail,
+    PipelineDone,
+}
+
+impl Trigger {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Trigger::StepStart => "on_step_start",
+            Trigger::StepExit => "on_step_exit",
+            Trigger::PipelineFail => "on_pipeline_fail",
+            Trigger::PipelineDone => "on_pipeline_done",
+        }
+    }
+}
+
+/// Commands run on lifecycle events, configured in the `[hooks]` table of
+/// the orchestrator config and in the `hooks` object of a plan
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hooks {
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub on_step_start:    Vec<Hook>,
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub on_step_exit:     Vec<Hook>,
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub on_pipeline_fail: Vec<Hook>,
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub on_pipeline_done: Vec<Hook>,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self == &Hooks::default()
+    }
+
+    pub fn get(&self, trigger: Trigger) -> &[Hook] {
+        match trigger {
+            Trigger::StepStart => &self.on_step_start,
+            Trigger::StepExit => &self.on_step_exit,
+            Trigger::PipelineFail => &self.on_pipeline_fail,
+            Trigger::PipelineDone => &self.on_pipeline_done,
+        }
+    }
+
+    /// Combines config hooks (`self`) with plan hooks, both are run
+    pub fn merge(&self, plan: &Hooks) -> Hooks {
+        let both = |a: &[Hook], b: &[Hook]| [a, b].concat();
+        Hooks {
+            on_step_start:    both(&self.on_step_start, &plan.on_step_start),
+            on_step_exit:     both(&self.on_step_exit, &plan.on_step_exit),
+            on_pipeline_fail: both(
+                &self.on_pipeline_fail,
+                &plan.on_pipeline_fail,
+            ),
+            on_pipeline_done: both(
+                &self.on_pipeline_done,
+                &plan.on_pipeline_done,
+            ),
+        }
+    }
+}
+
+/// The details of an event handed to a hook
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HookEvent {
+    /// Name of the trigger, like `on_step_exit`
+    pub event:     String,
+    /// The nautirust command the event happened in: run, prepare or stop
+    pub command:   String,
+    pub plan:      String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id:    Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_dir:   Option<String>,
+    /// Id of the step, runner or channel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step:      Option<String>,
+    /// step or subStep during a run, step, runner or channel for the scripts
+    /// of prepare and stop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind:      Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid:       Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason:    Option<String>,
+    /// The processes that failed, for the pipeline events
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed:    Vec<String>,
+    /// Milliseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+impl HookEvent {
+    /// The event as environment variables, `runId` becomes
+    /// `NAUTIRUST_RUN_ID` and lists are joined with commas
+    pub fn env(&self) -> Vec<(String, String)> {
+        let value = serde_json::to_value(self).unwrap_or_default();
+        let object = match value {
+            Value::Object(object) => object,
+            _ => return Vec::new(),
+        };
+
+        object
+            .into_iter()
+            .map(|(key, value)| {
+                let mut name = String::from("NAUTIRUST_");
+                for c in key.chars() {
+                    if c.is_uppercase() {
+                        name.push('_');
+                    }
+                    name.push(c.to_ascii_uppercase());
+                }
+
+                let value = match value {
+                    Value::String(s) => s,
+                    Value::Array(items) => {
+                        items
+                            .iter()
+                            .map(|i| i.as_str().unwrap_or_default())
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    }
+                    other => other.to_string(),
+                };
+                (name, value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "io")]
+pub use io::*;
+
+#[cfg(feature = "io")]
+mod io {
+    use std::io::Write;
+    use std::process::{Command, ExitStatus, Stdio};
+
+    use super::{Hook, HookEvent, HookInput, Hooks, Trigger};
+
+    impl Hook {
+        /// Runs the hook for `event`, waiting until it exits
+        pub fn run(&self, event: &HookEvent) -> std::io::Result<ExitStatus> {
+            let mut proc = Command::new("sh");
+            proc.args(["-c", &self.command]);
+
+            match self.input {
+                HookInput::Stdin => {
+                    proc.stdin(Stdio::piped());
+                }
+                HookInput::Env => {
+                    proc.stdin(Stdio::null());
+                    proc.envs(event.env());
+                }
+            }
+
+            let mut child = proc.spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                let json = serde_json::to_string(event)?;
+                // A hook that does not read its input closes the pipe early
+                let _ = writeln!(stdin, "{}", json);
+            }
+            child.wait()
+        }
+    }
+
+    /// Runs the hooks of `trigger` one after the other, returning the ones
+    /// that failed with why
+    pub fn run_hooks(
+        hooks: &Hooks,
+        trigger: Trigger,
+        event: &HookEvent,
+    ) -> Vec<(String, String)> {
+        let mut event = event.clone();
+        event.event = trigger.name().to_string();
+
+        hooks
+            .get(trigger)
+            .iter()
+            .filter_map(|hook| {
+                let why = match hook.run(&event) {
+                    Ok(status) if status.success() => return None,
+                    Ok(status) => {
+                        match status.code() {
+                            Some(code) => format!("exit code {}", code),
+                            None => String::from("killed"),
+                        }
+                    }
+                    Err(e) => e.to_string(),
+                };
+                Some((hook.command.clone(), why))
+            })
+            .collect()
+    }
+}
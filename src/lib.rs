@@ -1,14 +1,20 @@
-
 #[macro_use]
 extern crate serde_json;
 
 #[cfg(feature = "io")]
 extern crate async_std;
 
-
+pub mod channel;
 #[cfg(feature = "cli")]
 pub mod commands;
-pub mod channel;
+pub mod control;
+#[cfg(feature = "io")]
+pub mod exec;
+pub mod hooks;
+pub mod limits;
+pub mod plan;
+#[cfg(feature = "io")]
+pub mod registry;
 pub mod runner;
 pub mod step;
-
+pub mod version;
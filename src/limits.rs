@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+/// Bounds on a step process, settable on both runners and steps
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct Limits {
+    /// Seconds after which the step is terminated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout:      Option<u64>,
+    /// Seconds between the polite termination signal and killing the step
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grace_period: Option<u64>,
+    /// Maximum size of the address space in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_memory:   Option<u64>,
+    /// Maximum CPU time in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_time:     Option<u64>,
+    /// Maximum number of open file descriptors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_files:   Option<u64>,
+    /// Niceness added to the step process
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nice:         Option<i32>,
+}
+
+/// Grace period used when none is configured
+pub const DEFAULT_GRACE_PERIOD: u64 = 10;
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self == &Limits::default()
+    }
+
+    /// Combines runner limits (`self`) with step limits, the step wins
+    pub fn merge(&self, step: &Limits) -> Limits {
+        Limits {
+            timeout:      step.timeout.or(self.timeout),
+            grace_period: step.grace_period.or(self.grace_period),
+            max_memory:   step.max_memory.or(self.max_memory),
+            cpu_time:     step.cpu_time.or(self.cpu_time),
+            open_files:   step.open_files.or(self.open_files),
+            nice:         step.nice.or(self.nice),
+        }
+    }
+
+    pub fn grace_period(&self) -> u64 {
+        self.grace_period.unwrap_or(DEFAULT_GRACE_PERIOD)
+    }
+}
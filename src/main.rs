@@ -1,26 +1,18 @@
 use std::error::Error;
 
 use async_std::path::Path;
-use commands::Command;
 use config::builder::DefaultState;
 use config::ConfigBuilder;
+use nautirust::commands::{mock, Command};
+use nautirust::hooks::Hooks;
+use nautirust::{channel, registry, runner};
 use serde::{Deserialize, Serialize};
 
-extern crate jsonschema;
-
-#[macro_use]
-extern crate serde_json;
 #[macro_use]
 extern crate async_std;
 
 use clap::{Parser, Subcommand};
 
-mod channel;
-mod commands;
-mod param;
-mod runner;
-mod step;
-
 const TOML_LOCATION: &str = "orchestrator.toml";
 
 #[derive(Parser, Debug)]
@@ -62,6 +54,9 @@ struct AppConfig {
     channels: String,
     /// Glob to indicate runner locations
     runners:  String,
+    /// Commands run on lifecycle events of a pipeline
+    #[serde(default)]
+    hooks:    Hooks,
 }
 
 async fn load_cfg(args: Args) -> Result<(AppConfig, Command), Box<dyn Error>> {
@@ -97,13 +92,34 @@ async fn load_cfg(args: Args) -> Result<(AppConfig, Command), Box<dyn Error>> {
 
 #[main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let (config, command) = load_cfg(Args::parse()).await?;
+    let args = Args::parse();
+
+    // Mock steps are started by a pipeline, they need no config or definitions
+    if let Command::Mock(_) = args.command {
+        args.command
+            .execute(Vec::new(), Vec::new(), &Hooks::default())
+            .await;
+        return Ok(());
+    }
+
+    let (config, command) = load_cfg(args).await?;
 
-    let channels = channel::parse_channels(&config.channels).await;
-    let runners = runner::parse_runners(&config.runners, &channels).await;
+    // Definitions installed from a registry complement the local ones
+    let cache = registry::default_cache_dir();
 
-    command.execute(channels, runners).await;
+    let mut channels = channel::parse_channels(&config.channels).await;
+    if let Some(cache) = &cache {
+        registry::add_installed_channels(cache, &mut channels).await;
+    }
+    channel::add_builtin_channels(&mut channels);
+
+    let mut runners = runner::parse_runners(&config.runners, &channels).await;
+    if let Some(cache) = &cache {
+        registry::add_installed_runners(cache, &mut runners, &channels).await;
+    }
+    mock::add_runner(&mut runners, &channels);
+
+    command.execute(channels, runners, &config.hooks).await;
 
     Ok(())
 }
-
@@ -0,0 +1,812 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::channel::{Channel, ChannelConfig};
+use crate::hooks::Hooks;
+use crate::runner::Runner;
+use crate::step::{
+    Output, RunThing, Step, StepArg, StepArgument, StepArguments, SubStep,
+};
+
+/// A pipeline, as written by `generate` and read by `run`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Steps {
+    #[serde(rename = "values")]
+    pub steps:  Vec<RunThing>,
+    pub params: Vec<String>,
+    /// Run on lifecycle events, next to the hooks of the orchestrator config
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks:  Hooks,
+}
+
+impl Steps {
+    pub fn from_json(content: &str) -> Result<Self, String> {
+        serde_json::from_str(content).map_err(|e| e.to_string())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Replaces the step with the id of `step` by this new definition of it,
+    /// keeping the arguments it still declares. The result is checked like
+    /// `PlanBuilder::build` does.
+    pub fn replace_step(
+        &mut self,
+        step: &Step,
+        runners: &[Runner],
+        channels: &[Channel],
+    ) -> Result<&RunThing, Vec<String>> {
+        let index = self
+            .steps
+            .iter()
+            .position(|run| run.processor_config.id == step.id)
+            .ok_or_else(|| vec![format!("No step {} in the plan", step.id)])?;
+
+        let mut builder = PlanBuilder::new(runners, channels);
+        builder.add_step(step).map_err(|e| vec![e])?;
+        for (arg, value) in &self.steps[index].args {
+            if !step.args.iter().any(|a| &a.id == arg) {
+                continue;
+            }
+
+            let target = |field: &str| Target::new(&step.id, arg, field);
+            let set = match value {
+                StepArgument::StreamReader { fields } => {
+                    fields.iter().try_for_each(|(field, config)| {
+                        builder
+                            .read_from(&target(field), config.clone())
+                            .map(|_| ())
+                    })
+                }
+                StepArgument::StreamWriter { fields } => {
+                    fields.iter().try_for_each(|(field, config)| {
+                        builder
+                            .write_to(&target(field), config.clone())
+                            .map(|_| ())
+                    })
+                }
+                value => builder.set(&step.id, arg, value.clone()).map(|_| ()),
+            };
+            set.map_err(|e| vec![e])?;
+        }
+
+        self.steps[index] = builder.build()?.steps.remove(0);
+        Ok(&self.steps[index])
+    }
+
+    /// The channel of the stream writer field `target`
+    pub fn writer_channel(&self, target: &Target) -> Option<&ChannelConfig> {
+        let run = self
+            .steps
+            .iter()
+            .find(|run| run.processor_config.id == target.step)?;
+        match run.args.get(&target.arg)? {
+            StepArgument::StreamWriter { fields } => fields.get(&target.field),
+            _ => None,
+        }
+    }
+
+    /// The `fifo` and `unix-socket` channels of the steps with their path, see
+    /// `ChannelConfig::pipe_path`, or the paths that are not valid
+    pub fn pipes(
+        &self,
+        run_file: &dyn Fn(&str) -> String,
+    ) -> Result<Vec<(&ChannelConfig, PathBuf)>, Vec<String>> {
+        let mut pipes: Vec<(&ChannelConfig, PathBuf)> = Vec::new();
+        let mut errors = Vec::new();
+        let configs = self
+            .steps
+            .iter()
+            .flat_map(|run| run.readers().chain(run.writers()));
+        for config in configs {
+            match config.pipe_path(run_file) {
+                Ok(Some(path)) => {
+                    if !pipes.iter().any(|(_, p)| p == &path) {
+                        pipes.push((config, path));
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    if !errors.contains(&e) {
+                        errors.push(e);
+                    }
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(pipes)
+    }
+}
+
+/// A field of a stream reader or writer argument of a step
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub step:  String,
+    pub arg:   String,
+    pub field: String,
+}
+
+impl Target {
+    pub fn new(step: &str, arg: &str, field: &str) -> Self {
+        Self {
+            step:  step.to_string(),
+            arg:   arg.to_string(),
+            field: field.to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for Target {
+    type Err = String;
+
+    /// Parses `<step>.<arg>.<field>`, the step id can contain dots
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.rsplitn(3, '.');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(field), Some(arg), Some(step))
+                if !field.is_empty() && !arg.is_empty() && !step.is_empty() =>
+            {
+                Ok(Target::new(step, arg, field))
+            }
+            _ => Err(format!("expected <step>.<arg>.<field>, got '{}'", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.step, self.arg, self.field)
+    }
+}
+
+/// Builds a pipeline without asking the user: add steps, link their stream
+/// writers to stream readers, set the other arguments and `build` it
+pub struct PlanBuilder<'a> {
+    runners:  &'a [Runner],
+    channels: &'a [Channel],
+    steps:    Vec<StepArguments>,
+    params:   Vec<String>,
+    /// Steps used as sub-step, they are not run on their own
+    used:     HashSet<String>,
+}
+
+/// Sets the arguments marked `default` that are not set to their value
+fn with_defaults(mut args: StepArguments) -> StepArguments {
+    for arg in &args.step.args {
+        if arg.default && !args.arguments.contains_key(&arg.id) {
+            let value = Value::String(arg.value.clone());
+            args.arguments
+                .insert(arg.id.clone(), StepArgument::Plain { value });
+        }
+    }
+    args
+}
+
+impl<'a> PlanBuilder<'a> {
+    pub fn new(runners: &'a [Runner], channels: &'a [Channel]) -> Self {
+        Self {
+            runners,
+            channels,
+            steps: Vec::new(),
+            params: Vec::new(),
+            used: HashSet::new(),
+        }
+    }
+
+    /// Adds a step, its runner has to be known and its config valid
+    pub fn add_step(&mut self, step: &Step) -> Result<(), String> {
+        if self.get(&step.id).is_some() {
+            return Err(format!(
+                "Found multiple steps with the same id '{}'",
+                step.id
+            ));
+        }
+
+        let runner = step.find_runner(self.runners)?;
+        if let Err(e) = runner.schema.validate(&step.config) {
+            let errors = e.map(|e| e.to_string()).collect::<Vec<_>>();
+            return Err(format!(
+                "Step {} is not valid according to runner {}: {}",
+                step.id,
+                runner.id,
+                errors.join(", ")
+            ));
+        }
+
+        self.steps.push(StepArguments::new(step));
+        Ok(())
+    }
+
+    fn get(&self, step: &str) -> Option<&StepArguments> {
+        self.steps.iter().find(|s| s.step.id == step)
+    }
+
+    fn get_mut(&mut self, step: &str) -> Result<&mut StepArguments, String> {
+        self.steps
+            .iter_mut()
+            .find(|s| s.step.id == step)
+            .ok_or_else(|| format!("No step {} in the plan", step))
+    }
+
+    /// The declared argument `arg` of `step`
+    fn declared(&self, step: &str, arg: &str) -> Result<&StepArg, String> {
+        self.get(step)
+            .ok_or_else(|| format!("No step {} in the plan", step))?
+            .step
+            .args
+            .iter()
+            .find(|a| a.id == arg)
+            .ok_or_else(|| format!("Step {} has no argument {}", step, arg))
+    }
+
+    /// Checks that argument `arg` of `step` is declared with type `ty`
+    fn declared_as(
+        &self,
+        step: &str,
+        arg: &str,
+        ty: &str,
+    ) -> Result<(), String> {
+        let declared = self.declared(step, arg)?;
+        if declared.ty != ty {
+            return Err(format!(
+                "Step {}: argument {} is a {}, not a {}",
+                step, arg, declared.ty, ty
+            ));
+        }
+        Ok(())
+    }
+
+    fn set(
+        &mut self,
+        step: &str,
+        arg: &str,
+        value: StepArgument,
+    ) -> Result<&mut Self, String> {
+        self.declared(step, arg)?;
+        self.get_mut(step)?.add_argument(arg.to_string(), value);
+        Ok(self)
+    }
+
+    /// Sets argument `arg` of `step` to a plain value
+    pub fn set_value(
+        &mut self,
+        step: &str,
+        arg: &str,
+        value: Value,
+    ) -> Result<&mut Self, String> {
+        self.set(step, arg, StepArgument::Plain { value })
+    }
+
+    /// Sets argument `arg` of `step` to the content of a file
+    pub fn set_file(
+        &mut self,
+        step: &str,
+        arg: &str,
+        path: &str,
+        serialization: &str,
+    ) -> Result<&mut Self, String> {
+        let value = StepArgument::File {
+            path:          path.to_string(),
+            serialization: serialization.to_string(),
+        };
+        self.set(step, arg, value)
+    }
+
+    /// Sets argument `arg` of `step` to a param, given when the plan is run
+    pub fn set_param(
+        &mut self,
+        step: &str,
+        arg: &str,
+        name: &str,
+    ) -> Result<&mut Self, String> {
+        self.set(
+            step,
+            arg,
+            StepArgument::Param {
+                name: name.to_string(),
+            },
+        )?;
+        if !self.params.iter().any(|p| p == name) {
+            self.params.push(name.to_string());
+        }
+        Ok(self)
+    }
+
+    /// Sets argument `arg` of `step` to the output of step `sub`, as it is
+    /// configured at this point. `sub` is then only run as a sub-step.
+    pub fn set_sub_step(
+        &mut self,
+        step: &str,
+        arg: &str,
+        sub: &str,
+        output: Output,
+        serialization: &str,
+        cache: bool,
+    ) -> Result<&mut Self, String> {
+        let run = self
+            .get(sub)
+            .ok_or_else(|| format!("No step {} in the plan", sub))?
+            .clone();
+        let run = with_defaults(run).into_runthing();
+
+        self.set(
+            step,
+            arg,
+            StepArgument::Step {
+                sub: Box::new(SubStep {
+                    run,
+                    serialization: serialization.to_string(),
+                    output,
+                    cache,
+                }),
+            },
+        )?;
+        self.used.insert(sub.to_string());
+        Ok(self)
+    }
+
+    /// Checks that the runner of `step` can use the channel `config`
+    fn check_channel(
+        &self,
+        step: &str,
+        config: &ChannelConfig,
+    ) -> Result<(), String> {
+        let runner = self
+            .get(step)
+            .ok_or_else(|| format!("No step {} in the plan", step))?
+            .step
+            .find_runner(self.runners)?;
+
+        if !runner.can_use_channel.iter().any(|c| c == config.ty()) {
+            return Err(format!(
+                "Runner {} of step {} cannot use channel {}",
+                runner.id,
+                step,
+                config.ty()
+            ));
+        }
+        if !runner
+            .can_use_serialization
+            .iter()
+            .any(|s| s == config.serialization())
+        {
+            return Err(format!(
+                "Runner {} of step {} cannot use serialization {}",
+                runner.id,
+                step,
+                config.serialization()
+            ));
+        }
+
+        let channel = self
+            .channels
+            .iter()
+            .find(|c| c.id == config.ty())
+            .ok_or_else(|| format!("No such channel found! {}", config.ty()))?;
+        if let Err(e) = channel.schema.validate(config.config()) {
+            let errors = e.map(|e| e.to_string()).collect::<Vec<_>>();
+            return Err(format!(
+                "Invalid configuration for channel {}: {}",
+                channel.id,
+                errors.join(", ")
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Writes a stream writer field to a channel, without a reader in the plan
+    pub fn write_to(
+        &mut self,
+        writer: &Target,
+        config: ChannelConfig,
+    ) -> Result<&mut Self, String> {
+        self.declared_as(&writer.step, &writer.arg, "streamWriter")?;
+        self.check_channel(&writer.step, &config)?;
+
+        self.get_mut(&writer.step)?.use_target(
+            &writer.arg,
+            &writer.field,
+            config,
+        );
+        Ok(self)
+    }
+
+    /// Reads a stream reader field from a channel, without a writer in the
+    /// plan
+    pub fn read_from(
+        &mut self,
+        reader: &Target,
+        config: ChannelConfig,
+    ) -> Result<&mut Self, String> {
+        self.declared_as(&reader.step, &reader.arg, "streamReader")?;
+        self.check_channel(&reader.step, &config)?;
+
+        let args = self.get_mut(&reader.step)?;
+        let fields = match args.arguments.get_mut(&reader.arg) {
+            Some(StepArgument::StreamReader { fields }) => fields,
+            _ => {
+                args.arguments.insert(
+                    reader.arg.clone(),
+                    StepArgument::StreamReader {
+                        fields: HashMap::new(),
+                    },
+                );
+                match args.arguments.get_mut(&reader.arg) {
+                    Some(StepArgument::StreamReader { fields }) => fields,
+                    _ => unreachable!(),
+                }
+            }
+        };
+        fields.insert(reader.field.clone(), config);
+        Ok(self)
+    }
+
+    /// Links a stream writer field to a stream reader field over a channel
+    pub fn link(
+        &mut self,
+        writer: &Target,
+        reader: &Target,
+        config: ChannelConfig,
+    ) -> Result<&mut Self, String> {
+        self.check_channel(&reader.step, &config)?;
+        self.write_to(writer, config.clone())?;
+        self.read_from(reader, config)
+    }
+
+    pub fn add_param(&mut self, name: &str) -> &mut Self {
+        if !self.params.iter().any(|p| p == name) {
+            self.params.push(name.to_string());
+        }
+        self
+    }
+
+    /// Checks that every declared argument of every step is set, arguments
+    /// marked `default` are set to their value
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        for args in &self.steps {
+            let step = &args.step;
+            for arg in &step.args {
+                let set = args.arguments.get(&arg.id);
+                let fields = match (arg.ty.as_str(), set) {
+                    (
+                        "streamReader",
+                        Some(StepArgument::StreamReader { fields }),
+                    ) => fields,
+                    (
+                        "streamWriter",
+                        Some(StepArgument::StreamWriter { fields }),
+                    ) => fields,
+                    ("streamReader" | "streamWriter", None) => {
+                        errors.push(format!(
+                            "Step {}: {} {} is not linked",
+                            step.id, arg.ty, arg.id
+                        ));
+                        continue;
+                    }
+                    ("streamReader" | "streamWriter", Some(_)) => {
+                        errors.push(format!(
+                            "Step {}: argument {} is not a {}",
+                            step.id, arg.id, arg.ty
+                        ));
+                        continue;
+                    }
+                    (_, None) if !arg.default => {
+                        errors.push(format!(
+                            "Step {}: argument {} is not set",
+                            step.id, arg.id
+                        ));
+                        continue;
+                    }
+                    _ => continue,
+                };
+
+                for id in arg.fields() {
+                    if !fields.contains_key(id) {
+                        errors.push(format!(
+                            "Step {}: {} is not linked",
+                            step.id,
+                            Target::new(&step.id, &arg.id, id)
+                        ));
+                    }
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Validates and returns the plan, steps are kept in the order they were
+    /// added
+    pub fn build(self) -> Result<Steps, Vec<String>> {
+        self.validate()?;
+
+        let steps = self
+            .steps
+            .into_iter()
+            .filter(|args| !self.used.contains(&args.step.id))
+            .map(|args| with_defaults(args).into_value())
+            .collect();
+
+        Ok(Steps {
+            steps,
+            params: self.params,
+            hooks: Hooks::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error<T>(result: Result<T, String>) -> String {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e,
+        }
+    }
+
+    fn runners() -> Vec<Runner> {
+        vec![serde_json::from_value(json!({
+            "id": "JsRunner",
+            "runnerScript": "node {config}",
+            "canUseChannel": ["file"],
+            "requiredFields": ["jsFile"],
+            "canUseSerialization": ["json"],
+        }))
+        .unwrap()]
+    }
+
+    fn channels() -> Vec<Channel> {
+        vec![serde_json::from_value(json!({
+            "id": "file",
+            "requiredFields": ["path"],
+        }))
+        .unwrap()]
+    }
+
+    fn step(id: &str, args: Value) -> Step {
+        serde_json::from_value(json!({
+            "id": id,
+            "runnerId": "JsRunner",
+            "config": { "jsFile": format!("{}.js", id) },
+            "args": args,
+        }))
+        .unwrap()
+    }
+
+    fn writer() -> Step {
+        step(
+            "writer",
+            json!([
+                { "id": "out", "type": "streamWriter", "targetIds": ["data"] },
+                { "id": "count", "type": "plain" },
+                { "id": "delay", "type": "plain", "default": true, "value": "5" },
+            ]),
+        )
+    }
+
+    fn reader() -> Step {
+        step(
+            "reader",
+            json!([
+                { "id": "in", "type": "streamReader", "sourceIds": ["data"] },
+            ]),
+        )
+    }
+
+    fn file(path: &str) -> ChannelConfig {
+        ChannelConfig::new(
+            String::from("file"),
+            String::from("json"),
+            json!({ "path": path }),
+        )
+    }
+
+    #[test]
+    fn builds_two_linked_steps() {
+        let (runners, channels) = (runners(), channels());
+        let mut builder = PlanBuilder::new(&runners, &channels);
+        builder.add_step(&writer()).unwrap();
+        builder.add_step(&reader()).unwrap();
+        builder
+            .link(
+                &"writer.out.data".parse().unwrap(),
+                &"reader.in.data".parse().unwrap(),
+                file("data.json"),
+            )
+            .unwrap()
+            .set_value("writer", "count", json!(3))
+            .unwrap();
+        let plan = builder.build().unwrap();
+
+        let ids: Vec<_> = plan
+            .steps
+            .iter()
+            .map(|run| run.processor_config.id.as_str())
+            .collect();
+        assert_eq!(ids, ["writer", "reader"]);
+        assert_eq!(plan.steps[0].writers().next(), Some(&file("data.json")));
+        assert_eq!(plan.steps[1].readers().next(), Some(&file("data.json")));
+        assert!(matches!(
+            &plan.steps[0].args["count"],
+            StepArgument::Plain { value } if value == &json!(3)
+        ));
+        // Arguments marked default are set to their value
+        assert!(matches!(
+            &plan.steps[0].args["delay"],
+            StepArgument::Plain { value } if value == "5"
+        ));
+        assert_eq!(
+            plan.writer_channel(&Target::new("writer", "out", "data")),
+            Some(&file("data.json"))
+        );
+    }
+
+    #[test]
+    fn unlinked_and_unset_arguments_fail() {
+        let (runners, channels) = (runners(), channels());
+        let mut builder = PlanBuilder::new(&runners, &channels);
+        builder.add_step(&writer()).unwrap();
+        assert_eq!(
+            builder.add_step(&writer()).unwrap_err(),
+            "Found multiple steps with the same id 'writer'"
+        );
+
+        let errors = builder.build().unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                "Step writer: streamWriter out is not linked",
+                "Step writer: argument count is not set",
+            ]
+        );
+    }
+
+    #[test]
+    fn set_value_fails_on_unknown_steps_and_arguments() {
+        let (runners, channels) = (runners(), channels());
+        let mut builder = PlanBuilder::new(&runners, &channels);
+        builder.add_step(&writer()).unwrap();
+
+        assert_eq!(
+            error(builder.set_value("other", "count", json!(1))),
+            "No step other in the plan"
+        );
+        assert_eq!(
+            error(builder.set_value("writer", "other", json!(1))),
+            "Step writer has no argument other"
+        );
+    }
+
+    #[test]
+    fn read_from_and_write_to_check_the_channel() {
+        let (runners, channels) = (runners(), channels());
+        let mut builder = PlanBuilder::new(&runners, &channels);
+        builder.add_step(&writer()).unwrap();
+        builder.add_step(&reader()).unwrap();
+        let out = Target::new("writer", "out", "data");
+        let input = Target::new("reader", "in", "data");
+
+        let ws = ChannelConfig::new(
+            String::from("ws"),
+            String::from("json"),
+            json!({}),
+        );
+        assert_eq!(
+            error(builder.write_to(&out, ws)),
+            "Runner JsRunner of step writer cannot use channel ws"
+        );
+
+        let turtle = ChannelConfig::new(
+            String::from("file"),
+            String::from("turtle"),
+            json!({ "path": "data.ttl" }),
+        );
+        assert_eq!(
+            error(builder.read_from(&input, turtle)),
+            "Runner JsRunner of step reader cannot use serialization turtle"
+        );
+
+        let invalid = ChannelConfig::new(
+            String::from("file"),
+            String::from("json"),
+            json!({ "other": "data.json" }),
+        );
+        assert!(error(builder.read_from(&input, invalid))
+            .starts_with("Invalid configuration for channel file"));
+
+        assert_eq!(
+            error(
+                builder.read_from(
+                    &Target::new("reader", "other", "data"),
+                    file("x")
+                )
+            ),
+            "Step reader has no argument other"
+        );
+        assert_eq!(
+            error(
+                builder
+                    .write_to(&Target::new("other", "out", "data"), file("x"))
+            ),
+            "No step other in the plan"
+        );
+    }
+
+    #[test]
+    fn read_from_and_write_to_check_the_argument_type() {
+        let (runners, channels) = (runners(), channels());
+        let mut builder = PlanBuilder::new(&runners, &channels);
+        builder.add_step(&writer()).unwrap();
+        builder.add_step(&reader()).unwrap();
+
+        assert_eq!(
+            error(
+                builder
+                    .write_to(&Target::new("reader", "in", "data"), file("x"))
+            ),
+            "Step reader: argument in is a streamReader, not a streamWriter"
+        );
+        assert_eq!(
+            error(
+                builder.read_from(
+                    &Target::new("writer", "out", "data"),
+                    file("x")
+                )
+            ),
+            "Step writer: argument out is a streamWriter, not a streamReader"
+        );
+        assert_eq!(
+            error(
+                builder.write_to(
+                    &Target::new("writer", "count", "data"),
+                    file("x")
+                )
+            ),
+            "Step writer: argument count is a plain, not a streamWriter"
+        );
+    }
+
+    #[test]
+    fn parses_targets() {
+        assert_eq!(
+            "step.arg.field".parse::<Target>(),
+            Ok(Target::new("step", "arg", "field"))
+        );
+        // Extra dots are part of the step id
+        assert_eq!(
+            "my.step.arg.field".parse::<Target>(),
+            Ok(Target::new("my.step", "arg", "field"))
+        );
+        assert_eq!(
+            Target::new("my.step", "arg", "field").to_string(),
+            "my.step.arg.field"
+        );
+
+        for missing in [
+            "",
+            "step",
+            "step.arg",
+            ".arg.field",
+            "step..field",
+            "step.arg.",
+        ] {
+            assert!(missing.parse::<Target>().is_err(), "{}", missing);
+        }
+    }
+}
@@ -0,0 +1,551 @@
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::path::{Component, Path, PathBuf};
+
+use async_std::fs;
+use async_std::stream::StreamExt;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+use crate::channel::{parse_channel, Channel};
+use crate::runner::{parse_runner_files, Runner};
+
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Kind {
+    Runner,
+    Channel,
+}
+
+impl Kind {
+    /// Name of the cache directory of this kind
+    fn dir(&self) -> &'static str {
+        match self {
+            Kind::Runner => "runners",
+            Kind::Channel => "channels",
+        }
+    }
+
+    /// Definition file looked for in a source directory
+    fn default_file(&self) -> &'static str {
+        match self {
+            Kind::Runner => "runner.json",
+            Kind::Channel => "channel.json",
+        }
+    }
+}
+
+impl Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Kind::Runner => write!(f, "runner"),
+            Kind::Channel => write!(f, "channel"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub id:      String,
+    pub version: String,
+    /// Directory with the definition, relative to the index
+    pub source:  String,
+    /// Definition file in `source`, `runner.json` or `channel.json` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file:    Option<String>,
+}
+
+impl Entry {
+    fn version(&self) -> Option<Version> {
+        Version::parse(&self.version).ok()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Index {
+    #[serde(default)]
+    pub runners:  Vec<Entry>,
+    #[serde(default)]
+    pub channels: Vec<Entry>,
+}
+
+impl Index {
+    pub fn entries(&self, kind: Kind) -> &[Entry] {
+        match kind {
+            Kind::Runner => &self.runners,
+            Kind::Channel => &self.channels,
+        }
+    }
+
+    /// The most recent version of `id` that satisfies `req`
+    pub fn find(
+        &self,
+        kind: Kind,
+        id: &str,
+        req: Option<&VersionReq>,
+    ) -> Option<&Entry> {
+        self.entries(kind)
+            .iter()
+            .filter(|e| e.id == id)
+            .filter(|e| {
+                req.map(|r| e.version().map(|v| r.matches(&v)).unwrap_or(false))
+                    .unwrap_or(true)
+            })
+            .max_by(|a, b| {
+                match (a.version(), b.version()) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (Some(_), None) => Ordering::Greater,
+                    (None, Some(_)) => Ordering::Less,
+                    (None, None) => Ordering::Equal,
+                }
+            })
+    }
+}
+
+/// A registry, a directory with an `index.json` listing the available runners
+/// and channels (or the index itself), the `source` of each entry is a
+/// directory relative to the index that is copied as a whole
+pub struct Registry {
+    /// Path of the index file
+    pub location: String,
+    pub index:    Index,
+    base:         PathBuf,
+}
+
+impl Registry {
+    /// Reads the index of the registry at `location`
+    pub async fn load(location: &str) -> Result<Self, String> {
+        let path = location.strip_prefix("file://").unwrap_or(location);
+        let mut path = PathBuf::from(path);
+        if fs::metadata(&path)
+            .await
+            .map(|m| m.is_dir())
+            .unwrap_or_default()
+        {
+            path.push(INDEX_FILE);
+        }
+
+        let content = fs::read_to_string(&path)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let index = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid index {}: {}", path.display(), e))?;
+        let path = std::fs::canonicalize(&path).unwrap_or(path);
+        let base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+        Ok(Self {
+            location: path.display().to_string(),
+            index,
+            base,
+        })
+    }
+
+    /// Copies the most recent version of `id` that satisfies `req` into the
+    /// cache, replacing an earlier installation
+    pub async fn install(
+        &self,
+        kind: Kind,
+        id: &str,
+        req: Option<&VersionReq>,
+        cache: &Path,
+    ) -> Result<Installed, String> {
+        let entry = self.index.find(kind, id, req).ok_or_else(|| {
+            format!(
+                "No {} '{}' in registry {}",
+                kind,
+                crate::version::join_requirement(id, req),
+                self.location
+            )
+        })?;
+
+        check_id(id)?;
+
+        // The source has to stay inside the registry
+        let source = self.base.join(&entry.source);
+        let source = std::fs::canonicalize(&source).map_err(|e| {
+            format!("Source {} not found: {}", source.display(), e)
+        })?;
+        let base =
+            std::fs::canonicalize(&self.base).map_err(|e| e.to_string())?;
+        if !source.starts_with(&base) {
+            return Err(format!(
+                "Source {} is outside of the registry {}",
+                entry.source,
+                base.display()
+            ));
+        }
+
+        let file = entry
+            .file
+            .clone()
+            .unwrap_or_else(|| kind.default_file().to_string());
+        let inside = Path::new(&file)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+        if !inside {
+            return Err(format!("File {} is outside of its source", file));
+        }
+        if !source.join(&file).is_file() {
+            return Err(format!(
+                "Definition {} not found",
+                source.join(&file).display()
+            ));
+        }
+
+        // Copied next to the installed version first, which is only replaced
+        // once the copy is complete
+        let target = cache.join(kind.dir()).join(id);
+        let copy = cache.join(kind.dir()).join(format!("{}.tmp", id));
+        let old = cache.join(kind.dir()).join(format!("{}.old", id));
+        for leftover in [&copy, &old] {
+            if fs::metadata(leftover).await.is_ok() {
+                fs::remove_dir_all(leftover)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        if let Err(e) = copy_dir(&source, &copy).await {
+            let _ = fs::remove_dir_all(&copy).await;
+            return Err(format!("Failed to copy {}: {}", source.display(), e));
+        }
+
+        let replaced = fs::metadata(&target).await.is_ok();
+        if replaced {
+            fs::rename(&target, &old).await.map_err(|e| e.to_string())?;
+        }
+        if let Err(e) = fs::rename(&copy, &target).await {
+            if replaced {
+                let _ = fs::rename(&old, &target).await;
+            }
+            let _ = fs::remove_dir_all(&copy).await;
+            return Err(format!("Failed to install {}: {}", id, e));
+        }
+        if replaced {
+            let _ = fs::remove_dir_all(&old).await;
+        }
+
+        let installed = Installed {
+            id: id.to_string(),
+            version: entry.version.clone(),
+            registry: self.location.clone(),
+            file,
+        };
+        let content =
+            serde_json::to_vec_pretty(&installed).map_err(|e| e.to_string())?;
+        fs::write(metadata_file(cache, kind, id), content)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(installed)
+    }
+}
+
+/// Bookkeeping of an installed definition, stored next to its directory
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Installed {
+    pub id:       String,
+    pub version:  String,
+    pub registry: String,
+    pub file:     String,
+}
+
+/// Fails on ids that can't be used as a single file name in the cache
+fn check_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || id == "." || id == ".." || id.contains(['/', '\\']) {
+        return Err(format!("Invalid id '{}'", id));
+    }
+    Ok(())
+}
+
+fn metadata_file(cache: &Path, kind: Kind, id: &str) -> PathBuf {
+    cache.join(kind.dir()).join(format!("{}.json", id))
+}
+
+/// Where installed definitions are kept, `<data dir>/nautirust/registry`
+pub fn default_cache_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|d| d.join("nautirust").join("registry"))
+}
+
+/// All installed definitions of a kind, with the path of their definition file
+pub async fn installed(cache: &Path, kind: Kind) -> Vec<(Installed, PathBuf)> {
+    let dir = cache.join(kind.dir());
+    let mut out = Vec::new();
+    let mut entries = match fs::read_dir(&dir).await {
+        Ok(entries) => entries,
+        Err(_) => return out,
+    };
+
+    while let Some(Ok(entry)) = entries.next().await {
+        let path: PathBuf = entry.path().into();
+        if path.extension().map(|e| e != "json").unwrap_or(true) {
+            continue;
+        }
+
+        let installed = fs::read_to_string(&path)
+            .await
+            .ok()
+            .and_then(|c| serde_json::from_str::<Installed>(&c).ok());
+        if let Some(installed) = installed {
+            let file = dir.join(&installed.id).join(&installed.file);
+            out.push((installed, file));
+        }
+    }
+
+    out.sort_by(|(a, _), (b, _)| a.id.cmp(&b.id));
+    out
+}
+
+/// Adds the installed channels, channels found locally take precedence
+pub async fn add_installed_channels(cache: &Path, channels: &mut Vec<Channel>) {
+    for (installed, file) in installed(cache, Kind::Channel).await {
+        if channels.iter().any(|c| c.id == installed.id) {
+            continue;
+        }
+
+        match parse_channel(file).await {
+            Ok(mut channel) => {
+                if channel.version.is_none() {
+                    channel.version = Version::parse(&installed.version).ok();
+                }
+                channels.push(channel)
+            }
+            Err(e) => {
+                eprintln!(
+                    "Parsing installed channel {} failed '{}'",
+                    installed.id, e
+                )
+            }
+        }
+    }
+}
+
+/// Adds the installed runners, runners found locally take precedence
+pub async fn add_installed_runners(
+    cache: &Path,
+    runners: &mut Vec<Runner>,
+    channels: &[Channel],
+) {
+    let installed = installed(cache, Kind::Runner)
+        .await
+        .into_iter()
+        .filter(|(installed, _)| !runners.iter().any(|r| r.id == installed.id))
+        .collect::<Vec<_>>();
+    let files = installed.iter().map(|(_, file)| file.clone());
+
+    // Definitions without a version get the one they were installed as
+    for mut runner in parse_runner_files(files, channels).await {
+        if runner.version.is_none() {
+            runner.version = installed
+                .iter()
+                .find(|(i, _)| i.id == runner.id)
+                .and_then(|(i, _)| Version::parse(&i.version).ok());
+        }
+        runners.push(runner);
+    }
+}
+
+/// Removes an installed definition, returns false when it was not installed
+pub async fn remove(
+    cache: &Path,
+    kind: Kind,
+    id: &str,
+) -> Result<bool, String> {
+    check_id(id)?;
+
+    let meta = fs::remove_file(metadata_file(cache, kind, id))
+        .await
+        .is_ok();
+    let dir = fs::remove_dir_all(cache.join(kind.dir()).join(id))
+        .await
+        .is_ok();
+    Ok(meta || dir)
+}
+
+async fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    let mut todo = vec![(from.to_path_buf(), to.to_path_buf())];
+
+    while let Some((from, to)) = todo.pop() {
+        fs::create_dir_all(&to).await?;
+        let mut entries = fs::read_dir(&from).await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let target = to.join(entry.file_name());
+            if entry.file_type().await?.is_dir() {
+                todo.push((entry.path().into(), target));
+            } else {
+                fs::copy(entry.path(), target).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    /// A registry with a runner `Echo` in two versions, and entries whose
+    /// source or file points outside of it
+    fn registry(dir: &Path) -> PathBuf {
+        let base = dir.join("registry");
+        for (source, version) in [("echo-1", "1.0.0"), ("echo-2", "2.1.0")] {
+            fs::create_dir_all(base.join(source).join("lib")).unwrap();
+            fs::write(
+                base.join(source).join("runner.json"),
+                format!(r#"{{"id": "Echo", "version": "{}"}}"#, version),
+            )
+            .unwrap();
+            fs::write(base.join(source).join("lib").join("echo.sh"), "echo")
+                .unwrap();
+        }
+        fs::create_dir_all(dir.join("outside")).unwrap();
+        fs::write(dir.join("outside").join("runner.json"), "{}").unwrap();
+
+        let index = json!({
+            "runners": [
+                { "id": "Echo", "version": "1.0.0", "source": "echo-1" },
+                { "id": "Echo", "version": "2.1.0", "source": "echo-2" },
+                { "id": "Escape", "version": "1.0.0", "source": "../outside" },
+                {
+                    "id": "Sneaky",
+                    "version": "1.0.0",
+                    "source": "echo-1",
+                    "file": "../../outside/runner.json",
+                },
+                { "id": "..", "version": "1.0.0", "source": "echo-1" },
+                { "id": "a/b", "version": "1.0.0", "source": "echo-1" },
+            ],
+        });
+        fs::write(base.join(INDEX_FILE), index.to_string()).unwrap();
+        base
+    }
+
+    #[async_std::test]
+    async fn install_list_and_remove() {
+        let dir = TempDir::new("registry").unwrap();
+        let base = registry(dir.path());
+        let cache = dir.path().join("cache");
+
+        let registry = Registry::load(base.to_str().unwrap()).await.unwrap();
+        let req = VersionReq::parse("^1").unwrap();
+        let installed = registry
+            .install(Kind::Runner, "Echo", Some(&req), &cache)
+            .await
+            .unwrap();
+        assert_eq!(installed.version, "1.0.0");
+
+        // Installing again replaces the earlier version
+        let installed = registry
+            .install(Kind::Runner, "Echo", None, &cache)
+            .await
+            .unwrap();
+        assert_eq!(installed.version, "2.1.0");
+
+        let list = super::installed(&cache, Kind::Runner).await;
+        assert_eq!(list.len(), 1);
+        let (installed, file) = &list[0];
+        assert_eq!(installed.id, "Echo");
+        assert_eq!(installed.version, "2.1.0");
+        assert_eq!(
+            file,
+            &cache.join("runners").join("Echo").join("runner.json")
+        );
+        assert!(file.is_file());
+        assert!(cache.join("runners/Echo/lib/echo.sh").is_file());
+
+        assert_eq!(remove(&cache, Kind::Runner, "Echo").await, Ok(true));
+        assert_eq!(remove(&cache, Kind::Runner, "Echo").await, Ok(false));
+        assert!(super::installed(&cache, Kind::Runner).await.is_empty());
+        assert!(!cache.join("runners").join("Echo").exists());
+    }
+
+    #[async_std::test]
+    async fn failed_install_keeps_the_installed_version() {
+        let dir = TempDir::new("registry").unwrap();
+        let base = registry(dir.path());
+        let cache = dir.path().join("cache");
+
+        let registry = Registry::load(base.to_str().unwrap()).await.unwrap();
+        let req = VersionReq::parse("^1").unwrap();
+        registry
+            .install(Kind::Runner, "Echo", Some(&req), &cache)
+            .await
+            .unwrap();
+
+        // Copying a dangling symlink fails halfway
+        std::os::unix::fs::symlink(
+            base.join("missing"),
+            base.join("echo-2").join("lib").join("missing"),
+        )
+        .unwrap();
+        assert!(registry
+            .install(Kind::Runner, "Echo", None, &cache)
+            .await
+            .unwrap_err()
+            .starts_with("Failed to copy"));
+
+        let list = super::installed(&cache, Kind::Runner).await;
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].0.version, "1.0.0");
+        let definition = fs::read_to_string(&list[0].1).unwrap();
+        assert!(definition.contains("1.0.0"), "{}", definition);
+        assert!(cache.join("runners/Echo/lib/echo.sh").is_file());
+        assert!(!cache.join("runners/Echo.tmp").exists());
+    }
+
+    #[async_std::test]
+    async fn install_fails_on_unknown_versions() {
+        let dir = TempDir::new("registry").unwrap();
+        let base = registry(dir.path());
+        let cache = dir.path().join("cache");
+
+        let registry = Registry::load(base.to_str().unwrap()).await.unwrap();
+        let req = VersionReq::parse("^3").unwrap();
+        assert!(registry
+            .install(Kind::Runner, "Echo", Some(&req), &cache)
+            .await
+            .is_err());
+        assert!(registry
+            .install(Kind::Channel, "Echo", None, &cache)
+            .await
+            .is_err());
+        assert!(!cache.exists());
+    }
+
+    #[async_std::test]
+    async fn install_stays_inside_registry_and_cache() {
+        let dir = TempDir::new("registry").unwrap();
+        let base = registry(dir.path());
+        let cache = dir.path().join("cache");
+
+        let registry = Registry::load(base.to_str().unwrap()).await.unwrap();
+        for id in ["Escape", "Sneaky", "..", "a/b"] {
+            let result = registry.install(Kind::Runner, id, None, &cache).await;
+            assert!(result.is_err(), "{} was installed", id);
+        }
+        assert!(!cache.exists());
+        assert!(dir.path().join("outside").join("runner.json").is_file());
+    }
+
+    #[async_std::test]
+    async fn remove_rejects_paths() {
+        let dir = TempDir::new("registry").unwrap();
+        let cache = dir.path().join("cache");
+        let victim = dir.path().join("victim");
+        fs::create_dir_all(&victim).unwrap();
+        fs::create_dir_all(cache.join("runners")).unwrap();
+
+        for id in ["", ".", "..", "../../victim", "a\\b"] {
+            assert!(remove(&cache, Kind::Runner, id).await.is_err(), "{}", id);
+        }
+        assert!(victim.is_dir());
+        assert!(cache.join("runners").is_dir());
+    }
+}
@@ -1,11 +1,38 @@
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
 use jsonschema::JSONSchema;
+use semver::{Version, VersionReq};
+use serde::de::Error as _;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::channel::Channel;
+use crate::limits::Limits;
+use crate::version::{check_requirement, split_requirement};
+
+/// How the steps of a runner are started on Kubernetes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RunnerKubernetes {
+    pub image:       String,
+    /// Container command, `{config}` and `{cwd}` are replaced like in the
+    /// runner script
+    pub command:     Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env:         BTreeMap<String, String>,
+    /// Container resources, as in a Kubernetes container spec
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources:   Option<Value>,
+}
 
 #[derive(Serialize, Debug)]
 pub struct Runner {
     pub id:                    String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version:               Option<Version>,
     pub start:                 Option<String>,
     pub docker:                Option<String>,
     pub stop:                  Option<String>,
@@ -13,14 +40,61 @@ pub struct Runner {
     pub script:                String,
     #[serde(rename = "canUseChannel")]
     pub can_use_channel:       Vec<String>,
+    /// Versions of the channels this runner works with, from
+    /// `canUseChannel` (`kafka@^2`)
+    #[serde(
+        rename = "channelVersions",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub channel_versions:      HashMap<String, VersionReq>,
     #[serde(rename = "requiredFields")]
     pub required_fields:       Vec<String>,
     #[serde(rename = "canUseSerialization")]
     pub can_use_serialization: Vec<String>,
+    #[serde(skip_serializing_if = "Limits::is_empty")]
+    pub limits:                Limits,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kubernetes:            Option<RunnerKubernetes>,
     #[serde(skip_serializing)]
     pub schema:                JSONSchema,
     #[serde(skip_serializing)]
     pub location:              Option<PathBuf>,
+    /// File the runner was parsed from
+    #[serde(skip_serializing)]
+    pub file:                  Option<PathBuf>,
+}
+
+/// The schema step configurations of a runner are checked against
+fn config_schema(required_fields: &[String]) -> JSONSchema {
+    let schema = json!({
+        "type": "object",
+        "required": required_fields,
+    });
+
+    JSONSchema::compile(&schema).expect("valid schema")
+}
+
+// The compiled schema can't be cloned, it is compiled again
+impl Clone for Runner {
+    fn clone(&self) -> Self {
+        Runner {
+            id:                    self.id.clone(),
+            version:               self.version.clone(),
+            start:                 self.start.clone(),
+            docker:                self.docker.clone(),
+            stop:                  self.stop.clone(),
+            script:                self.script.clone(),
+            can_use_channel:       self.can_use_channel.clone(),
+            channel_versions:      self.channel_versions.clone(),
+            required_fields:       self.required_fields.clone(),
+            can_use_serialization: self.can_use_serialization.clone(),
+            limits:                self.limits,
+            kubernetes:            self.kubernetes.clone(),
+            schema:                config_schema(&self.required_fields),
+            location:              self.location.clone(),
+            file:                  self.file.clone(),
+        }
+    }
 }
 
 impl<'de> Deserialize<'de> for Runner {
@@ -31,6 +105,7 @@ impl<'de> Deserialize<'de> for Runner {
         #[derive(Deserialize)]
         struct R {
             pub id:                    String,
+            pub version:               Option<Version>,
             pub docker:                Option<String>,
             pub start:                 Option<String>,
             pub stop:                  Option<String>,
@@ -42,9 +117,13 @@ impl<'de> Deserialize<'de> for Runner {
             pub required_fields:       Vec<String>,
             #[serde(rename = "canUseSerialization")]
             pub can_use_serialization: Vec<String>,
+            #[serde(default)]
+            pub limits:                Limits,
+            pub kubernetes:            Option<RunnerKubernetes>,
         }
 
         let R {
+            version,
             start,
             can_use_channel,
             can_use_serialization,
@@ -53,17 +132,29 @@ impl<'de> Deserialize<'de> for Runner {
             script,
             id,
             required_fields,
+            limits,
+            kubernetes,
         } = <R as Deserialize>::deserialize(deserializer)?;
 
-        let schema = json!({
-            "type": "object",
-            "required": required_fields,
-        });
+        let schema = config_schema(&required_fields);
 
-        let schema = JSONSchema::compile(&schema).expect("valid schema");
+        let mut channel_versions = HashMap::new();
+        let can_use_channel = can_use_channel
+            .iter()
+            .map(|channel| {
+                let (channel, req) =
+                    split_requirement(channel).map_err(D::Error::custom)?;
+                if let Some(req) = req {
+                    channel_versions.insert(channel.clone(), req);
+                }
+                Ok(channel)
+            })
+            .collect::<Result<_, D::Error>>()?;
 
         Ok(Runner {
             id,
+            version,
+            channel_versions,
             start,
             schema,
             required_fields,
@@ -72,11 +163,48 @@ impl<'de> Deserialize<'de> for Runner {
             can_use_serialization,
             stop,
             script,
+            limits,
+            kubernetes,
             location: None,
+            file: None,
         })
     }
 }
 
+impl Runner {
+    /// Checks that the channels this runner uses exist, in a version it
+    /// works with
+    pub fn check_channels(
+        &self,
+        channels: &[Channel],
+    ) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        for id in &self.can_use_channel {
+            let channel = match channels.iter().find(|c| &c.id == id) {
+                Some(channel) => channel,
+                None => {
+                    errors.push(format!("No such channel found! {}", id));
+                    continue;
+                }
+            };
+
+            if let Err(e) = check_requirement(
+                "channel",
+                id,
+                self.channel_versions.get(id),
+                channel.version.as_ref(),
+            ) {
+                errors.push(format!("Runner {} {}", self.id, e));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(())
+    }
+}
+
 #[cfg(feature = "io")]
 pub use io::*;
 #[cfg(feature = "io")]
@@ -87,38 +215,32 @@ mod io {
     use glob::glob;
 
     use super::*;
-    use crate::channel::Channel;
 
     pub async fn parse_runners(
         path: &str,
         channels: &[Channel],
     ) -> Vec<Runner> {
-        let mut runners = Vec::new();
-        let iterator = glob(path)
+        let paths = glob(path)
             .expect("Failed to read channels glob pattern")
-            .flatten()
-            .map(parse_runner);
+            .flatten();
+
+        parse_runner_files(paths, channels).await
+    }
 
-        let channel_exists = |id: &str| channels.iter().any(|c| c.id == id);
+    /// Parses runner definitions, skipping runners that use unknown channels
+    pub async fn parse_runner_files<I: IntoIterator<Item = PathBuf>>(
+        paths: I,
+        channels: &[Channel],
+    ) -> Vec<Runner> {
+        let mut runners = Vec::new();
+        let iterator = paths.into_iter().map(parse_runner);
 
         for item in iterator {
             match item.await {
                 Ok(runner) => {
-                    if runner.can_use_channel.iter().fold(
-                        true,
-                        |acc, channel_id| {
-                            if !channel_exists(channel_id) {
-                                eprintln!(
-                                    "No such channel found! {}",
-                                    channel_id
-                                );
-                                false
-                            } else {
-                                acc
-                            }
-                        },
-                    ) {
-                        runners.push(runner);
+                    match runner.check_channels(channels) {
+                        Ok(()) => runners.push(runner),
+                        Err(e) => e.iter().for_each(|e| eprintln!("{}", e)),
                     }
                 }
                 Err(e) => eprintln!("Parsing runner failed '{}'", e),
@@ -133,6 +255,7 @@ mod io {
         let file = read_to_string(&path).await?;
         let mut channel: Runner = serde_json::from_str(&file)?;
         channel.location = path.parent().map(|x| x.into());
+        channel.file = Some(path.canonicalize().unwrap_or(path));
         Ok(channel)
     }
 }
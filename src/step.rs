@@ -1,10 +1,15 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 use jsonschema::JSONSchema;
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 
 use crate::channel::ChannelConfig;
+use crate::limits::Limits;
+use crate::runner::Runner;
+use crate::version::{check_requirement, join_requirement, split_requirement};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StepArg {
@@ -21,15 +26,132 @@ pub struct StepArg {
     pub description: String,
 }
 
+impl StepArg {
+    /// The fields of a stream reader (`sourceIds`) or writer (`targetIds`)
+    pub fn fields(&self) -> Vec<&str> {
+        let key = match self.ty.as_str() {
+            "streamReader" => "sourceIds",
+            "streamWriter" => "targetIds",
+            _ => return Vec::new(),
+        };
+        self.other
+            .get(key)
+            .and_then(Value::as_array)
+            .map(|ids| ids.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default()
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "RawStep", into = "RawStep")]
 pub struct Step {
-    pub id:        String,
+    pub id:             String,
+    pub runner_id:      String,
+    /// Versions of the runner this step works with, from `runnerId`
+    /// (`JsRunner@^1.2`)
+    pub runner_version: Option<VersionReq>,
+    pub version:        Option<Version>,
+    pub config:         Value,
+    pub build:          Option<String>,
+    pub args:           Vec<StepArg>,
+    pub location:       Option<String>,
+    pub limits:         Limits,
+    /// Seconds `run` waits for the step to send `ready` on its control
+    /// channel before it starts the next steps
+    pub wait_ready:     Option<u64>,
+    /// File the step was parsed from, not part of the plan
+    pub file:           Option<PathBuf>,
+}
+
+/// `Step` as it is written, with the runner requirement in `runnerId`
+#[derive(Serialize, Deserialize)]
+struct RawStep {
+    id:         String,
     #[serde(rename = "runnerId")]
-    pub runner_id: String,
-    pub config:    Value,
-    pub build:     Option<String>,
-    pub args:      Vec<StepArg>,
-    pub location:  Option<String>,
+    runner_id:  String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version:    Option<Version>,
+    config:     Value,
+    build:      Option<String>,
+    args:       Vec<StepArg>,
+    location:   Option<String>,
+    #[serde(default, skip_serializing_if = "Limits::is_empty")]
+    limits:     Limits,
+    #[serde(
+        rename = "waitReady",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    wait_ready: Option<u64>,
+}
+
+impl TryFrom<RawStep> for Step {
+    type Error = String;
+
+    fn try_from(raw: RawStep) -> Result<Self, Self::Error> {
+        let (runner_id, runner_version) = split_requirement(&raw.runner_id)?;
+
+        Ok(Step {
+            id: raw.id,
+            runner_id,
+            runner_version,
+            version: raw.version,
+            config: raw.config,
+            build: raw.build,
+            args: raw.args,
+            location: raw.location,
+            limits: raw.limits,
+            wait_ready: raw.wait_ready,
+            file: None,
+        })
+    }
+}
+
+impl From<Step> for RawStep {
+    fn from(step: Step) -> Self {
+        RawStep {
+            runner_id:  join_requirement(
+                &step.runner_id,
+                step.runner_version.as_ref(),
+            ),
+            id:         step.id,
+            version:    step.version,
+            config:     step.config,
+            build:      step.build,
+            args:       step.args,
+            location:   step.location,
+            limits:     step.limits,
+            wait_ready: step.wait_ready,
+        }
+    }
+}
+
+impl Step {
+    /// Finds the runner of this step, checking its version
+    pub fn find_runner<'a>(
+        &self,
+        runners: &'a [Runner],
+    ) -> Result<&'a Runner, String> {
+        let runner = runners
+            .iter()
+            .find(|runner| runner.id == self.runner_id)
+            .ok_or_else(|| {
+                format!(
+                    "Step {}: no runner found for id {}",
+                    self.id, self.runner_id
+                )
+            })?;
+
+        check_requirement(
+            "runner",
+            &self.runner_id,
+            self.runner_version.as_ref(),
+            runner.version.as_ref(),
+        )
+        .map_err(|e| format!("Step {} {}", self.id, e))?;
+
+        Ok(runner)
+    }
 }
 
 pub fn config_is_valid(schema: &JSONSchema, config: &Value) -> bool {
@@ -53,7 +175,6 @@ mod io {
     use std::path::Path;
 
     use super::*;
-    use crate::runner::Runner;
 
     pub async fn parse_steps<'a, S, I>(
         paths: I,
@@ -71,11 +192,13 @@ mod io {
         for item in iterator {
             match item.await {
                 Ok(mut step) => {
-                    if let Some(runner) = runners
-                        .iter()
-                        .find(|runner| runner.id == step.runner_id)
-                    {
-                        if config_is_valid(&runner.schema, &step.config) {
+                    match step.find_runner(runners) {
+                        Ok(runner)
+                            if config_is_valid(
+                                &runner.schema,
+                                &step.config,
+                            ) =>
+                        {
                             let number = if let Some(n) = per_id.get(&step.id) {
                                 n + 1
                             } else {
@@ -87,8 +210,8 @@ mod io {
 
                             steps.push(step);
                         }
-                    } else {
-                        eprintln!("No runner found for id {}", step.runner_id);
+                        Ok(_) => {}
+                        Err(e) => eprintln!("{}", e),
                     }
                 }
                 Err(e) => eprintln!("Parsing step failed '{}'", e),
@@ -111,6 +234,7 @@ mod io {
         let file = read_to_string(path.as_ref()).await?;
         let mut channel: Step = serde_json::from_str(&file)?;
         channel.location = loc;
+        channel.file = Some(p.canonicalize().unwrap_or_else(|_| p.into()));
         Ok(channel)
     }
 }
@@ -125,7 +249,45 @@ pub enum Output {
 pub struct RunThing {
     #[serde(rename = "processorConfig")]
     pub processor_config: Step,
-    pub(crate) args:      HashMap<String, StepArgument>,
+    pub args:             HashMap<String, StepArgument>,
+}
+
+impl RunThing {
+    /// This step and its sub-steps, nested ones first
+    pub fn steps(&self) -> Vec<&Step> {
+        let mut out = Vec::new();
+        for arg in self.args.values() {
+            if let StepArgument::Step { sub } = arg {
+                out.extend(sub.run.steps());
+            }
+        }
+        out.push(&self.processor_config);
+        out
+    }
+
+    /// Channel configurations of the stream readers of this step
+    pub fn readers(&self) -> impl Iterator<Item = &ChannelConfig> {
+        self.args.values().flat_map(|arg| {
+            match arg {
+                StepArgument::StreamReader { fields } => Some(fields.values()),
+                _ => None,
+            }
+            .into_iter()
+            .flatten()
+        })
+    }
+
+    /// Channel configurations of the stream writers of this step
+    pub fn writers(&self) -> impl Iterator<Item = &ChannelConfig> {
+        self.args.values().flat_map(|arg| {
+            match arg {
+                StepArgument::StreamWriter { fields } => Some(fields.values()),
+                _ => None,
+            }
+            .into_iter()
+            .flatten()
+        })
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -133,6 +295,9 @@ pub struct SubStep {
     pub run:           RunThing,
     pub serialization: String,
     pub output:        Output,
+    /// Reuse the output of a previous run with the same inputs
+    #[serde(default)]
+    pub cache:         bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -153,7 +318,7 @@ pub enum StepArgument {
     },
     Step {
         #[serde(flatten)]
-        sub: SubStep,
+        sub: Box<SubStep>,
     },
     Param {
         name: String,
@@ -162,8 +327,8 @@ pub enum StepArgument {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StepArguments {
-    pub step:  Step,
-    arguments: HashMap<String, StepArgument>,
+    pub step:      Step,
+    pub arguments: HashMap<String, StepArgument>,
 }
 
 impl StepArguments {
@@ -0,0 +1,50 @@
+use semver::{Version, VersionReq};
+
+/// Splits `id@requirement` (`JsRunner@^1.2`) into the id and the requirement
+pub fn split_requirement(
+    value: &str,
+) -> Result<(String, Option<VersionReq>), String> {
+    match value.split_once('@') {
+        Some((id, req)) => {
+            let req = VersionReq::parse(req).map_err(|e| {
+                format!("Invalid version requirement '{}': {}", value, e)
+            })?;
+            Ok((id.to_string(), Some(req)))
+        }
+        None => Ok((value.to_string(), None)),
+    }
+}
+
+/// The inverse of `split_requirement`
+pub fn join_requirement(id: &str, req: Option<&VersionReq>) -> String {
+    match req {
+        Some(req) => format!("{}@{}", id, req),
+        None => id.to_string(),
+    }
+}
+
+/// Checks the version of runner or channel `id` against a requirement,
+/// definitions without a version only satisfy the absent requirement
+pub fn check_requirement(
+    kind: &str,
+    id: &str,
+    req: Option<&VersionReq>,
+    version: Option<&Version>,
+) -> Result<(), String> {
+    match (req, version) {
+        (None, _) => Ok(()),
+        (Some(req), Some(version)) if req.matches(version) => Ok(()),
+        (Some(req), Some(version)) => {
+            Err(format!(
+                "requires {} {}@{}, but version {} is found",
+                kind, id, req, version
+            ))
+        }
+        (Some(req), None) => {
+            Err(format!(
+                "requires {} {}@{}, but {} has no version",
+                kind, id, req, id
+            ))
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tempdir::TempDir;
+
+/// A step of the mock runner with a single stream reader or writer `arg` on
+/// the fifo `path`
+fn step(id: &str, config: Value, arg: &str, ty: &str, path: &str) -> Value {
+    let (declared, argument, ids) = match ty {
+        "reader" => ("streamReader", "StreamReader", "sourceIds"),
+        _ => ("streamWriter", "StreamWriter", "targetIds"),
+    };
+    json!({
+        "processorConfig": {
+            "id": id,
+            "runnerId": "nautirust-mock",
+            "config": config,
+            "args": [{ "id": arg, "type": declared, ids: ["data"] }],
+        },
+        "args": {
+            arg: {
+                "type": argument,
+                "fields": {
+                    "data": {
+                        "type": "fifo",
+                        "serialization": "text",
+                        "config": { "path": path },
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// Runs nautirust in `dir`, killing it when it takes longer than 30 seconds
+fn nautirust(dir: &Path, args: &[&str]) -> (bool, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nautirust"))
+        .args(args)
+        .current_dir(dir)
+        // Nothing installed from a registry gets in the way
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            panic!("nautirust {:?} did not finish", args);
+        }
+        sleep(Duration::from_millis(100));
+    };
+
+    let mut stdout = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut stdout)
+        .unwrap();
+    (status.success(), stdout)
+}
+
+#[test]
+fn two_mock_steps_over_a_fifo() {
+    let dir = TempDir::new("nautirust-mock").unwrap();
+    let plan = json!({
+        "values": [
+            step(
+                "writer",
+                json!({ "emit": ["hello", "world"], "transform": "upper" }),
+                "out",
+                "writer",
+                "data.fifo",
+            ),
+            step(
+                "reader",
+                json!({ "transform": "reverse", "log": true }),
+                "in",
+                "reader",
+                "data.fifo",
+            ),
+        ],
+        "params": [],
+    });
+    fs::write(dir.path().join("plan.json"), plan.to_string()).unwrap();
+
+    let (success, stdout) = nautirust(dir.path(), &["run", "plan.json"]);
+    assert!(success, "{}", stdout);
+
+    let echoed: Vec<&str> = stdout
+        .lines()
+        .filter_map(|line| line.split_once("reader: "))
+        .map(|(_, message)| message)
+        .collect();
+    // Control messages and output are not ordered with respect to each other
+    let (control, output): (Vec<&str>, Vec<&str>) = echoed
+        .into_iter()
+        .partition(|m| *m == "ready" || m.starts_with("done"));
+    assert_eq!(output, ["OLLEH", "DLROW"], "{}", stdout);
+    assert_eq!(control, ["ready", "done: 2 messages"], "{}", stdout);
+}